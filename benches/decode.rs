@@ -0,0 +1,134 @@
+//! Decoding benchmarks, run them with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mqttrs::*;
+
+/// Subscribe with 5 topics, the most that a `no_std` `Subscribe` can hold.
+#[rustfmt::skip]
+const SUBSCRIBE: &[u8] = &[
+    0b10000010, 72, 0, 10,
+    0, 11, b'h', b'o', b'm', b'e', b'/', b'1', b'/', b't', b'e', b'm', b'p', 1,
+    0, 11, b'h', b'o', b'm', b'e', b'/', b'2', b'/', b't', b'e', b'm', b'p', 1,
+    0, 11, b'h', b'o', b'm', b'e', b'/', b'3', b'/', b't', b'e', b'm', b'p', 1,
+    0, 11, b'h', b'o', b'm', b'e', b'/', b'4', b'/', b't', b'e', b'm', b'p', 1,
+    0, 11, b'h', b'o', b'm', b'e', b'/', b'+', b'/', b't', b'e', b'm', b'p', 0,
+];
+
+/// The allocating `Subscribe` against the borrowing `SubscribeRef`, reading all the topics.
+fn subscribe(c: &mut Criterion) {
+    let mut group = c.benchmark_group("subscribe");
+    group.bench_function("decode_slice", |b| {
+        b.iter(|| match decode_slice(black_box(SUBSCRIBE)) {
+            Ok(Some(Packet::Subscribe(s))) => {
+                s.topics.iter().map(|t| t.topic_path.len()).sum::<usize>()
+            }
+            other => panic!("Failed decode: {:?}", other),
+        })
+    });
+    group.bench_function("decode_subscribe_ref", |b| {
+        b.iter(
+            || match decode_subscribe_ref(black_box(SUBSCRIBE), Protocol::MQTT311) {
+                Ok(Some((_, s))) => s.topics().map(|(t, _)| t.len()).sum::<usize>(),
+                other => panic!("Failed decode: {:?}", other),
+            },
+        )
+    });
+    group.finish();
+}
+
+/// A keep-alive heavy stream: 4 pingreqs for each puback.
+fn pings() -> Vec<u8> {
+    let mut buf = Vec::new();
+    for _ in 0..20 {
+        for _ in 0..4 {
+            buf.extend_from_slice(&[0b11000000, 0]);
+        }
+        buf.extend_from_slice(&[0b01000000, 2, 0, 10]);
+    }
+    buf
+}
+
+/// Decode all the packets of `buf` with `decode`, and return how many there were.
+fn decode_all<'a>(
+    buf: &'a [u8],
+    decode: impl Fn(&'a [u8]) -> Result<Option<(usize, Option<Packet<'a>>)>, Error>,
+) -> usize {
+    let mut offset = 0;
+    let mut count = 0;
+    while let Some((len, packet)) = decode(&buf[offset..]).unwrap() {
+        black_box(packet);
+        offset += len;
+        count += 1;
+    }
+    count
+}
+
+/// The fast path for fixed-size packets against the generic one. `decode_slice_filtered()`
+/// always parses the fixed header and body generically, so it stands in for the generic path.
+fn fixed(c: &mut Criterion) {
+    let buf = pings();
+    let types = [PacketType::Pingreq, PacketType::Puback];
+    let mut group = c.benchmark_group("pingreq_heavy");
+    group.bench_function("decode_slice", |b| {
+        b.iter(|| {
+            decode_all(black_box(&buf), |buf| {
+                Ok(decode_slice_with_len(buf)?.map(|(len, p)| (len, Some(p))))
+            })
+        })
+    });
+    group.bench_function("generic", |b| {
+        b.iter(|| {
+            decode_all(black_box(&buf), |buf| {
+                decode_slice_filtered(buf, Protocol::MQTT311, &types)
+            })
+        })
+    });
+    group.finish();
+}
+
+/// A stream of QoS 1 publishes, each followed by its puback.
+fn publishes() -> Vec<u8> {
+    let mut buf = Vec::new();
+    for _ in 0..50 {
+        buf.extend_from_slice(&[0b00110010, 12, 0, 3, b'a', b'/', b'b', 0, 10]);
+        buf.extend_from_slice(b"hello");
+        buf.extend_from_slice(&[0b01000000, 2, 0, 10]);
+    }
+    buf
+}
+
+/// Decoding into a reused slot against returning each packet by value.
+fn into_slot(c: &mut Criterion) {
+    let buf = publishes();
+    let mut group = c.benchmark_group("publish_stream");
+    group.bench_function("decode_slice_with_len", |b| {
+        b.iter(|| {
+            let buf = black_box(&buf[..]);
+            let mut offset = 0;
+            while let Some((len, packet)) = decode_slice_with_len(&buf[offset..]).unwrap() {
+                black_box(&packet);
+                offset += len;
+            }
+            offset
+        })
+    });
+    group.bench_function("decode_into", |b| {
+        let mut slot = None;
+        b.iter(|| {
+            let buf = black_box(&buf[..]);
+            let mut offset = 0;
+            loop {
+                match decode_into(&buf[offset..], &mut slot).unwrap() {
+                    0 => break,
+                    len => offset += len,
+                }
+                black_box(&slot);
+            }
+            offset
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, subscribe, fixed, into_slot);
+criterion_main!(benches);
@@ -0,0 +1,145 @@
+#[cfg(feature = "defmt")]
+use defmt::Format;
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
+use crate::{decoder::*, encoder::*, properties::*, *};
+
+/// MQTT 5 authentication exchange packet ([MQTT 3.15]).
+///
+/// Used for enhanced authentication after a `Connect` with an `AuthenticationMethod` property,
+/// and for re-authentication. An empty `Auth` (`Auth::default()`) means success.
+///
+/// [MQTT 3.15]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct Auth<'a> {
+    /// `ReasonCode::Success`, `ContinueAuthentication`, or `ReAuthenticate`.
+    pub reason_code: ReasonCode,
+    /// Properties: `AuthenticationMethod`, `AuthenticationData`, `ReasonString`, and any number
+    /// of `UserProperty`.
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    pub properties: Option<Properties<'a>>,
+}
+
+/// Reason codes allowed in Auth packets.
+const AUTH_REASON_CODES: &[u8] = &[0x00, 0x18, 0x19];
+
+impl<'a> Default for Auth<'a> {
+    fn default() -> Self {
+        Auth {
+            reason_code: ReasonCode::Success,
+            properties: None,
+        }
+    }
+}
+
+impl<'a> Auth<'a> {
+    /// Create an `Auth` sent by the client to start re-authentication, with the same
+    /// authentication method as its `Connect`, and optional initial authentication data.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let auth = Auth::reauthenticate("SCRAM-SHA-1", Some(b"client-first"));
+    /// assert_eq!(auth.reason_code, ReasonCode::ReAuthenticate);
+    /// assert_eq!(auth.method(), Some("SCRAM-SHA-1"));
+    /// ```
+    pub fn reauthenticate(method: &'a str, data: Option<&'a [u8]>) -> Self {
+        Auth {
+            reason_code: ReasonCode::ReAuthenticate,
+            properties: Some(Properties::inline(
+                Property::AuthenticationMethod(method),
+                data.map(Property::AuthenticationData),
+            )),
+        }
+    }
+
+    /// Create an `Auth` sent by the client or server to continue the authentication exchange.
+    ///
+    /// The spec requires the authentication method in each step, in addition to the data.
+    pub fn continue_auth(method: &'a str, data: &'a [u8]) -> Self {
+        Auth {
+            reason_code: ReasonCode::ContinueAuthentication,
+            properties: Some(Properties::inline(
+                Property::AuthenticationMethod(method),
+                Some(Property::AuthenticationData(data)),
+            )),
+        }
+    }
+
+    /// Return the `AuthenticationMethod` property, if any.
+    pub fn method(&self) -> Option<&'a str> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::AuthenticationMethod(s) => Some(s),
+            _ => None,
+        })
+    }
+
+    /// Return the `AuthenticationData` property, if any.
+    pub fn data(&self) -> Option<&'a [u8]> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::AuthenticationData(b) => Some(b),
+            _ => None,
+        })
+    }
+
+    fn check_reason_code(&self) -> Result<(), Error> {
+        let code = self.reason_code.to_u8();
+        if AUTH_REASON_CODES.contains(&code) {
+            Ok(())
+        } else {
+            Err(Error::InvalidReasonCode(code))
+        }
+    }
+
+    pub(crate) fn from_buffer(
+        remaining_len: usize,
+        buf: &'a [u8],
+        offset: &mut usize,
+        protocol: Protocol,
+    ) -> Result<Self, Error> {
+        if protocol != Protocol::MQTT5 {
+            // Reserved packet type before MQTT 5
+            return Err(Error::InvalidHeader);
+        }
+        let end = *offset + remaining_len;
+        let mut auth = Auth::default();
+        if remaining_len > 0 {
+            auth.reason_code = ReasonCode::from_u8(read_u8(buf, offset)?)?;
+            auth.check_reason_code()?;
+            if remaining_len > 1 {
+                auth.properties = Some(Properties::from_buffer(
+                    &buf[..end],
+                    offset,
+                    &AUTH_PROPERTIES,
+                )?);
+            }
+        }
+        Ok(auth)
+    }
+
+    /// Length of the packet after the fixed header: reason code (0/1) + properties.
+    pub(crate) fn remaining_len(&self) -> usize {
+        match (self.reason_code, self.properties) {
+            (ReasonCode::Success, None) => 0,
+            (_, None) => 1,
+            (_, Some(p)) => 1 + p.len_with_prefix(),
+        }
+    }
+
+    pub(crate) fn to_buffer(self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        self.check_reason_code()?;
+        let length = self.remaining_len();
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, 0b11110000)?;
+        let write_len = write_length(buf, offset, length)? + 1;
+
+        if length > 0 {
+            write_u8(buf, offset, self.reason_code.to_u8())?;
+        }
+        if let Some(properties) = self.properties {
+            properties.to_buffer(buf, offset, &AUTH_PROPERTIES)?;
+        }
+        Ok(write_len)
+    }
+}
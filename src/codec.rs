@@ -0,0 +1,145 @@
+use crate::{decoder::*, *};
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// A [tokio_util] codec, to read and write packets with `Framed`, `FramedRead`, or `FramedWrite`.
+///
+/// A decoded `Packet` borrows from the buffer it was decoded from, so it can't be a decoder item.
+/// Instead, the decoder returns an [OwnedPacket], and [as_packet()] gives a `Packet` back.
+/// Incomplete packets are kept in the read buffer until more data arrives.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use bytes::BytesMut;
+/// use tokio_util::codec::{Decoder, Encoder};
+///
+/// let mut codec = MqttCodec::default();
+/// let mut buf = BytesMut::new();
+/// codec.encode(Packet::Pingreq, &mut buf).unwrap();
+/// let packet = codec.decode(&mut buf).unwrap().unwrap();
+/// assert_eq!(Packet::Pingreq, packet.as_packet());
+/// ```
+///
+/// [tokio_util]: https://docs.rs/tokio-util/0.7/tokio_util/codec/index.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+/// [as_packet()]: enum.OwnedPacket.html#method.as_packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MqttCodec {
+    protocol: Protocol,
+}
+
+impl MqttCodec {
+    /// Create a codec for the given protocol version. The default is `Protocol::MQTT311`.
+    pub fn new(protocol: Protocol) -> Self {
+        MqttCodec { protocol }
+    }
+}
+
+impl Default for MqttCodec {
+    fn default() -> Self {
+        MqttCodec::new(Protocol::MQTT311)
+    }
+}
+
+impl Decoder for MqttCodec {
+    type Item = OwnedPacket;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<OwnedPacket>, Error> {
+        let (len, packet) = match decode_slice_with_protocol(src, self.protocol)? {
+            Some((len, packet)) => (len, packet.to_owned_packet()?),
+            None => {
+                // Make room for the rest of the packet, if its length is known.
+                if let Some(needed) = bytes_needed(src)? {
+                    src.reserve(needed);
+                }
+                return Ok(None);
+            }
+        };
+        src.advance(len);
+        Ok(Some(packet))
+    }
+}
+
+impl<'a> Encoder<Packet<'a>> for MqttCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Packet<'a>, dst: &mut BytesMut) -> Result<(), Error> {
+        crate::encode(&item, dst).map(|_| ())
+    }
+}
+
+impl Encoder<OwnedPacket> for MqttCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: OwnedPacket, dst: &mut BytesMut) -> Result<(), Error> {
+        crate::encode(&item.as_packet(), dst).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use bytes::BytesMut;
+    use core::convert::TryFrom;
+    use futures::{executor::block_on, SinkExt, StreamExt};
+    use std::{io::Cursor, vec::Vec};
+    use tokio_util::codec::{Decoder, Framed};
+
+    #[test]
+    fn framed() {
+        // Two packets in one read, followed by a partial packet
+        let publish = Publish {
+            dup: false,
+            qospid: QosPid::from_u8u16(1, 10),
+            retain: false,
+            topic_name: "a/b",
+            payload: b"hello",
+            properties: None,
+        }
+        .into();
+        let ack = Packet::Puback(Pid::try_from(10).unwrap().into());
+        let mut data = encode_to_bytes(&publish).unwrap().to_vec();
+        data.extend_from_slice(&encode_to_bytes(&ack).unwrap());
+        data.extend_from_slice(&[0b00110000, 10, 0, 3]);
+
+        let mut framed = Framed::new(Cursor::new(data), MqttCodec::default());
+        let packet = block_on(framed.next()).unwrap().unwrap();
+        assert_eq!(publish, packet.as_packet());
+        let packet = block_on(framed.next()).unwrap().unwrap();
+        assert_eq!(ack, packet.as_packet());
+        // The partial packet stays in the read buffer, and is an error at EOF
+        assert!(block_on(framed.next()).unwrap().is_err());
+
+        let mut framed = Framed::new(Cursor::new(Vec::new()), MqttCodec::default());
+        block_on(framed.send(Packet::Pingreq)).unwrap();
+        assert_eq!(&framed.get_ref().get_ref()[..], &[0b11000000, 0]);
+        // Decoded packets can be sent back
+        block_on(framed.send(Packet::Pingresp.to_owned_packet().unwrap())).unwrap();
+        assert_eq!(&framed.get_ref().get_ref()[2..], &[0b11010000, 0]);
+    }
+
+    #[test]
+    fn partial() {
+        let mut codec = MqttCodec::default();
+        let mut buf = BytesMut::from(&[0b00110000, 7, 0, 3][..]);
+        assert_eq!(Ok(None), codec.decode(&mut buf));
+        assert_eq!(4, buf.len());
+        buf.extend_from_slice(b"a/b");
+        assert_eq!(Ok(None), codec.decode(&mut buf));
+        assert_eq!(&[0b00110000, 7, 0, 3, b'a', b'/', b'b'], &buf[..]);
+        buf.extend_from_slice(&[b'h', b'i', 0b11000000]);
+        // The publish is complete, the ping isn't
+        match codec.decode(&mut buf).unwrap().unwrap() {
+            OwnedPacket::Publish(p) => {
+                assert_eq!(p.topic_name, "a/b");
+                assert_eq!(p.payload, b"hi");
+            }
+            other => panic!("Failed decode: {:?}", other),
+        }
+        assert_eq!(&[0b11000000], &buf[..]);
+        buf.extend_from_slice(&[0]);
+        assert_eq!(Ok(Some(OwnedPacket::Pingreq)), codec.decode(&mut buf));
+        assert!(buf.is_empty());
+    }
+}
@@ -21,7 +21,13 @@ prop_compose! {
 }
 prop_compose! {
     fn stg_subtopic()(topic_path in stg_topic(), qos in stg_qos()) -> SubscribeTopic {
-        SubscribeTopic { topic_path, qos }
+        SubscribeTopic {
+            topic_path,
+            qos,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: RetainHandling::SendAtSubscribe,
+        }
     }
 }
 prop_compose! {
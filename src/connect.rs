@@ -1,11 +1,12 @@
 #[cfg(feature = "defmt")]
 use defmt::Format;
-use crate::{decoder::*, encoder::*, *};
+use crate::{decoder::*, encoder::*, properties::*, publish::check_topic_name, *};
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
 #[cfg(not(feature = "std"))]
 use heapless::String;
 #[cfg(feature = "std")]
 use std::string::String;
-use core::str::FromStr;
 
 
 /// Protocol version.
@@ -14,11 +15,11 @@ use core::str::FromStr;
 ///
 /// [`Connect`]: struct.Connect.html
 ///
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub enum Protocol {
-    /// [MQTT 3.1.1] is the most commonly implemented version. [MQTT 5] isn't yet supported my by
-    /// `mqttrs`.
+    /// [MQTT 3.1.1] is the most commonly implemented version.
     ///
     /// [MQTT 3.1.1]: https://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html
     /// [MQTT 5]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html
@@ -27,42 +28,92 @@ pub enum Protocol {
     /// 3.1.1, but you should watch out for implementation discrepancies. `Mqttrs` handles it like
     /// standard MQTT 3.1.1.
     MQIsdp,
+    /// [MQTT 5] support is a work in progress. `Connect` packets with this protocol use their
+    /// `properties`, and it can be passed to [`decode_slice_with_protocol()`] to decode the MQTT 5
+    /// form of the other packets that support it.
+    ///
+    /// [MQTT 5]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html
+    /// [`decode_slice_with_protocol()`]: fn.decode_slice_with_protocol.html
+    MQTT5,
+    /// MQTT 3.1 with the standard protocol name (`"MQTT"`, level 3), as sent by some legacy
+    /// implementations. `Mqttrs` handles it like standard MQTT 3.1.1.
+    MQTT31,
 }
 impl Protocol {
     pub(crate) fn new(name: &str, level: u8) -> Result<Protocol, Error> {
         match (name, level) {
             ("MQIsdp", 3) => Ok(Protocol::MQIsdp),
+            ("MQTT", 3) => Ok(Protocol::MQTT31),
             ("MQTT", 4) => Ok(Protocol::MQTT311),
-            _ => Err(Error::InvalidProtocol(String::from_str(name).unwrap(), 0)),
-    }
+            ("MQTT", 5) => Ok(Protocol::MQTT5),
+            _ => Err(Error::InvalidProtocol(protocol_name(name), level)),
+        }
     }
     pub(crate) fn from_buffer<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
         let protocol_name = read_str(buf, offset)?;
-        let protocol_level = buf[*offset];
-        *offset += 1;
+        let protocol_level = read_u8(buf, offset)?;
 
         Protocol::new(protocol_name, protocol_level)
     }
-    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+    /// The protocol name and level sent in a `Connect`.
+    fn name_level(self) -> (&'static str, u8) {
         match self {
-            Protocol::MQTT311 => {
-                let slice = &[0u8, 4, 'M' as u8, 'Q' as u8, 'T' as u8, 'T' as u8, 4];
-                for &byte in slice {
-                    write_u8(buf, offset, byte)?;
-                }
-                Ok(slice.len())
-            }
-            Protocol::MQIsdp => {
-                let slice = &[
-                    0u8, 4, 'M' as u8, 'Q' as u8, 'i' as u8, 's' as u8, 'd' as u8, 'p' as u8, 4,
-                ];
-                for &byte in slice {
-                    write_u8(buf, offset, byte)?;
-                }
-                Ok(slice.len())
-            }
+            Protocol::MQTT311 => ("MQTT", 4),
+            Protocol::MQIsdp => ("MQIsdp", 3),
+            Protocol::MQTT5 => ("MQTT", 5),
+            Protocol::MQTT31 => ("MQTT", 3),
         }
     }
+    /// The `Error::InvalidProtocol` for a known protocol that the server doesn't support.
+    pub(crate) fn refused(self) -> Error {
+        let (name, level) = self.name_level();
+        Error::InvalidProtocol(protocol_name(name), level)
+    }
+    /// Length of the encoded protocol name and level.
+    pub(crate) fn len(self) -> usize {
+        2 + self.name_level().0.len() + 1
+    }
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let (name, level) = self.name_level();
+        write_string(buf, offset, name)?;
+        write_u8(buf, offset, level)?;
+        Ok(self.len())
+    }
+}
+
+/// Copy a protocol name into an `Error::InvalidProtocol`.
+#[cfg(feature = "std")]
+fn protocol_name(name: &str) -> String {
+    String::from(name)
+}
+
+/// Copy a protocol name into an `Error::InvalidProtocol`, truncating it to fit.
+#[cfg(not(feature = "std"))]
+fn protocol_name(name: &str) -> String<10> {
+    let mut s = String::new();
+    for c in name.chars() {
+        if s.push(c).is_err() {
+            break;
+        }
+    }
+    s
+}
+
+/// Credentials sent by the client in the [Connect] packet, see [Connect::credentials()].
+///
+/// MQTT 5 allows a password without a username, so unlike MQTT 3.1.1 this needs a `Password`
+/// variant. Encoding and decoding reject that case for older protocols.
+///
+/// [Connect]: struct.Connect.html
+/// [Connect::credentials()]: struct.Connect.html#method.credentials
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credentials<'a> {
+    Anonymous,
+    Username(&'a str),
+    /// Only allowed by MQTT 5.
+    Password(&'a [u8]),
+    UsernamePassword(&'a str, &'a [u8]),
 }
 
 /// Message that the server should publish when the client disconnects.
@@ -71,23 +122,69 @@ impl Protocol {
 ///
 /// [Connect]: struct.Connect.html
 /// [MQTT 3.1.3.3]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718031
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct LastWill<'a> {
     pub topic: &'a str,
     pub message: &'a [u8],
     pub qos: QoS,
     pub retain: bool,
+    /// MQTT 5 will properties, only used if the `Connect` protocol is `Protocol::MQTT5`.
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    pub properties: Option<Properties<'a>>,
+}
+
+impl<'a> LastWill<'a> {
+    /// Create a `LastWill` with `QoS::AtMostOnce`, no retain flag, and no properties.
+    ///
+    /// Returns `Err(Error::InvalidTopic)` if `topic` is empty or contains wildcards, as the will is
+    /// published to a concrete topic.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let will = LastWill::new("status/client", b"offline")?.qos(QoS::AtLeastOnce).retain(true);
+    /// assert_eq!(will.qos, QoS::AtLeastOnce);
+    /// assert_eq!(Err(Error::InvalidTopic), LastWill::new("status/+", b"offline"));
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn new(topic: &'a str, message: &'a [u8]) -> Result<Self, Error> {
+        check_topic_name(topic)?;
+        Ok(LastWill {
+            topic,
+            message,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: None,
+        })
+    }
+
+    /// Set the `qos`.
+    pub fn qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Set the `retain` flag.
+    pub fn retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
 }
 
 /// Sucess value of a [Connack] packet.
 ///
 /// See [MQTT 3.2.2.3] for interpretations.
 ///
+/// An MQTT 5 `Connack` is decoded as `Accepted` or as a `Reason` code. The other variants are
+/// only decoded from MQTT 3.1.1, and are encoded as their MQTT 5 equivalent (for example
+/// `NotAuthorized` as `ReasonCode::NotAuthorized`).
+///
 /// [Connack]: struct.Connack.html
 /// [MQTT 3.2.2.3]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718035
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub enum ConnectReturnCode {
     Accepted,
     RefusedProtocolVersion,
@@ -95,17 +192,37 @@ pub enum ConnectReturnCode {
     ServerUnavailable,
     BadUsernamePassword,
     NotAuthorized,
+    /// MQTT 5 reason code. Encoding it in an MQTT 3.1.1 `Connack`, or encoding a reason code that
+    /// isn't allowed in a `Connack`, returns `Error::InvalidConnectReturnCode`.
+    Reason(ReasonCode),
 }
+/// The MQTT 5 reason codes allowed in a `Connack` ([MQTT 5 3.2.2.2]).
+///
+/// [MQTT 5 3.2.2.2]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html
+const CONNACK_REASON_CODES: &[u8] = &[
+    0x00, 0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8A, 0x8C, 0x90, 0x95, 0x97,
+    0x99, 0x9A, 0x9B, 0x9C, 0x9D, 0x9F,
+];
+
 impl ConnectReturnCode {
-    fn to_u8(&self) -> u8 {
-        match *self {
-            ConnectReturnCode::Accepted => 0,
-            ConnectReturnCode::RefusedProtocolVersion => 1,
-            ConnectReturnCode::RefusedIdentifierRejected => 2,
-            ConnectReturnCode::ServerUnavailable => 3,
-            ConnectReturnCode::BadUsernamePassword => 4,
-            ConnectReturnCode::NotAuthorized => 5,
-        }
+    fn to_u8(&self, mqtt5: bool) -> Result<u8, Error> {
+        let (code, reason) = match *self {
+            ConnectReturnCode::Accepted => (0, ReasonCode::Success),
+            ConnectReturnCode::RefusedProtocolVersion => {
+                (1, ReasonCode::UnsupportedProtocolVersion)
+            }
+            ConnectReturnCode::RefusedIdentifierRejected => {
+                (2, ReasonCode::ClientIdentifierNotValid)
+            }
+            ConnectReturnCode::ServerUnavailable => (3, ReasonCode::ServerUnavailable),
+            ConnectReturnCode::BadUsernamePassword => (4, ReasonCode::BadUserNameOrPassword),
+            ConnectReturnCode::NotAuthorized => (5, ReasonCode::NotAuthorized),
+            ConnectReturnCode::Reason(r) if mqtt5 && CONNACK_REASON_CODES.contains(&r.to_u8()) => {
+                return Ok(r.to_u8())
+            }
+            ConnectReturnCode::Reason(r) => return Err(Error::InvalidConnectReturnCode(r.to_u8())),
+        };
+        Ok(if mqtt5 { reason.to_u8() } else { code })
     }
     pub(crate) fn from_u8(byte: u8) -> Result<ConnectReturnCode, Error> {
         match byte {
@@ -118,62 +235,262 @@ impl ConnectReturnCode {
             n => Err(Error::InvalidConnectReturnCode(n)),
         }
     }
+    fn from_u8_mqtt5(byte: u8) -> Result<ConnectReturnCode, Error> {
+        match byte {
+            0 => Ok(ConnectReturnCode::Accepted),
+            n if CONNACK_REASON_CODES.contains(&n) => {
+                Ok(ConnectReturnCode::Reason(ReasonCode::from_u8(n)?))
+            }
+            n => Err(Error::InvalidConnectReturnCode(n)),
+        }
+    }
 }
 
 /// Connect packet ([MQTT 3.1]).
 ///
 /// [MQTT 3.1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Connect<'a> {
     pub protocol: Protocol,
     pub keep_alive: u16,
     pub client_id: &'a str,
     pub clean_session: bool,
+    #[cfg_attr(feature = "derive", serde(borrow))]
     pub last_will: Option<LastWill<'a>>,
+    /// `Some("")` is an empty username, which is not the same as `None`: the username flag is
+    /// set on the wire. The same goes for `password`.
+    #[cfg_attr(feature = "derive", serde(borrow))]
     pub username: Option<&'a str>,
+    #[cfg_attr(feature = "derive", serde(borrow))]
     pub password: Option<&'a [u8]>,
+    /// MQTT 5 properties, only used if `protocol` is `Protocol::MQTT5`.
+    ///
+    /// Decoding an MQTT 5 `Connect` always returns `Some`, and encoding one with `None` writes an
+    /// empty property block.
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    pub properties: Option<Properties<'a>>,
 }
 
 /// Connack packet ([MQTT 3.2]).
 ///
 /// [MQTT 3.2]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718033
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct Connack {
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct Connack<'a> {
     pub session_present: bool,
     pub code: ConnectReturnCode,
+    /// MQTT 5 properties.
+    ///
+    /// `None` for MQTT 3.1.1. `Some` (even if empty) encodes the MQTT 5 packet format.
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    pub properties: Option<Properties<'a>>,
+}
+
+/// Builder for a [Connect] packet, returned by [Connect::builder()].
+///
+/// [Connect]: struct.Connect.html
+/// [Connect::builder()]: struct.Connect.html#method.builder
+#[derive(Debug, Clone)]
+pub struct ConnectBuilder<'a> {
+    connect: Connect<'a>,
+    max_client_id_len: Option<usize>,
+}
+
+impl<'a> ConnectBuilder<'a> {
+    /// Set the keep alive interval, in seconds. 0 disables the keep alive mechanism.
+    pub fn keep_alive(mut self, keep_alive: u16) -> Self {
+        self.connect.keep_alive = keep_alive;
+        self
+    }
+
+    /// Set whether the server should discard the previous session state.
+    pub fn clean_session(mut self, clean_session: bool) -> Self {
+        self.connect.clean_session = clean_session;
+        self
+    }
+
+    /// Set the message the server publishes if the client disconnects ungracefully.
+    pub fn will(mut self, will: LastWill<'a>) -> Self {
+        self.connect.last_will = Some(will);
+        self
+    }
+
+    /// Set the username and password.
+    pub fn credentials(mut self, username: &'a str, password: &'a [u8]) -> Self {
+        self.connect.username = Some(username);
+        self.connect.password = Some(password);
+        self
+    }
+
+    /// Make [build()] fail if the client identifier is longer than `max` bytes.
+    ///
+    /// Servers only have to accept up to 23 bytes ([MQTT-3.1.3-5]), so this lets a client fail
+    /// early instead of getting a `ConnectReturnCode::RefusedIdentifierRejected` [Connack].
+    ///
+    /// [build()]: #method.build
+    /// [Connack]: struct.Connack.html
+    /// [MQTT-3.1.3-5]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028
+    pub fn max_client_id_len(mut self, max: usize) -> Self {
+        self.max_client_id_len = Some(max);
+        self
+    }
+
+    /// Build the `Connect` packet, checking the constraints set on this builder.
+    pub fn build(self) -> Result<Connect<'a>, Error> {
+        if let Some(max) = self.max_client_id_len {
+            self.connect.validate_client_id_len(max)?;
+        }
+        Ok(self.connect)
+    }
 }
 
 impl<'a> Connect<'a> {
+    /// Start building an MQTT 3.1.1 `Connect` packet with a clean session and no keep alive.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let connect = Connect::builder("client")
+    ///     .keep_alive(60)
+    ///     .credentials("user", b"pass")
+    ///     .max_client_id_len(23)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(connect.client_id, "client");
+    /// assert_eq!(connect.keep_alive, 60);
+    /// assert_eq!(
+    ///     Err(Error::InvalidClientId),
+    ///     Connect::builder("client").max_client_id_len(5).build()
+    /// );
+    /// ```
+    pub fn builder(client_id: &'a str) -> ConnectBuilder<'a> {
+        ConnectBuilder {
+            connect: Connect {
+                protocol: Protocol::MQTT311,
+                keep_alive: 0,
+                client_id,
+                clean_session: true,
+                last_will: None,
+                username: None,
+                password: None,
+                properties: None,
+            },
+            max_client_id_len: None,
+        }
+    }
+
+    /// Return `Err(Error::InvalidClientId)` if the client identifier is longer than `max` bytes.
+    ///
+    /// Servers only have to accept identifiers of up to 23 bytes, see
+    /// [ConnectBuilder::max_client_id_len()].
+    ///
+    /// [ConnectBuilder::max_client_id_len()]: struct.ConnectBuilder.html#method.max_client_id_len
+    pub fn validate_client_id_len(&self, max: usize) -> Result<(), Error> {
+        if self.client_id.len() > max {
+            Err(Error::InvalidClientId)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Return whether the client requests the server to send `ResponseInformation` in the
+    /// `Connack`.
+    ///
+    /// This is the MQTT 5 `RequestResponseInformation` property, or `false` if it is absent.
+    pub fn request_response_information(&self) -> bool {
+        self.properties
+            .and_then(|p| {
+                p.iter().find_map(|p| match p {
+                    Property::RequestResponseInformation(b) => Some(b),
+                    _ => None,
+                })
+            })
+            .unwrap_or(false)
+    }
+
+    /// Return the MQTT 5 `MaximumPacketSize` property, if any.
+    ///
+    /// The server must not send packets larger than this to the client.
+    pub fn maximum_packet_size(&self) -> Option<u32> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::MaximumPacketSize(n) => Some(n),
+            _ => None,
+        })
+    }
+
+    /// Return the username and password as a [Credentials].
+    ///
+    /// A password without a username is only allowed by MQTT 5, and returns
+    /// `Err(Error::InvalidCredentials)` with older protocols. This can only happen with a `Connect`
+    /// built by hand, as encoding and decoding reject it too.
+    ///
+    /// [Credentials]: enum.Credentials.html
+    pub fn credentials(&self) -> Result<Credentials<'a>, Error> {
+        match (self.username, self.password) {
+            (None, None) => Ok(Credentials::Anonymous),
+            (Some(u), None) => Ok(Credentials::Username(u)),
+            (Some(u), Some(p)) => Ok(Credentials::UsernamePassword(u, p)),
+            (None, Some(p)) if self.protocol == Protocol::MQTT5 => Ok(Credentials::Password(p)),
+            (None, Some(_)) => Err(Error::InvalidCredentials),
+        }
+    }
+
     pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        Ok(Self::from_buffer_lenient(buf, offset, false)?.0)
+    }
+
+    /// Decode a `Connect`, also returning the raw username bytes.
+    ///
+    /// With `lenient_username`, a non-UTF8 username doesn't fail decoding: it is only returned as
+    /// bytes, and `Connect::username` is `None`.
+    pub(crate) fn from_buffer_lenient(
+        buf: &'a [u8],
+        offset: &mut usize,
+        lenient_username: bool,
+    ) -> Result<(Self, Option<&'a [u8]>), Error> {
         let protocol = Protocol::from_buffer(buf, offset)?;
 
-        let connect_flags = buf[*offset];
-        let keep_alive = ((buf[*offset + 1] as u16) << 8) | buf[*offset + 2] as u16;
-        *offset += 3;
+        let connect_flags = read_u8(buf, offset)?;
+        let keep_alive = read_u16(buf, offset)?;
+        let properties = match protocol {
+            Protocol::MQTT5 => Some(Properties::from_buffer(buf, offset, &CONNECT_PROPERTIES)?),
+            _ => None,
+        };
 
-        let client_id = read_str(buf, offset)?;
+        let client_id = read_str_mqtt(buf, offset)?;
 
         let last_will = if connect_flags & 0b100 != 0 {
-            let will_topic = read_str(buf, offset)?;
+            let will_properties = match protocol {
+                Protocol::MQTT5 => Some(Properties::from_buffer(buf, offset, &WILL_PROPERTIES)?),
+                _ => None,
+            };
+            let will_topic = read_topic(buf, offset)?;
             let will_message = read_bytes(buf, offset)?;
-            let will_qod = QoS::from_u8((connect_flags & 0b11000) >> 3)?;
+            let will_qod = QoS::from_bits(connect_flags, 3)?;
             Some(LastWill {
                 topic: will_topic,
                 message: will_message,
                 qos: will_qod,
                 retain: (connect_flags & 0b00100000) != 0,
+                properties: will_properties,
             })
         } else {
             None
         };
 
-        let username = if connect_flags & 0b10000000 != 0 {
-            Some(read_str(buf, offset)?)
+        let username_bytes = if connect_flags & 0b10000000 != 0 {
+            Some(read_bytes(buf, offset)?)
         } else {
             None
         };
+        let username = match username_bytes.map(core::str::from_utf8) {
+            Some(Ok(s)) => Some(s),
+            Some(Err(_)) if lenient_username => None,
+            Some(Err(e)) => return Err(Error::InvalidString(e)),
+            None => None,
+        };
 
         let password = if connect_flags & 0b01000000 != 0 {
             Some(read_bytes(buf, offset)?)
@@ -181,9 +498,19 @@ impl<'a> Connect<'a> {
             None
         };
 
+        // `buf` ends with the packet, which must not contain more than the known fields.
+        if *offset != buf.len() {
+            return Err(Error::InvalidLength);
+        }
+
+        // A password requires a username before MQTT 5 (MQTT-3.1.2-22).
+        if connect_flags & 0b11000000 == 0b01000000 && protocol != Protocol::MQTT5 {
+            return Err(Error::InvalidCredentials);
+        }
+
         let clean_session = (connect_flags & 0b10) != 0;
 
-        Ok(Connect {
+        let connect = Connect {
             protocol,
             keep_alive,
             client_id,
@@ -191,37 +518,83 @@ impl<'a> Connect<'a> {
             password,
             last_will,
             clean_session,
-        })
+            properties,
+        };
+        Ok((connect, username_bytes))
     }
 
-    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
-        let header: u8 = 0b00010000;
-        let mut length: usize = 6 + 1 + 1; // NOTE: protocol_name(6) + protocol_level(1) + flags(1);
-        let mut connect_flags: u8 = 0b00000000;
-        if self.clean_session {
-            connect_flags |= 0b10;
-        };
+    /// Length of the packet after the fixed header.
+    pub(crate) fn remaining_len(&self) -> usize {
+        let mut length: usize = self.protocol.len() + 1; // NOTE: protocol name, level + flags(1)
+        if self.protocol == Protocol::MQTT5 {
+            length += self.properties.unwrap_or_default().len_with_prefix();
+            if let Some(last_will) = &self.last_will {
+                length += last_will.properties.unwrap_or_default().len_with_prefix();
+            }
+        }
         length += 2 + self.client_id.len();
         length += 2; // keep alive
         if let Some(username) = self.username {
-            connect_flags |= 0b10000000;
             length += username.len();
             length += 2;
         };
         if let Some(password) = self.password {
-            connect_flags |= 0b01000000;
             length += password.len();
             length += 2;
         };
+        if let Some(last_will) = &self.last_will {
+            length += last_will.message.len();
+            length += last_will.topic.len();
+            length += 4;
+        };
+        length
+    }
+
+    /// Check that the client identifier fits its length prefix, and that an empty one comes with
+    /// a clean session before MQTT 5 ([MQTT-3.1.3-7]).
+    ///
+    /// [MQTT-3.1.3-7]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028
+    fn check_client_id(&self) -> Result<(), Error> {
+        let persistent_anonymous =
+            self.client_id.is_empty() && !self.clean_session && self.protocol != Protocol::MQTT5;
+        if self.client_id.len() > u16::MAX as usize || persistent_anonymous {
+            return Err(Error::InvalidClientId);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let header: u8 = 0b00010000;
+        let length = self.remaining_len();
+        let mqtt5 = self.protocol == Protocol::MQTT5;
+        self.check_client_id()?;
+        self.credentials()?;
+        let properties = self.properties.unwrap_or_default();
+        let will_properties = self
+            .last_will
+            .as_ref()
+            .and_then(|w| w.properties)
+            .unwrap_or_default();
+        if !mqtt5 {
+            check_no_properties(properties)?;
+            check_no_properties(will_properties)?;
+        }
+        let mut connect_flags: u8 = 0b00000000;
+        if self.clean_session {
+            connect_flags |= 0b10;
+        };
+        if self.username.is_some() {
+            connect_flags |= 0b10000000;
+        };
+        if self.password.is_some() {
+            connect_flags |= 0b01000000;
+        };
         if let Some(last_will) = &self.last_will {
             connect_flags |= 0b00000100;
-            connect_flags |= last_will.qos.to_u8() << 3;
+            connect_flags |= last_will.qos.to_bits(3);
             if last_will.retain {
                 connect_flags |= 0b00100000;
             };
-            length += last_will.message.len();
-            length += last_will.topic.len();
-            length += 4;
         };
         check_remaining(buf, offset, length + 1)?;
 
@@ -233,10 +606,16 @@ impl<'a> Connect<'a> {
 
         write_u8(buf, offset, connect_flags)?;
         write_u16(buf, offset, self.keep_alive)?;
+        if mqtt5 {
+            properties.to_buffer(buf, offset, &CONNECT_PROPERTIES)?;
+        }
 
         write_string(buf, offset, self.client_id)?;
 
         if let Some(last_will) = &self.last_will {
+            if mqtt5 {
+                will_properties.to_buffer(buf, offset, &WILL_PROPERTIES)?;
+            }
             write_string(buf, offset, last_will.topic)?;
             write_bytes(buf, offset, &last_will.message)?;
         };
@@ -252,29 +631,138 @@ impl<'a> Connect<'a> {
     }
 }
 
-impl Connack {
-    pub(crate) fn from_buffer<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
-        let flags = buf[*offset];
-        let return_code = buf[*offset + 1];
-        *offset += 2;
+/// MQTT 3.1.1 packets can't have properties.
+fn check_no_properties(properties: Properties) -> Result<(), Error> {
+    match properties.iter().next() {
+        Some(p) => Err(Error::InvalidProperty(p.id())),
+        None => Ok(()),
+    }
+}
+
+impl<'a> Connack<'a> {
+    /// Return `true` if the server accepted the connection.
+    ///
+    /// Use this rather than comparing `code`, so that the same check works for MQTT 3.1.1 and
+    /// MQTT 5, where the server reports success as [`ReasonCode::Success`].
+    pub fn is_acceptable(&self) -> bool {
+        self.code == ConnectReturnCode::Accepted
+            || self.code == ConnectReturnCode::Reason(ReasonCode::Success)
+    }
+
+    /// Return the highest QoS supported by the server.
+    ///
+    /// This is the MQTT 5 `MaximumQoS` property, or `QoS::ExactlyOnce` if it is absent.
+    pub fn max_qos(&self) -> QoS {
+        self.properties
+            .and_then(|p| {
+                p.iter().find_map(|p| match p {
+                    Property::MaximumQoS(qos) => Some(qos),
+                    _ => None,
+                })
+            })
+            .unwrap_or(QoS::ExactlyOnce)
+    }
+
+    /// Return the MQTT 5 `ResponseInformation` property, if any.
+    ///
+    /// This is sent by the server if the client set `RequestResponseInformation`, and is
+    /// typically used as a prefix for response topics.
+    pub fn response_information(&self) -> Option<&'a str> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::ResponseInformation(s) => Some(s),
+            _ => None,
+        })
+    }
+
+    /// Return the MQTT 5 `MaximumPacketSize` property, if any.
+    ///
+    /// The client must not send packets larger than this to the server, see
+    /// [effective_max_packet_size()].
+    ///
+    /// [effective_max_packet_size()]: #method.effective_max_packet_size
+    pub fn maximum_packet_size(&self) -> Option<u32> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::MaximumPacketSize(n) => Some(n),
+            _ => None,
+        })
+    }
+
+    /// Return the size of the largest packet that the client may send to the server.
+    ///
+    /// This is the MQTT 5 `MaximumPacketSize` property, or the largest packet allowed by the
+    /// protocol (a 268435455 bytes remaining length, plus 5 bytes of fixed header) if it is
+    /// absent.
+    pub fn effective_max_packet_size(&self) -> u32 {
+        self.maximum_packet_size().unwrap_or(268435455 + 5)
+    }
+
+    /// Return the MQTT 5 `ServerReference` property, if any.
+    ///
+    /// This is another server that the client can use, typically sent with a refused connection.
+    pub fn server_reference(&self) -> Option<&'a str> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::ServerReference(s) => Some(s),
+            _ => None,
+        })
+    }
+
+    /// Return whether the server supports retained messages.
+    ///
+    /// This is the MQTT 5 `RetainAvailable` property, or `true` if it is absent.
+    pub fn retain_available(&self) -> bool {
+        self.properties
+            .and_then(|p| {
+                p.iter().find_map(|p| match p {
+                    Property::RetainAvailable(b) => Some(b),
+                    _ => None,
+                })
+            })
+            .unwrap_or(true)
+    }
+
+    pub(crate) fn from_buffer(
+        remaining_len: usize,
+        buf: &'a [u8],
+        offset: &mut usize,
+        protocol: Protocol,
+    ) -> Result<Self, Error> {
+        let end = *offset + remaining_len;
+        let flags = read_u8(buf, offset)?;
+        let return_code = read_u8(buf, offset)?;
+        let (code, properties) = match protocol {
+            Protocol::MQTT5 => (
+                ConnectReturnCode::from_u8_mqtt5(return_code)?,
+                Some(Properties::from_buffer(&buf[..end], offset, &CONNACK_PROPERTIES)?),
+            ),
+            _ => (ConnectReturnCode::from_u8(return_code)?, None),
+        };
         Ok(Connack {
             session_present: (flags & 0b1 == 1),
-            code: ConnectReturnCode::from_u8(return_code)?,
+            code,
+            properties,
         })
     }
+    /// Length of the packet after the fixed header.
+    pub(crate) fn remaining_len(&self) -> usize {
+        2 + self.properties.map_or(0, |p| p.len_with_prefix())
+    }
+
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
-        check_remaining(buf, offset, 4)?;
         let header: u8 = 0b00100000;
-        let length: u8 = 2;
+        let length = self.remaining_len();
         let mut flags: u8 = 0b00000000;
         if self.session_present {
             flags |= 0b1;
         };
-        let rc = self.code.to_u8();
+        let rc = self.code.to_u8(self.properties.is_some())?;
+        check_remaining(buf, offset, 1)?;
         write_u8(buf, offset, header)?;
-        write_u8(buf, offset, length)?;
+        let write_len = write_length(buf, offset, length)? + 1;
         write_u8(buf, offset, flags)?;
         write_u8(buf, offset, rc)?;
-        Ok(4)
+        if let Some(properties) = self.properties {
+            properties.to_buffer(buf, offset, &CONNACK_PROPERTIES)?;
+        }
+        Ok(write_len)
     }
 }
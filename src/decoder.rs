@@ -1,30 +1,122 @@
+#[cfg(feature = "defmt")]
+use defmt::Format;
 use crate::*;
+#[cfg(feature = "std")]
+use bytes::{Bytes, BytesMut};
+#[cfg(feature = "std")]
+use std::vec::Vec;
 
+/// Copy the packet at the start of `input` to `output` without decoding it, and return its length.
+///
+/// Returns `Ok(0)` if `input` doesn't contain a complete packet yet, and `Err(Error::WriteZero)`
+/// if `output` is too small. Use [clone_packet_with_status()] to tell an empty `input` from an
+/// incomplete packet. Only the fixed header is validated, which makes this a cheap way to
+/// forward packets.
+///
+/// The copy is byte-for-byte, so it is identical to the result of [decode_slice()] followed by
+/// [encode_slice()] only if the sender used the minimal encoding. For example, a remaining length
+/// of 0 encoded on two bytes (`0x80, 0x00`) is valid, but `encode_slice()` writes it on one byte.
+///
+/// ```
+/// # use mqttrs::*;
+/// let input = [0b11000000, 0, 0b11010000, 0];
+/// let mut output = [0u8; 8];
+/// assert_eq!(Ok(2), clone_packet(&input, &mut output));
+/// assert_eq!(Ok(Some(Packet::Pingreq)), decode_slice(&output[..2]));
+/// ```
+///
+/// [clone_packet_with_status()]: fn.clone_packet_with_status.html
+/// [decode_slice()]: fn.decode_slice.html
+/// [encode_slice()]: fn.encode_slice.html
 pub fn clone_packet(input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
-    if input.is_empty() {
-        return Ok(0);
+    match clone_packet_with_status(input, output)? {
+        CloneStatus::Copied(len) => Ok(len),
+        CloneStatus::Incomplete | CloneStatus::Empty => Ok(0),
     }
+}
+
+/// Result of [clone_packet_with_status()].
+///
+/// [clone_packet_with_status()]: fn.clone_packet_with_status.html
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloneStatus {
+    /// A packet of this length was copied.
+    Copied(usize),
+    /// The input starts with an incomplete packet, more bytes are needed.
+    Incomplete,
+    /// The input is empty.
+    Empty,
+}
 
+/// Copy the packet at the start of `input` to `output` like [clone_packet()], but tell whether
+/// `input` was empty or only contained the start of a packet.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut output = [0u8; 8];
+/// assert_eq!(Ok(CloneStatus::Empty), clone_packet_with_status(&[], &mut output));
+/// assert_eq!(Ok(CloneStatus::Incomplete), clone_packet_with_status(&[0b11000000], &mut output));
+/// assert_eq!(Ok(CloneStatus::Copied(2)), clone_packet_with_status(&[0b11000000, 0], &mut output));
+/// ```
+///
+/// [clone_packet()]: fn.clone_packet.html
+pub fn clone_packet_with_status(input: &[u8], output: &mut [u8]) -> Result<CloneStatus, Error> {
+    if input.is_empty() {
+        return Ok(CloneStatus::Empty);
+    }
     let mut offset = 0;
-    // while Header::new(input[offset]).is_err() {
-    //     offset += 1;
-    //     if input[offset..].is_empty() {
-    //         return Ok(0);
-    //     }
-    // }
-
-    let start = offset;
-    if let Some((_, remaining_len)) = read_header(input, &mut offset)? {
-        let end = offset + remaining_len;
-        let len = end - start;
-        output[..len].copy_from_slice(&input[start..end]);
-        Ok(len)
-    } else {
+    match read_header(input, &mut offset)? {
+        Some((_, remaining_len)) => {
+            let len = offset + remaining_len;
+            if output.len() < len {
+                return Err(Error::WriteZero);
+            }
+            output[..len].copy_from_slice(&input[..len]);
+            Ok(CloneStatus::Copied(len))
+        }
         // Don't have a full packet
-        Ok(0)
+        None => Ok(CloneStatus::Incomplete),
     }
 }
 
+/// Split all complete packets off the front of a [BytesMut] buffer.
+///
+/// Each returned [Bytes] contains exactly one packet, which can then be decoded independently
+/// (for example with [decode_slice()]). An incomplete packet at the end of the buffer stays in
+/// the buffer, waiting for more data. On error, the buffer is left untouched.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use bytes::*;
+/// // A PINGREQ, a PINGRESP, and the first byte of a DISCONNECT.
+/// let mut buf = BytesMut::from(&[0b11000000u8, 0, 0b11010000, 0, 0b11100000] as &[u8]);
+/// let frames = drain_packets(&mut buf).unwrap();
+/// assert_eq!(frames.len(), 2);
+/// assert_eq!(Ok(Some(Packet::Pingresp)), decode_slice(&frames[1]));
+/// assert_eq!(&buf[..], &[0b11100000]);
+/// ```
+///
+/// [BytesMut]: https://docs.rs/bytes/1.0.0/bytes/struct.BytesMut.html
+/// [Bytes]: https://docs.rs/bytes/1.0.0/bytes/struct.Bytes.html
+/// [decode_slice()]: fn.decode_slice.html
+#[cfg(feature = "std")]
+pub fn drain_packets(buf: &mut BytesMut) -> Result<Vec<Bytes>, Error> {
+    let mut lens = Vec::new();
+    let mut offset = 0;
+    loop {
+        let start = offset;
+        match read_header(buf, &mut offset)? {
+            Some((_, remaining_len)) => {
+                offset += remaining_len;
+                lens.push(offset - start);
+            }
+            None => break,
+        }
+    }
+    Ok(lens.into_iter().map(|len| buf.split_to(len).freeze()).collect())
+}
+
 /// Decode bytes from a [BytesMut] buffer as a [Packet] enum.
 ///
 /// The buf is never actually written to, it only takes a `BytesMut` instead of a `Bytes` to
@@ -107,9 +199,221 @@ pub fn decode_slice<'a>(buf: &'a [u8]) -> Result<Option<Packet<'a>>, Error> {
 /// [Packet]: ../enum.Packet.html
 /// [BytesMut]: https://docs.rs/bytes/1.0.0/bytes/struct.BytesMut.html
 pub fn decode_slice_with_len<'a>(buf: &'a [u8]) -> Result<Option<(usize, Packet<'a>)>, Error> {
+    decode_slice_with_protocol(buf, Protocol::MQTT311)
+}
+
+/// Decode the packet starting at `buf[*offset]`, and advance `offset` past it.
+///
+/// This is useful when MQTT packets are embedded in a larger format. `offset` is left unchanged
+/// if the packet is incomplete or can't be decoded.
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0xFF, 0b11000000, 0, 0b11010000, 0];
+/// let mut offset = 1;
+/// assert_eq!(Ok(Some(Packet::Pingreq)), decode_at(&buf, &mut offset));
+/// assert_eq!(Ok(Some(Packet::Pingresp)), decode_at(&buf, &mut offset));
+/// assert_eq!(Ok(None), decode_at(&buf, &mut offset));
+/// assert_eq!(offset, 5);
+/// ```
+pub fn decode_at<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Option<Packet<'a>>, Error> {
+    let start = (*offset).min(buf.len());
+    match decode_slice_with_len(&buf[start..])? {
+        Some((len, packet)) => {
+            *offset = start + len;
+            Ok(Some(packet))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Decode the packet at the start of `buf` into `slot`, and return the number of bytes consumed.
+///
+/// Returns `Ok(0)` if the packet is incomplete. `slot` is only written when a packet is decoded,
+/// so a decode loop can reuse the same slot.
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0b11000000, 0, 0b11010000];
+/// let mut slot = None;
+/// assert_eq!(Ok(2), decode_into(&buf, &mut slot));
+/// assert_eq!(Some(Packet::Pingreq), slot);
+/// assert_eq!(Ok(0), decode_into(&buf[2..], &mut slot));
+/// assert_eq!(Some(Packet::Pingreq), slot);
+/// ```
+pub fn decode_into<'a>(buf: &'a [u8], slot: &mut Option<Packet<'a>>) -> Result<usize, Error> {
+    match decode_slice_with_len(buf)? {
+        Some((len, packet)) => {
+            *slot = Some(packet);
+            Ok(len)
+        }
+        None => Ok(0),
+    }
+}
+
+/// Streaming decoder, accumulating bytes in a caller-provided buffer until a packet is complete.
+///
+/// This is meant for reading in small chunks, for example from a UART, without allocating. A
+/// decoded packet borrows from the buffer, so its bytes are only discarded by the next call to
+/// [push()] or [next_packet()].
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut storage = [0u8; 64];
+/// let mut decoder = Decoder::new(&mut storage, Protocol::MQTT311);
+/// decoder.push(&[0b11000000])?;
+/// assert_eq!(None, decoder.next_packet()?);
+/// decoder.push(&[0, 0b11010000, 0])?;
+/// assert_eq!(Some(Packet::Pingreq), decoder.next_packet()?);
+/// assert_eq!(Some(Packet::Pingresp), decoder.next_packet()?);
+/// assert_eq!(None, decoder.next_packet()?);
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [push()]: #method.push
+/// [next_packet()]: #method.next_packet
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    buf: &'a mut [u8],
+    protocol: Protocol,
+    /// Number of bytes in `buf`.
+    len: usize,
+    /// Number of bytes at the start of `buf` used by the last decoded packet.
+    consumed: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// Create a decoder storing partial packets in `buf`, which must be big enough for the
+    /// largest expected packet.
+    pub fn new(buf: &'a mut [u8], protocol: Protocol) -> Self {
+        Decoder {
+            buf,
+            protocol,
+            len: 0,
+            consumed: 0,
+        }
+    }
+
+    /// Append received bytes.
+    ///
+    /// Returns `Err(Error::WriteZero)` without appending anything if they don't fit in the buffer.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.compact();
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(Error::WriteZero);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Decode the next packet, or return `Ok(None)` if it isn't complete yet.
+    ///
+    /// On error, the invalid data stays in the buffer, use [clear()] to drop it.
+    ///
+    /// [clear()]: #method.clear
+    pub fn next_packet(&mut self) -> Result<Option<Packet<'_>>, Error> {
+        self.compact();
+        match decode_slice_with_protocol(&self.buf[..self.len], self.protocol)? {
+            Some((len, packet)) => {
+                self.consumed = len;
+                Ok(Some(packet))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Number of buffered bytes, excluding the last decoded packet.
+    pub fn buffered(&self) -> usize {
+        self.len - self.consumed
+    }
+
+    /// Drop all buffered bytes.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.consumed = 0;
+    }
+
+    /// Discard the bytes of the last decoded packet.
+    fn compact(&mut self) {
+        self.buf.copy_within(self.consumed..self.len, 0);
+        self.len -= self.consumed;
+        self.consumed = 0;
+    }
+}
+
+/// Decode bytes like [decode_slice_with_len()], using the packet format of the [Protocol]
+/// negotiated by the connection's `Connect` packet.
+///
+/// Packets don't say which protocol version they use (except for `Connect` itself, which is
+/// always decoded according to its own protocol field), so the caller needs to keep track of it.
+///
+/// ```
+/// # use mqttrs::*;
+/// // MQTT 5 SUBSCRIBE with a subscription identifier property.
+/// let buf = [0b10000010, 11, 0, 10, 2, 0x0B, 42, 0, 3, b'a', b'/', b'b', 1];
+/// match decode_slice_with_protocol(&buf, Protocol::MQTT5) {
+///     Ok(Some((len, Packet::Subscribe(s)))) => {
+///         assert_eq!(len, 13);
+///         assert_eq!(s.subscription_id(), Some(42));
+///     },
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+///
+/// [decode_slice_with_len()]: fn.decode_slice_with_len.html
+/// [Protocol]: enum.Protocol.html
+pub fn decode_slice_with_protocol<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+) -> Result<Option<(usize, Packet<'a>)>, Error> {
+    if let Some(r) = decode_fixed(buf)? {
+        return Ok(Some(r));
+    }
+    decode_generic(buf, protocol)
+}
+
+/// Decode the common fixed-size packets (pings, empty disconnects, and acks without reason code)
+/// directly, skipping the generic header parsing. Returns `Ok(None)` for anything else.
+///
+/// These packets look the same in all protocol versions.
+pub(crate) fn decode_fixed<'a>(buf: &'a [u8]) -> Result<Option<(usize, Packet<'a>)>, Error> {
+    match buf {
+        [0b11000000, 0, ..] => Ok(Some((2, Packet::Pingreq))),
+        [0b11010000, 0, ..] => Ok(Some((2, Packet::Pingresp))),
+        [0b11100000, 0, ..] => Ok(Some((2, Disconnect::default().into()))),
+        [hd @ 0b01000000, 2, ..]
+        | [hd @ 0b01010000, 2, ..]
+        | [hd @ 0b01100010, 2, ..]
+        | [hd @ 0b01110000, 2, ..]
+        | [hd @ 0b10110000, 2, ..]
+            if buf.len() >= 4 =>
+        {
+            let pid = Pid::from_buffer(buf, &mut 2)?;
+            let packet = match hd {
+                0b01000000 => Packet::Puback(pid.into()),
+                0b01010000 => Packet::Pubrec(pid.into()),
+                0b01100010 => Packet::Pubrel(pid.into()),
+                0b01110000 => Packet::Pubcomp(pid.into()),
+                _ => Packet::Unsuback(pid),
+            };
+            Ok(Some((4, packet)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Decode any packet, see [decode_slice_with_protocol()].
+///
+/// [decode_slice_with_protocol()]: fn.decode_slice_with_protocol.html
+pub(crate) fn decode_generic<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+) -> Result<Option<(usize, Packet<'a>)>, Error> {
     let mut offset = 0;
     if let Some((header, remaining_len)) = read_header(buf, &mut offset)? {
-        let r = read_packet(header, remaining_len, buf, &mut offset)?;
+        let r = read_packet(header, remaining_len, buf, &mut offset, protocol)?;
         Ok(Some((offset, r)))
     } else {
         // Don't have a full packet
@@ -117,61 +421,525 @@ pub fn decode_slice_with_len<'a>(buf: &'a [u8]) -> Result<Option<(usize, Packet<
     }
 }
 
+/// Limits applied to untrusted input by [decode_slice_with_limits()].
+///
+/// MQTT 5 user properties can be repeated any number of times, so a peer can make a single
+/// packet arbitrarily expensive to process. The default is unlimited.
+///
+/// `max_packet_size` is checked as soon as the fixed header is received, like
+/// [decode_slice_max()] does, and the property limits once the whole packet is received.
+///
+/// [decode_slice_with_limits()]: fn.decode_slice_with_limits.html
+/// [decode_slice_max()]: fn.decode_slice_max.html
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum number of properties in a property block.
+    pub max_properties: usize,
+    /// Maximum length of a property block in bytes, excluding its length prefix.
+    pub max_properties_len: usize,
+    /// Maximum size of a packet in bytes, including its fixed header.
+    pub max_packet_size: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_properties: usize::MAX,
+            max_properties_len: usize::MAX,
+            max_packet_size: usize::MAX,
+        }
+    }
+}
+
+impl DecodeLimits {
+    fn check(&self, properties: Option<Properties>) -> Result<(), Error> {
+        match properties {
+            Some(p) if p.len() > self.max_properties_len => Err(Error::LimitExceeded),
+            Some(p) if p.iter().nth(self.max_properties).is_some() => Err(Error::LimitExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_packet(&self, packet: &Packet) -> Result<(), Error> {
+        match packet {
+            Packet::Connect(c) => {
+                self.check(c.properties)?;
+                self.check(c.last_will.as_ref().and_then(|w| w.properties))
+            }
+            Packet::Connack(c) => self.check(c.properties),
+            Packet::Publish(p) => self.check(p.properties),
+            Packet::Puback(a) | Packet::Pubrec(a) | Packet::Pubrel(a) | Packet::Pubcomp(a) => {
+                self.check(a.properties)
+            }
+            Packet::Subscribe(s) => self.check(s.properties),
+            Packet::Disconnect(d) => self.check(d.properties),
+            Packet::Auth(a) => self.check(a.properties),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Decode bytes like [decode_slice_with_protocol()], and return `Err(Error::LimitExceeded)` if
+/// the packet exceeds the [DecodeLimits].
+///
+/// ```
+/// # use mqttrs::*;
+/// // MQTT 5 PUBACK with two user properties.
+/// let buf = [0b01000000, 16, 0, 10, 0, 12, 0x26, 0, 1, b'a', 0, 0, 0x26, 0, 1, b'b', 0, 0];
+/// let limits = DecodeLimits { max_properties: 1, ..DecodeLimits::default() };
+/// assert_eq!(Err(Error::LimitExceeded),
+///            decode_slice_with_limits(&buf, Protocol::MQTT5, &limits));
+///
+/// // Publish header announcing a 10MB packet, rejected before the body is received.
+/// let buf = [0b00110000, 0x80, 0xAD, 0xE2, 0x04];
+/// let limits = DecodeLimits { max_packet_size: 1024, ..DecodeLimits::default() };
+/// assert_eq!(Err(Error::LimitExceeded),
+///            decode_slice_with_limits(&buf, Protocol::MQTT5, &limits));
+/// ```
+///
+/// [decode_slice_with_protocol()]: fn.decode_slice_with_protocol.html
+/// [DecodeLimits]: struct.DecodeLimits.html
+pub fn decode_slice_with_limits<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+    limits: &DecodeLimits,
+) -> Result<Option<(usize, Packet<'a>)>, Error> {
+    if let Some((_, header_len, remaining_len)) = fixed_header(buf)? {
+        if header_len + remaining_len > limits.max_packet_size {
+            return Err(Error::LimitExceeded);
+        }
+    }
+    match decode_slice_with_protocol(buf, protocol)? {
+        Some((len, packet)) => {
+            limits.check_packet(&packet)?;
+            Ok(Some((len, packet)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Decode bytes like [decode_slice_with_len()], but return `Err(Error::InvalidLength)` if the
+/// packet's remaining length is more than `max_len`.
+///
+/// The length is checked as soon as the fixed header is received, so that a server doesn't wait
+/// for (and buffer) a huge packet announced by a misbehaving client.
+///
+/// ```
+/// # use mqttrs::*;
+/// // Publish header announcing a 10MB packet
+/// let buf = [0b00110000, 0x80, 0xAD, 0xE2, 0x04];
+/// assert_eq!(Ok(None), decode_slice_with_len(&buf));
+/// assert_eq!(Err(Error::InvalidLength), decode_slice_max(&buf, 1024));
+/// ```
+///
+/// [decode_slice_with_len()]: fn.decode_slice_with_len.html
+pub fn decode_slice_max<'a>(
+    buf: &'a [u8],
+    max_len: usize,
+) -> Result<Option<(usize, Packet<'a>)>, Error> {
+    match fixed_header(buf)? {
+        Some((_, _, remaining_len)) if remaining_len > max_len => Err(Error::InvalidLength),
+        _ => decode_slice_with_len(buf),
+    }
+}
+
+/// Decode bytes like [decode_slice_with_protocol()], and return `Err(Error::TrailingData(n))` if
+/// a `Disconnect` is followed by `n` more bytes.
+///
+/// A client closes the connection after sending a `Disconnect`, so anything after it comes from a
+/// misbehaving peer. For other packets, the number of trailing bytes is `buf.len()` minus the
+/// returned length.
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0b11100000, 0, 0xDE, 0xAD];
+/// assert_eq!(Err(Error::TrailingData(2)), decode_slice_strict(&buf, Protocol::MQTT311));
+/// ```
+///
+/// [decode_slice_with_protocol()]: fn.decode_slice_with_protocol.html
+pub fn decode_slice_strict<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+) -> Result<Option<(usize, Packet<'a>)>, Error> {
+    match decode_slice_with_protocol(buf, protocol)? {
+        Some((len, Packet::Disconnect(_))) if len < buf.len() => {
+            Err(Error::TrailingData(buf.len() - len))
+        }
+        r => Ok(r),
+    }
+}
+
+/// Decode bytes like [decode_slice_with_protocol()], but only parse packets whose type is in
+/// `types`.
+///
+/// Other packets are skipped without parsing their body: the returned length covers the whole
+/// packet, with `None` instead of the packet. This is cheaper when only some packet types matter,
+/// for example when extracting the `Publish` packets of a network capture.
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0b11000000, 0, 0b00110000, 5, 0, 3, b'a', b'/', b'b'];
+/// let types = [PacketType::Publish];
+/// assert_eq!(Ok(Some((2, None))), decode_slice_filtered(&buf, Protocol::MQTT311, &types));
+/// match decode_slice_filtered(&buf[2..], Protocol::MQTT311, &types) {
+///     Ok(Some((7, Some(Packet::Publish(p))))) => assert_eq!(p.topic_name, "a/b"),
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+///
+/// [decode_slice_with_protocol()]: fn.decode_slice_with_protocol.html
+#[allow(clippy::type_complexity)]
+pub fn decode_slice_filtered<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+    types: &[PacketType],
+) -> Result<Option<(usize, Option<Packet<'a>>)>, Error> {
+    let mut offset = 0;
+    match read_header(buf, &mut offset)? {
+        Some((header, remaining_len)) if types.contains(&header.typ) => {
+            let packet = read_packet(header, remaining_len, buf, &mut offset, protocol)?;
+            Ok(Some((offset, Some(packet))))
+        }
+        Some((_, remaining_len)) => Ok(Some((offset + remaining_len, None))),
+        // Don't have a full packet
+        None => Ok(None),
+    }
+}
+
+/// Decode bytes like [decode_slice_with_protocol()], but also return the length of the packet
+/// with the error, so that the caller can skip an invalid packet and decode the next one.
+///
+/// The length is `None` if the fixed header itself is invalid: the packet boundaries are unknown,
+/// and the stream can't be resynchronized.
+///
+/// ```
+/// # use mqttrs::*;
+/// // Subscribe with QoS 3, followed by a pingreq
+/// let buf = [0b10000010, 6, 0, 10, 0, 1, b'a', 3, 0b11000000, 0];
+/// assert_eq!(Err((Some(8), Error::InvalidQos(3))),
+///            decode_slice_resync(&buf, Protocol::MQTT311));
+/// assert_eq!(Ok(Some((2, Packet::Pingreq))), decode_slice_resync(&buf[8..], Protocol::MQTT311));
+/// assert_eq!(Err((None, Error::InvalidHeader)), decode_slice_resync(&[0, 0], Protocol::MQTT311));
+/// ```
+///
+/// [decode_slice_with_protocol()]: fn.decode_slice_with_protocol.html
+#[allow(clippy::type_complexity)]
+pub fn decode_slice_resync<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+) -> Result<Option<(usize, Packet<'a>)>, (Option<usize>, Error)> {
+    decode_slice_with_protocol(buf, protocol).map_err(|e| (packet_len(buf).ok().flatten(), e))
+}
+
+/// Decode a `Subscribe` packet like [decode_slice_with_protocol()], but borrow its topics from
+/// `buf` instead of copying them into a `LimitedVec`.
+///
+/// This is the cheapest way for a broker to route subscriptions. Returns
+/// `Err(Error::ProtocolViolation)` if the packet is not a `Subscribe`.
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'b', 1];
+/// match decode_subscribe_ref(&buf, Protocol::MQTT311) {
+///     Ok(Some((len, s))) => {
+///         assert_eq!(len, 10);
+///         let mut topics = s.topics();
+///         assert_eq!(topics.next(), Some(("a/b", QoS::AtLeastOnce)));
+///         assert_eq!(topics.next(), None);
+///     },
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+///
+/// [decode_slice_with_protocol()]: fn.decode_slice_with_protocol.html
+pub fn decode_subscribe_ref<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+) -> Result<Option<(usize, SubscribeRef<'a>)>, Error> {
+    let mut offset = 0;
+    match read_header(buf, &mut offset)? {
+        Some((header, remaining_len)) if header.typ == PacketType::Subscribe => {
+            let s = SubscribeRef::from_buffer(remaining_len, buf, &mut offset, protocol)?;
+            Ok(Some((offset, s)))
+        }
+        Some(_) => Err(Error::ProtocolViolation),
+        // Don't have a full packet
+        None => Ok(None),
+    }
+}
+
+/// Decode a `Connect` packet like [decode_slice_with_len()], but accept a non-UTF8 username.
+///
+/// MQTT requires the username to be a UTF-8 string, but some devices send binary usernames. This
+/// returns the raw username bytes alongside the `Connect`, whose `username` is `None` if they
+/// aren't valid UTF-8. Returns `Err(Error::ProtocolViolation)` if the packet is not a `Connect`.
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0b00010000, 17, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b10000010, 0, 10,
+///            0, 1, b'c', 0, 2, 0xC3, 0x28];
+/// assert!(decode_slice(&buf).is_err());
+/// match decode_connect_lenient(&buf) {
+///     Ok(Some((19, connect, username))) => {
+///         assert_eq!(connect.username, None);
+///         assert_eq!(username, Some(&[0xC3, 0x28][..]));
+///     },
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+///
+/// [decode_slice_with_len()]: fn.decode_slice_with_len.html
+#[allow(clippy::type_complexity)]
+pub fn decode_connect_lenient<'a>(
+    buf: &'a [u8],
+) -> Result<Option<(usize, Connect<'a>, Option<&'a [u8]>)>, Error> {
+    let mut offset = 0;
+    match read_header(buf, &mut offset)? {
+        Some((header, remaining_len)) if header.typ == PacketType::Connect => {
+            let (connect, username) =
+                Connect::from_buffer_lenient(&buf[..offset + remaining_len], &mut offset, true)?;
+            Ok(Some((offset, connect, username)))
+        }
+        Some(_) => Err(Error::ProtocolViolation),
+        // Don't have a full packet
+        None => Ok(None),
+    }
+}
+
+/// Decode the first packet of a connection, which must be a `Connect`, and return it with its
+/// length, without the `Packet` wrapper.
+///
+/// Returns `Err(Error::ProtocolViolation)` for any other packet type, so that a server can close
+/// connections that skip the handshake ([MQTT-3.1.0-1]).
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0b00010000, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00000010, 0, 10, 0, 1, b'c',
+///            0b11000000, 0];
+/// let (len, connect) = decode_connect(&buf)?.unwrap();
+/// assert_eq!((15, "c"), (len, connect.client_id));
+/// assert_eq!(Err(Error::ProtocolViolation), decode_connect(&buf[len..]));
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [MQTT-3.1.0-1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028
+pub fn decode_connect<'a>(buf: &'a [u8]) -> Result<Option<(usize, Connect<'a>)>, Error> {
+    let mut offset = 0;
+    match read_header(buf, &mut offset)? {
+        Some((header, remaining_len)) if header.typ == PacketType::Connect => {
+            let connect = Connect::from_buffer(&buf[..offset + remaining_len], &mut offset)?;
+            Ok(Some((offset, connect)))
+        }
+        Some(_) => Err(Error::ProtocolViolation),
+        // Don't have a full packet
+        None => Ok(None),
+    }
+}
+
+/// Alias of [decode_connect()].
+///
+/// This was the first name of the handshake decoder, and is kept so that existing callers keep
+/// compiling. New code should use [decode_connect()], which matches the other `decode_*`
+/// functions.
+///
+/// ```
+/// # use mqttrs::*;
+/// let pingreq = [0b11000000, 0];
+/// assert_eq!(Err(Error::ProtocolViolation), expect_connect(&pingreq));
+/// ```
+///
+/// [decode_connect()]: fn.decode_connect.html
+pub fn expect_connect<'a>(buf: &'a [u8]) -> Result<Option<(usize, Connect<'a>)>, Error> {
+    decode_connect(buf)
+}
+
+/// Decode the first packet of a connection like [decode_connect()], but only accept the
+/// protocols in `allowed`.
+///
+/// Returns `Err(Error::InvalidProtocol)` if the client uses another protocol. Its
+/// [as_connack_code()] is `ConnectReturnCode::RefusedProtocolVersion`, so that a server can
+/// enforce the versions it supports.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mqisdp = [0b00010000, 15, 0, 6, b'M', b'Q', b'I', b's', b'd', b'p', 3, 0b00000010, 0, 10,
+///               0, 1, b'c'];
+/// let err = decode_connect_with_allowed(&mqisdp, &[Protocol::MQTT311]).unwrap_err();
+/// assert_eq!(Some(ConnectReturnCode::RefusedProtocolVersion), err.as_connack_code());
+/// ```
+///
+/// [decode_connect()]: fn.decode_connect.html
+/// [as_connack_code()]: enum.Error.html#method.as_connack_code
+pub fn decode_connect_with_allowed<'a>(
+    buf: &'a [u8],
+    allowed: &[Protocol],
+) -> Result<Option<(usize, Connect<'a>)>, Error> {
+    match decode_connect(buf)? {
+        Some((_, connect)) if !allowed.contains(&connect.protocol) => {
+            Err(connect.protocol.refused())
+        }
+        r => Ok(r),
+    }
+}
+
 fn read_packet<'a>(
     header: Header,
     remaining_len: usize,
     buf: &'a [u8],
     offset: &mut usize,
+    protocol: Protocol,
 ) -> Result<Packet<'a>, Error> {
+    // Stop at the end of the packet, so that a corrupt field length can't read into the next one.
+    let buf = &buf[..payload_end(buf, *offset, remaining_len)?];
     Ok(match header.typ {
         PacketType::Pingreq => Packet::Pingreq,
         PacketType::Pingresp => Packet::Pingresp,
-        PacketType::Disconnect => Packet::Disconnect,
+        PacketType::Disconnect => {
+            Disconnect::from_buffer(remaining_len, buf, offset, protocol)?.into()
+        }
+        PacketType::Auth => Auth::from_buffer(remaining_len, buf, offset, protocol)?.into(),
         PacketType::Connect => Connect::from_buffer(buf, offset)?.into(),
-        PacketType::Connack => Connack::from_buffer(buf, offset)?.into(),
-        PacketType::Publish => Publish::from_buffer(&header, remaining_len, buf, offset)?.into(),
-        PacketType::Puback => Packet::Puback(Pid::from_buffer(buf, offset)?),
-        PacketType::Pubrec => Packet::Pubrec(Pid::from_buffer(buf, offset)?),
-        PacketType::Pubrel => Packet::Pubrel(Pid::from_buffer(buf, offset)?),
-        PacketType::Pubcomp => Packet::Pubcomp(Pid::from_buffer(buf, offset)?),
-        PacketType::Subscribe => Subscribe::from_buffer(remaining_len, buf, offset)?.into(),
+        PacketType::Connack => {
+            Connack::from_buffer(remaining_len, buf, offset, protocol)?.into()
+        }
+        PacketType::Publish => {
+            Publish::from_buffer(&header, remaining_len, buf, offset, protocol)?.into()
+        }
+        PacketType::Puback => Packet::Puback(Ack::from_buffer(
+            header.typ,
+            remaining_len,
+            buf,
+            offset,
+            protocol,
+        )?),
+        PacketType::Pubrec => Packet::Pubrec(Ack::from_buffer(
+            header.typ,
+            remaining_len,
+            buf,
+            offset,
+            protocol,
+        )?),
+        PacketType::Pubrel => Packet::Pubrel(Ack::from_buffer(
+            header.typ,
+            remaining_len,
+            buf,
+            offset,
+            protocol,
+        )?),
+        PacketType::Pubcomp => Packet::Pubcomp(Ack::from_buffer(
+            header.typ,
+            remaining_len,
+            buf,
+            offset,
+            protocol,
+        )?),
+        PacketType::Subscribe => {
+            Subscribe::from_buffer(remaining_len, buf, offset, protocol)?.into()
+        }
         PacketType::Suback => Suback::from_buffer(remaining_len, buf, offset)?.into(),
         PacketType::Unsubscribe => Unsubscribe::from_buffer(remaining_len, buf, offset)?.into(),
         PacketType::Unsuback => Packet::Unsuback(Pid::from_buffer(buf, offset)?),
     })
 }
 
-/// Read the parsed header and remaining_len from the buffer. Only return Some() and advance the
-/// buffer position if there is enough data in the buffer to read the full packet.
-pub(crate) fn read_header<'a>(
-    buf: &'a [u8],
-    offset: &mut usize,
-) -> Result<Option<(Header, usize)>, Error> {
+/// Return how many more bytes are needed to decode the packet at the start of `buf`.
+///
+/// Returns `Ok(Some(0))` if the packet is complete, and `Ok(None)` if the fixed header isn't
+/// complete yet, so that the packet length is unknown. A socket reader can use this to request
+/// exactly the right amount of data.
+///
+/// ```
+/// # use mqttrs::*;
+/// // Publish packet with a 7-bytes remaining length, of which 3 bytes were received.
+/// let buf = [0b00110000, 7, 0, 3, b'a'];
+/// assert_eq!(Ok(Some(4)), bytes_needed(&buf));
+/// assert_eq!(Ok(None), bytes_needed(&buf[..1]));
+/// ```
+pub fn bytes_needed(buf: &[u8]) -> Result<Option<usize>, Error> {
+    Ok(packet_len(buf)?.map(|total| total.saturating_sub(buf.len())))
+}
+
+/// How much of a packet has been received, see [packet_progress()].
+///
+/// [packet_progress()]: fn.packet_progress.html
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of bytes of the packet in the buffer, at most `total`.
+    pub received: usize,
+    /// Length of the whole packet, including the fixed header.
+    pub total: usize,
+}
+
+/// Return how much of the packet at the start of `buf` has been received, for example to display
+/// a progress bar for a large publish.
+///
+/// Returns `Ok(None)` if the fixed header isn't complete yet, like [bytes_needed()].
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0b00110000, 7, 0, 3, b'a'];
+/// assert_eq!(Ok(Some(Progress { received: 5, total: 9 })), packet_progress(&buf));
+/// ```
+///
+/// [bytes_needed()]: fn.bytes_needed.html
+pub fn packet_progress(buf: &[u8]) -> Result<Option<Progress>, Error> {
+    Ok(packet_len(buf)?.map(|total| Progress {
+        received: buf.len().min(total),
+        total,
+    }))
+}
+
+/// Total length of the packet at the start of `buf`, if the fixed header is complete.
+fn packet_len(buf: &[u8]) -> Result<Option<usize>, Error> {
+    Ok(fixed_header(buf)?.map(|(_, header_len, remaining_len)| header_len + remaining_len))
+}
+
+/// Parse the fixed header at the start of `buf`, if it is complete: return the header, the
+/// length of the fixed header, and the remaining length it declares.
+///
+/// This is the only parser of the remaining length, the packet doesn't need to be complete.
+pub(crate) fn fixed_header(buf: &[u8]) -> Result<Option<(Header, usize, usize)>, Error> {
+    let header = match buf.first() {
+        Some(hd) => Header::new(*hd)?,
+        None => return Ok(None),
+    };
     let mut len: usize = 0;
     for pos in 0..=3 {
-        if buf.len() > *offset + pos + 1 {
-            let byte = buf[*offset + pos + 1];
-            len += (byte as usize & 0x7F) << (pos * 7);
-            if (byte & 0x80) == 0 {
-                // Continuation bit == 0, length is parsed
-                if buf.len() < *offset + 2 + pos + len {
-                    // Won't be able to read full packet
-                    return Ok(None);
+        match buf.get(pos + 1) {
+            Some(byte) => {
+                len += (*byte as usize & 0x7F) << (pos * 7);
+                if (byte & 0x80) == 0 {
+                    // Continuation bit == 0, length is parsed
+                    return Ok(Some((header, 2 + pos, len)));
                 }
-                // Parse header byte, skip past the header, and return
-                let header = Header::new(buf[*offset])?;
-                *offset += pos + 2;
-                return Ok(Some((header, len)));
             }
-        } else {
             // Couldn't read full length
-            return Ok(None);
+            None => return Ok(None),
         }
     }
     // Continuation byte == 1 four times, that's illegal.
     Err(Error::InvalidHeader)
 }
 
+/// Read the parsed header and remaining_len from the buffer. Only return Some() and advance the
+/// buffer position if there is enough data in the buffer to read the full packet.
+pub(crate) fn read_header<'a>(
+    buf: &'a [u8],
+    offset: &mut usize,
+) -> Result<Option<(Header, usize)>, Error> {
+    let buf = buf.get(*offset..).unwrap_or_default();
+    match fixed_header(buf)? {
+        Some((header, header_len, remaining_len)) if buf.len() >= header_len + remaining_len => {
+            *offset += header_len;
+            Ok(Some((header, remaining_len)))
+        }
+        // Won't be able to read full packet
+        _ => Ok(None),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct Header {
     pub typ: PacketType,
@@ -181,39 +949,92 @@ pub(crate) struct Header {
 }
 impl Header {
     pub fn new(hd: u8) -> Result<Header, Error> {
-        let (typ, flags_ok) = match hd >> 4 {
-            1 => (PacketType::Connect, hd & 0b1111 == 0),
-            2 => (PacketType::Connack, hd & 0b1111 == 0),
-            3 => (PacketType::Publish, true),
-            4 => (PacketType::Puback, hd & 0b1111 == 0),
-            5 => (PacketType::Pubrec, hd & 0b1111 == 0),
-            6 => (PacketType::Pubrel, hd & 0b1111 == 0b0010),
-            7 => (PacketType::Pubcomp, hd & 0b1111 == 0),
-            8 => (PacketType::Subscribe, hd & 0b1111 == 0b0010),
-            9 => (PacketType::Suback, hd & 0b1111 == 0),
-            10 => (PacketType::Unsubscribe, hd & 0b1111 == 0b0010),
-            11 => (PacketType::Unsuback, hd & 0b1111 == 0),
-            12 => (PacketType::Pingreq, hd & 0b1111 == 0),
-            13 => (PacketType::Pingresp, hd & 0b1111 == 0),
-            14 => (PacketType::Disconnect, hd & 0b1111 == 0),
-            _ => (PacketType::Connect, false),
+        let typ = PacketType::from_u8(hd >> 4)?;
+        let flags = hd & 0b1111;
+        let flags_ok = match typ {
+            PacketType::Publish => true,
+            PacketType::Pubrel | PacketType::Subscribe | PacketType::Unsubscribe => flags == 0b0010,
+            _ => flags == 0,
         };
         if !flags_ok {
-            return Err(Error::InvalidHeader);
+            return Err(Error::InvalidHeaderFlags(typ, flags));
         }
         Ok(Header {
             typ,
             dup: hd & 0b1000 != 0,
-            qos: QoS::from_u8((hd & 0b110) >> 1)?,
+            qos: QoS::from_bits(hd, 1)?,
             retain: hd & 1 == 1,
         })
     }
 }
 
+/// Return the end offset of a packet body starting at `offset`, or `Error::InvalidLength` if
+/// `buf` is shorter than `remaining_len`.
+pub(crate) fn payload_end(buf: &[u8], offset: usize, remaining_len: usize) -> Result<usize, Error> {
+    let end = offset + remaining_len;
+    if end > buf.len() {
+        return Err(Error::InvalidLength);
+    }
+    Ok(end)
+}
+
+pub(crate) fn read_u8(buf: &[u8], offset: &mut usize) -> Result<u8, Error> {
+    let byte = *buf.get(*offset).ok_or(Error::InvalidLength)?;
+    *offset += 1;
+    Ok(byte)
+}
+
+pub(crate) fn read_u16(buf: &[u8], offset: &mut usize) -> Result<u16, Error> {
+    Ok(((read_u8(buf, offset)? as u16) << 8) | read_u8(buf, offset)? as u16)
+}
+
+pub(crate) fn read_u32(buf: &[u8], offset: &mut usize) -> Result<u32, Error> {
+    Ok(((read_u16(buf, offset)? as u32) << 16) | read_u16(buf, offset)? as u32)
+}
+
+/// Read an MQTT 5 [Variable Byte Integer], which must be entirely contained in `buf`.
+///
+/// [Variable Byte Integer]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901011
+pub(crate) fn read_variable_int(buf: &[u8], offset: &mut usize) -> Result<usize, Error> {
+    let mut value: usize = 0;
+    for pos in 0..=3 {
+        let byte = read_u8(buf, offset)?;
+        value += (byte as usize & 0x7F) << (pos * 7);
+        if (byte & 0x80) == 0 {
+            return Ok(value);
+        }
+    }
+    // Continuation byte == 1 four times, that's illegal.
+    Err(Error::InvalidLength)
+}
+
 pub(crate) fn read_str<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a str, Error> {
     core::str::from_utf8(read_bytes(buf, offset)?).map_err(|e| Error::InvalidString(e))
 }
 
+/// Read a string, which must not contain U+0000 ([MQTT 1.5.3]).
+///
+/// The null character is valid UTF-8, so this returns `Error::InvalidNullCharacter` rather than
+/// `Error::InvalidString`.
+///
+/// [MQTT 1.5.3]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html
+pub(crate) fn read_str_mqtt<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a str, Error> {
+    let s = read_str(buf, offset)?;
+    if s.contains('\0') {
+        return Err(Error::InvalidNullCharacter);
+    }
+    Ok(s)
+}
+
+/// Read a topic name or filter like [read_str_mqtt()], returning `Error::InvalidTopic` if it
+/// contains U+0000.
+pub(crate) fn read_topic<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a str, Error> {
+    match read_str_mqtt(buf, offset) {
+        Err(Error::InvalidNullCharacter) => Err(Error::InvalidTopic),
+        r => r,
+    }
+}
+
 pub(crate) fn read_bytes<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a [u8], Error> {
     if buf[*offset..].len() < 2 {
         return Err(Error::InvalidLength);
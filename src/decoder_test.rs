@@ -1,7 +1,7 @@
 use crate::*;
 use bytes::BytesMut;
-use subscribe::LimitedString;
-use core::str::FromStr;
+use subscribe::{LimitedString, LimitedVec};
+use core::{convert::TryFrom, str::FromStr};
 
 macro_rules! header {
     ($t:ident, $d:expr, $q:ident, $r:expr) => {
@@ -47,12 +47,17 @@ fn header_firstbyte() {
         (0b1100_0000, header!(Pingreq, false, AtMostOnce, false)),
         (0b1101_0000, header!(Pingresp, false, AtMostOnce, false)),
         (0b1110_0000, header!(Disconnect, false, AtMostOnce, false)),
+        (0b1111_0000, header!(Auth, false, AtMostOnce, false)),
     ];
     for n in 0..=255 {
         let res = match valid.iter().find(|(byte, _)| *byte == n) {
             Some((_, header)) => Ok(Some((*header, 0))),
             None if ((n & 0b110) == 0b110) && (n >> 4 == 3) => Err(Error::InvalidQos(3)),
-            None => Err(Error::InvalidHeader),
+            None if n >> 4 == 0 => Err(Error::InvalidHeader),
+            None => {
+                let typ = PacketType::from_u8(n >> 4).unwrap();
+                Err(Error::InvalidHeaderFlags(typ, n & 0b1111))
+            }
         };
         let mut buf: &[u8] = &[n, 0];
         let mut offset = 0;
@@ -98,6 +103,58 @@ fn header_len() {
     }
 }
 
+/// Same cases as `header_len` for `fixed_header()`, which doesn't need the full packet.
+#[rustfmt::skip]
+#[test]
+fn fixed_header_len() {
+    let h = header!(Connect, false, AtMostOnce, false);
+    for (res, bytes) in [
+        (Ok(Some((h, 2, 0))),       &[1 << 4, 0][..]),
+        (Ok(Some((h, 2, 127))),     &[1 << 4, 127][..]),
+        (Ok(Some((h, 2, 64))),      &[1 << 4, 0x40][..]), // Bit 6 isn't the continuation bit
+        (Ok(None),                  &[1 << 4, 0x80][..]),
+        (Ok(Some((h, 3, 0))),       &[1 << 4, 0x80, 0][..]),
+        (Ok(Some((h, 3, 128))),     &[1 << 4, 0x80, 1][..]),
+        (Ok(Some((h, 3, 10000))),   &[1 << 4, 0x80+16, 78][..]),
+        (Err(Error::InvalidHeader), &[1 << 4, 0x80, 0x80, 0x80, 0x80][..]),
+        (Ok(None),                  &[][..]),
+        (Err(Error::InvalidHeader), &[0][..]), // Invalid packet type, even without a length
+    ].iter() {
+        assert_eq!(*res, decoder::fixed_header(bytes), "{:?}", bytes);
+        // The packet length agrees
+        let needed = res
+            .clone()
+            .map(|r| r.map(|(_, header_len, len)| (header_len + len).saturating_sub(bytes.len())));
+        assert_eq!(needed, bytes_needed(bytes));
+    }
+}
+
+/// Same as `header_len`, for the 3 and 4 length bytes bands, up to the 256MB maximum.
+///
+/// Only the fixed header is needed to check the length, so this doesn't allocate the packets.
+#[rustfmt::skip]
+#[test]
+fn header_len_large() {
+    let h = header!(Connect, false, AtMostOnce, false);
+    for (len, bytes) in [
+        (16_384,      &[1 << 4, 0x80, 0x80, 0x01][..]),
+        (2_097_151,   &[1 << 4, 0xFF, 0xFF, 0x7F][..]),
+        (2_097_152,   &[1 << 4, 0x80, 0x80, 0x80, 0x01][..]),
+        (268_435_455, &[1 << 4, 0xFF, 0xFF, 0xFF, 0x7F][..]),
+    ].iter() {
+        // Complete fixed header
+        assert_eq!(Ok(Some((h, bytes.len(), *len))), decoder::fixed_header(bytes));
+        assert_eq!(Ok(Some(*len)), bytes_needed(bytes));
+        let mut offset = 0;
+        assert_eq!(Ok(None), decoder::read_header(bytes, &mut offset));
+        assert_eq!(offset, 0);
+        // Incomplete fixed header
+        let partial = &bytes[..bytes.len() - 1];
+        assert_eq!(Ok(None), decoder::fixed_header(partial));
+        assert_eq!(Ok(None), bytes_needed(partial));
+    }
+}
+
 #[test]
 fn non_utf8_string() {
     let mut data: &[u8] = &[
@@ -136,6 +193,91 @@ fn inner_length_too_long() {
     // assert_eq!(slice, []);
 }
 
+/// Same as `inner_length_too_long` for other packets, with a following packet that the corrupt
+/// length could reach into.
+#[test]
+fn inner_length_too_long_other_packets() {
+    let next = [0b11000000, 0, 0b11000000, 0, 0b11000000, 0, 0b11000000, 0, 0, 0, 0, 0];
+    let cases: [&[u8]; 4] = [
+        // Publish topic
+        &[0b00110000, 5, 0x00, 0x0a, b'a', b'/', b'b'],
+        // Subscribe topic filter
+        &[0b10000010, 8, 0x00, 0x0a, 0x00, 0x09, b'a', b'/', b'b', 0],
+        // Unsubscribe topic filter
+        &[0b10100010, 7, 0x00, 0x0a, 0x00, 0x09, b'a', b'/', b'b'],
+        // Will topic
+        &[
+            0b00010000, 20, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0b00000110, 0x00, 0x0a, //
+            0x00, 0x04, b't', b'e', b's', b't', // client_id
+            0x00, 0x09, b'/', b'a', // will topic with invalid length
+        ],
+    ];
+    for case in cases.iter() {
+        let mut data = case.to_vec();
+        data.extend_from_slice(&next);
+        assert_eq!(Err(Error::InvalidLength), decode_slice(&data), "{:?}", case);
+    }
+    let mut data = cases[1].to_vec();
+    data.extend_from_slice(&next);
+    assert_eq!(Err(Error::InvalidLength), decode_subscribe_ref(&data, Protocol::MQTT311));
+}
+
+/// The opposite of `inner_length_too_long`: the fields end before the remaining_len.
+#[test]
+fn inner_length_too_short() {
+    let data: &[u8] = &[
+        0b00010000, 18, // Connect packet, remaining_len=18
+        0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0b00000010, // clean session
+        0x00, 0x0a, // keepalive 10 sec
+        0x00, 0x04, b't', b'e', b's', b't', // client_id
+        0x00, 0x00, // trailing padding
+        0b11000000, 0, // next packet
+    ];
+    assert_eq!(Err(Error::InvalidLength), decode_slice(data));
+    assert_eq!(Err(Error::InvalidLength), expect_connect(data));
+    assert_eq!(Err(Error::InvalidLength), decode_connect_lenient(data));
+
+    // Without the padding
+    let mut data = data.to_vec();
+    data[1] = 16;
+    data.drain(18..20);
+    match decode_slice_with_len(&data) {
+        Ok(Some((18, Packet::Connect(c)))) => assert_eq!(c.client_id, "test"),
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
+/// A `remaining_len` longer than the buffer is an error, not a panic, even when bypassing the
+/// header checks.
+#[test]
+fn remaining_len_past_buffer() {
+    // Publish header claiming 50 bytes, with only 10 present
+    let data: &[u8] = &[0b00110000, 50, 0x00, 0x03, b'a', b'/', b'b', 1, 2, 3, 4, 5];
+    assert_eq!(Ok(None), decode_slice(data));
+    let header = header!(Publish, false, AtMostOnce, false);
+    let mut offset = 2;
+    assert_eq!(
+        Err(Error::InvalidLength),
+        Publish::from_buffer(&header, 50, data, &mut offset, Protocol::MQTT311)
+    );
+
+    let data: &[u8] = &[0x00, 0x0a, 0x00, 0x03, b'a', b'/', b'b', 0];
+    let mut offset = 0;
+    assert_eq!(
+        Err(Error::InvalidLength),
+        Subscribe::from_buffer(50, data, &mut offset, Protocol::MQTT311)
+    );
+    let mut offset = 0;
+    assert_eq!(
+        Err(Error::InvalidLength),
+        SubscribeRef::from_buffer(50, data, &mut offset, Protocol::MQTT311)
+    );
+    let mut offset = 0;
+    assert_eq!(Err(Error::InvalidLength), Unsubscribe::from_buffer(50, data, &mut offset));
+    let mut offset = 0;
+    assert_eq!(Err(Error::InvalidLength), Suback::from_buffer(50, data, &mut offset));
+}
+
 #[test]
 fn test_half_connect() {
     let mut data: &[u8] = &[
@@ -154,6 +296,353 @@ fn test_half_connect() {
     assert_eq!(12, data.len());
 }
 
+#[test]
+fn test_packet_type_u8() {
+    let types = [
+        (1, PacketType::Connect),
+        (2, PacketType::Connack),
+        (3, PacketType::Publish),
+        (4, PacketType::Puback),
+        (5, PacketType::Pubrec),
+        (6, PacketType::Pubrel),
+        (7, PacketType::Pubcomp),
+        (8, PacketType::Subscribe),
+        (9, PacketType::Suback),
+        (10, PacketType::Unsubscribe),
+        (11, PacketType::Unsuback),
+        (12, PacketType::Pingreq),
+        (13, PacketType::Pingresp),
+        (14, PacketType::Disconnect),
+        (15, PacketType::Auth),
+    ];
+    for (byte, typ) in types.iter() {
+        assert_eq!(typ.to_u8(), *byte);
+        assert_eq!(PacketType::from_u8(*byte), Ok(*typ));
+    }
+    for byte in [0, 16, 255].iter() {
+        assert_eq!(PacketType::from_u8(*byte), Err(Error::InvalidHeader));
+    }
+}
+
+#[test]
+fn test_expect_connect() {
+    let connect = [
+        0b00010000, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00000010, 0, 10, 0, 1, b'c',
+    ];
+    match expect_connect(&connect) {
+        Ok(Some((15, c))) => {
+            assert_eq!(c.client_id, "c");
+            assert!(Packet::Connect(c).is_connect());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(Ok(None), expect_connect(&connect[..14]));
+
+    // Publish as the first packet
+    let publish = [0b00110000, 5, 0, 1, b'a', b'h', b'i'];
+    assert!(!decode_slice(&publish).unwrap().unwrap().is_connect());
+    assert_eq!(Err(Error::ProtocolViolation), expect_connect(&publish));
+}
+
+#[test]
+fn test_streaming_decoder() {
+    let data: &[u8] = &[0b00110010, 9, 0, 3, b'a', b'/', b'b', 0, 10, b'h', b'i'];
+    let mut storage = [0u8; 16];
+    let mut decoder = Decoder::new(&mut storage, Protocol::MQTT311);
+    for (i, byte) in data.iter().enumerate() {
+        decoder.push(&[*byte]).unwrap();
+        match decoder.next_packet() {
+            Ok(None) if i < data.len() - 1 => (),
+            Ok(Some(Packet::Publish(p))) if i == data.len() - 1 => {
+                assert_eq!(p.topic_name, "a/b");
+                assert_eq!(p.payload, b"hi");
+            }
+            other => panic!("Byte {}: {:?}", i, other),
+        }
+    }
+    assert_eq!(decoder.buffered(), 0);
+    assert_eq!(Ok(None), decoder.next_packet());
+
+    // The remaining bytes are moved to the start of the buffer
+    decoder.push(data).unwrap();
+    decoder.push(&[0b11000000, 0, 0b11010000]).unwrap();
+    assert_eq!(decoder.buffered(), 14);
+    assert_eq!(Err(Error::WriteZero), decoder.push(data));
+    assert!(decoder.next_packet().unwrap().is_some());
+    assert_eq!(Ok(Some(Packet::Pingreq)), decoder.next_packet());
+    decoder.push(&[0]).unwrap();
+    decoder.push(data).unwrap();
+    assert_eq!(decoder.buffered(), 13);
+    assert_eq!(Ok(Some(Packet::Pingresp)), decoder.next_packet());
+    assert!(decoder.next_packet().unwrap().is_some());
+
+    // Invalid data stays until cleared
+    decoder.push(&[0, 0]).unwrap();
+    assert_eq!(Err(Error::InvalidHeader), decoder.next_packet());
+    assert_eq!(Err(Error::InvalidHeader), decoder.next_packet());
+    decoder.clear();
+    assert_eq!(Ok(None), decoder.next_packet());
+}
+
+#[test]
+fn test_decode_slice_max() {
+    // 10MB remaining length
+    let data: &[u8] = &[0b00110000, 0x80, 0xAD, 0xE2, 0x04, 0, 1, b'a'];
+    assert_eq!(Ok(None), decode_slice_with_len(data));
+    assert_eq!(Err(Error::InvalidLength), decode_slice_max(data, 1024));
+    assert_eq!(Ok(None), decode_slice_max(data, 10_000_000));
+    assert_eq!(Ok(None), decode_slice_max(&data[..4], 1024));
+
+    // At the limit
+    let data: &[u8] = &[0b00110000, 5, 0, 1, b'a', b'h', b'i'];
+    assert_eq!(Err(Error::InvalidLength), decode_slice_max(data, 4));
+    match decode_slice_max(data, 5) {
+        Ok(Some((7, Packet::Publish(p)))) => assert_eq!(p.payload, b"hi"),
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
+#[test]
+fn test_decode_slice_filtered() {
+    let data: &[u8] = &[
+        0b00010000, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00000010, 0, 10, 0, 1, b'c', // connect
+        0b00110000, 5, 0, 1, b'a', b'h', b'i', // publish
+        0b11000000, 0, // pingreq
+        0b00110010, 7, 0, 1, b'b', 0, 10, b'h', b'o', // publish
+        // Garbage, which isn't parsed
+        0b11000000, 3, 0xFF, 0xFF, 0xFF,
+        // Incomplete packet
+        0b00110000, 5, 0,
+    ];
+    let types = [PacketType::Publish];
+    let mut offset = 0;
+    let mut topics = vec![];
+    while let Some((len, packet)) =
+        decode_slice_filtered(&data[offset..], Protocol::MQTT311, &types).unwrap()
+    {
+        match packet {
+            Some(Packet::Publish(p)) => topics.push(p.topic_name),
+            None => (),
+            other => panic!("Unexpected packet: {:?}", other),
+        }
+        offset += len;
+    }
+    assert_eq!(topics, ["a", "b"]);
+    assert_eq!(offset, data.len() - 3);
+
+    // A filtered packet is still checked
+    let data: &[u8] = &[0b00110000, 3, 0, 1, b'+'];
+    assert_eq!(Err(Error::InvalidTopic), decode_slice_filtered(data, Protocol::MQTT311, &types));
+    assert_eq!(
+        Err(Error::InvalidHeader),
+        decode_slice_filtered(&[0, 0], Protocol::MQTT311, &types)
+    );
+
+    // MQTT 5 packets are decoded with their properties
+    let data: &[u8] = &[
+        0b00110000, 8, 0, 1, b'a', 2, 0x01, 1, b'h', b'i', // publish
+        0b11100000, 1, 0x04, // disconnect
+    ];
+    match decode_slice_filtered(data, Protocol::MQTT5, &types) {
+        Ok(Some((10, Some(Packet::Publish(p))))) => {
+            assert_eq!(p.topic_name, "a");
+            assert_eq!(p.payload, b"hi");
+            let properties = p.properties.unwrap();
+            assert_eq!(properties.iter().next(), Some(Property::PayloadFormatIndicator(true)));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(Ok(Some((3, None))), decode_slice_filtered(&data[10..], Protocol::MQTT5, &types));
+}
+
+#[test]
+fn test_decode_slice_resync() {
+    let data: &[u8] = &[
+        0b00110000, 5, 0, 1, b'a', b'h', b'i', // publish
+        0b10000010, 6, 0, 10, 0, 1, b'b', 3, // subscribe with QoS 3
+        0b00110000, 5, 0, 1, b'c', b'h', b'o', // publish
+    ];
+    let mut offset = 0;
+    let mut topics = vec![];
+    let mut errors = vec![];
+    while offset < data.len() {
+        match decode_slice_resync(&data[offset..], Protocol::MQTT311) {
+            Ok(Some((len, Packet::Publish(p)))) => {
+                topics.push(p.topic_name);
+                offset += len;
+            }
+            Err((Some(len), e)) => {
+                errors.push(e);
+                offset += len;
+            }
+            other => panic!("Failed decode: {:?}", other),
+        }
+    }
+    assert_eq!(topics, ["a", "c"]);
+    assert_eq!(errors, [Error::InvalidQos(3)]);
+
+    // Incomplete packets aren't errors
+    assert_eq!(Ok(None), decode_slice_resync(&data[7..12], Protocol::MQTT311));
+
+    // The length is unknown if the fixed header is invalid
+    let data: &[u8] = &[0b00110110, 5, 0, 1, b'a', b'h', b'i'];
+    assert_eq!(Err((None, Error::InvalidQos(3))), decode_slice_resync(data, Protocol::MQTT311));
+    let data: &[u8] = &[0b00110000, 0xFF, 0xFF, 0xFF, 0xFF];
+    assert_eq!(Err((None, Error::InvalidHeader)), decode_slice_resync(data, Protocol::MQTT311));
+}
+
+#[test]
+fn test_decode_connect() {
+    let data = [
+        0b00010000, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00000010, 0, 10, 0, 1, b'c', // connect
+        0b11000000, 0, // pingreq
+    ];
+    match decode_connect(&data) {
+        Ok(Some((15, c))) => {
+            assert_eq!(c.protocol, Protocol::MQTT311);
+            assert_eq!(c.client_id, "c");
+            assert_eq!(c.keep_alive, 10);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(Ok(None), decode_connect(&data[..14]));
+    assert_eq!(Err(Error::ProtocolViolation), decode_connect(&data[15..]));
+}
+
+#[test]
+fn test_decode_connect_with_allowed() {
+    let mqtt311 = [
+        0b00010000, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00000010, 0, 10, 0, 1, b'c',
+    ];
+    let mqisdp = [
+        0b00010000, 15, 0, 6, b'M', b'Q', b'I', b's', b'd', b'p', 3, 0b00000010, 0, 10, 0, 1, b'c',
+    ];
+    let allowed = [Protocol::MQTT311];
+    match decode_connect_with_allowed(&mqtt311, &allowed) {
+        Ok(Some((15, c))) => assert_eq!(c.protocol, Protocol::MQTT311),
+        other => panic!("Failed decode: {:?}", other),
+    }
+    let err = decode_connect_with_allowed(&mqisdp, &allowed).unwrap_err();
+    assert_eq!(err.as_connack_code(), Some(ConnectReturnCode::RefusedProtocolVersion));
+    match err {
+        Error::InvalidProtocol(n, l) => assert_eq!((n.as_str(), l), ("MQIsdp", 3)),
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // And the other way around
+    let allowed = [Protocol::MQIsdp];
+    match decode_connect_with_allowed(&mqisdp, &allowed) {
+        Ok(Some((17, c))) => assert_eq!(c.protocol, Protocol::MQIsdp),
+        other => panic!("Failed decode: {:?}", other),
+    }
+    match decode_connect_with_allowed(&mqtt311, &allowed) {
+        Err(Error::InvalidProtocol(n, l)) => assert_eq!((n.as_str(), l), ("MQTT", 4)),
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(Ok(None), decode_connect_with_allowed(&mqisdp[..16], &allowed));
+}
+
+#[test]
+fn test_connect_truncated_keep_alive() {
+    // The remaining length claims the whole packet was received, but it stops after the protocol
+    // header, the connect flags, or half the keep alive.
+    for len in 7..=9 {
+        let mut data = vec![
+            0b00010000, len, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0b00000010, 0x00,
+        ];
+        data.truncate(2 + len as usize);
+        assert_eq!(Err(Error::InvalidLength), decode_slice(&data), "len {}", len);
+        // Don't read the keep alive from the next packet
+        data.extend_from_slice(&[0b11000000, 0]);
+        assert_eq!(Err(Error::InvalidLength), decode_slice(&data), "len {} + pingreq", len);
+    }
+}
+
+#[test]
+fn test_bytes_needed() {
+    // Header incomplete: length unknown
+    assert_eq!(Ok(None), bytes_needed(&[]));
+    assert_eq!(Ok(None), bytes_needed(&[0b00110000]));
+    assert_eq!(Ok(None), bytes_needed(&[0b00110000, 0x80]));
+    assert_eq!(Ok(None), bytes_needed(&[0b00110000, 0x80, 0x80, 0x80]));
+
+    // Body incomplete
+    assert_eq!(Ok(Some(39)), bytes_needed(&[0b00010000, 39]));
+    let half_connect = [
+        0b00010000, 39, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0b11001110, 0x00, 0x0a, 0x00,
+        0x04,
+    ];
+    assert_eq!(Ok(Some(27)), bytes_needed(&half_connect));
+    assert_eq!(Ok(Some(128)), bytes_needed(&[0b00110000, 0x80, 0x01]));
+    assert_eq!(Ok(Some(16383)), bytes_needed(&[0b00110000, 0xFF, 0x7F]));
+    assert_eq!(Ok(Some(268435455)), bytes_needed(&[0b00110000, 0xFF, 0xFF, 0xFF, 0x7F]));
+
+    // Complete, with or without trailing data
+    assert_eq!(Ok(Some(0)), bytes_needed(&[0b11000000, 0]));
+    assert_eq!(Ok(Some(0)), bytes_needed(&[0b11000000, 0, 0b11000000]));
+    assert_eq!(Ok(Some(0)), bytes_needed(&[0b10110000, 2, 0, 10]));
+
+    // Malformed header
+    assert_eq!(Err(Error::InvalidHeader), bytes_needed(&[0b00000000]));
+    assert_eq!(
+        Err(Error::InvalidHeaderFlags(PacketType::Pingreq, 1)),
+        bytes_needed(&[0b11000001, 0])
+    );
+    assert_eq!(Err(Error::InvalidHeader), bytes_needed(&[0b00110000, 0xFF, 0xFF, 0xFF, 0xFF]));
+}
+
+#[test]
+fn test_packet_progress() {
+    // 1000 bytes publish: 3 bytes header, 990 bytes payload
+    let payload = [b'x'; 990];
+    let packet = Publish {
+        dup: false,
+        qospid: QosPid::from_u8u16(1, 10),
+        retain: false,
+        topic_name: "a/b",
+        payload: &payload,
+        properties: None,
+    }
+    .into();
+    let mut buf = [0u8; 1010];
+    assert_eq!(Ok(1000), encode_slice(&packet, &mut buf));
+
+    assert_eq!(Ok(None), packet_progress(&buf[..2]));
+    let progress = |received| Ok(Some(Progress { received, total: 1000 }));
+    assert_eq!(progress(3), packet_progress(&buf[..3]));
+    assert_eq!(progress(500), packet_progress(&buf[..500]));
+    assert_eq!(progress(1000), packet_progress(&buf[..1000]));
+    // Trailing data belongs to the next packet
+    assert_eq!(progress(1000), packet_progress(&buf[..]));
+    assert_eq!(Err(Error::InvalidHeader), packet_progress(&[0b00000000]));
+}
+
+/// All the places where QoS is decoded reject the reserved value 3 the same way.
+#[test]
+fn test_invalid_qos() {
+    // Publish header
+    let data: &[u8] = &[0b00110110, 5, 0, 1, b'a', 0, 10];
+    assert_eq!(Err(Error::InvalidQos(3)), decode_slice(data));
+
+    // Connect will flags
+    let data: &[u8] = &[
+        0b00010000, 18, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00011100, 0, 10, // header
+        0, 1, b'c', // client id
+        0, 1, b'w', 0, 0, // will topic and message
+    ];
+    assert_eq!(Err(Error::InvalidQos(3)), decode_slice(data));
+
+    // Subscribe topic, in both protocols
+    let data: &[u8] = &[0b10000010, 6, 0, 10, 0, 1, b'a', 3];
+    assert_eq!(Err(Error::InvalidQos(3)), decode_slice(data));
+    let data: &[u8] = &[0b10000010, 7, 0, 10, 0, 0, 1, b'a', 3];
+    assert_eq!(Err(Error::InvalidQos(3)), decode_slice_with_protocol(data, Protocol::MQTT5));
+
+    // Suback return code
+    let data: &[u8] = &[0b10010000, 3, 0, 10, 3];
+    assert_eq!(Err(Error::InvalidQos(3)), decode_slice(data));
+}
+
 #[test]
 fn test_connect_wrong_version() {
     let mut data: &[u8] = &[
@@ -173,6 +662,50 @@ fn test_connect_wrong_version() {
     );
 }
 
+#[test]
+fn test_connect_protocol_name_level() {
+    fn connect(name: &str, level: u8) -> Vec<u8> {
+        let mut data = vec![0b00010000, 0, 0x00, name.len() as u8];
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(&[level, 0b00000010, 0x00, 0x0a]);
+        if level == 5 {
+            data.push(0); // properties
+        }
+        data.extend_from_slice(&[0x00, 0x01, b'a']);
+        data[1] = (data.len() - 2) as u8;
+        data
+    }
+
+    // Known name and matching level
+    let known = [
+        ("MQTT", 4, Protocol::MQTT311),
+        ("MQIsdp", 3, Protocol::MQIsdp),
+        ("MQTT", 5, Protocol::MQTT5),
+        ("MQTT", 3, Protocol::MQTT31),
+    ];
+    for (name, level, protocol) in known.iter() {
+        match decode_slice(&connect(name, *level)) {
+            Ok(Some(Packet::Connect(c))) => assert_eq!(c.protocol, *protocol),
+            other => panic!("({:?}, {}) -> {:?}", name, level, other),
+        }
+    }
+
+    // Known name with an unsupported level: the error carries both, so that a server can reply
+    // with RefusedProtocolVersion.
+    for (name, level) in [("MQTT", 2), ("MQIsdp", 4), ("MQTT", 6), ("MQTT", 0)].iter() {
+        match decode_slice(&connect(name, *level)) {
+            Err(Error::InvalidProtocol(n, l)) => assert_eq!((n.as_str(), l), (*name, *level)),
+            other => panic!("({:?}, {}) -> {:?}", name, level, other),
+        }
+    }
+
+    // Garbage name, truncated in the error on no_std rather than panicking.
+    match decode_slice(&connect("garbage-protocol", 4)) {
+        Err(Error::InvalidProtocol(name, 4)) => assert!("garbage-protocol".starts_with(name.as_str())),
+        other => panic!("garbage name -> {:?}", other),
+    }
+}
+
 #[test]
 fn test_decode_packet_n() {
     let data: &[u8] = &[
@@ -204,9 +737,11 @@ fn test_decode_packet_n() {
             message: b"offline",
             qos: QoS::AtLeastOnce,
             retain: false,
+            properties: None,
         }),
         username: Some("rust"),
         password: Some(b"mq"),
+        properties: None,
     };
 
     let pkt2 = Packet::Pingreq;
@@ -225,6 +760,70 @@ fn test_decode_packet_n() {
     assert_eq!(pkt3, decode_pkt3);
 }
 
+#[test]
+fn test_decode_at() {
+    let data: &[u8] = &[
+        0xAA, 0xBB, // unrelated header
+        0b00110000, 7, 0, 3, b'a', b'/', b'b', b'h', b'i', // publish
+        0b01000000, 2, 0, 10, // puback
+        0xCC, 0, // unrelated trailer
+    ];
+    let mut offset = 2;
+    match decode_at(data, &mut offset) {
+        Ok(Some(Packet::Publish(p))) => assert_eq!(p.payload, b"hi"),
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(offset, 11);
+    match decode_at(data, &mut offset) {
+        Ok(Some(Packet::Puback(a))) => assert_eq!(a.pid.get(), 10),
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(offset, 15);
+
+    // Incomplete or invalid packets leave the offset unchanged
+    assert_eq!(Ok(None), decode_at(&data[..14], &mut offset));
+    assert_eq!(
+        Err(Error::InvalidHeaderFlags(PacketType::Pingreq, 0b1100)),
+        decode_at(data, &mut offset)
+    );
+    assert_eq!(offset, 15);
+    let mut offset = 100;
+    assert_eq!(Ok(None), decode_at(data, &mut offset));
+    assert_eq!(offset, 100);
+}
+
+#[test]
+fn test_decode_into() {
+    let data: &[u8] = &[
+        0b00110000, 7, 0, 3, b'a', b'/', b'b', b'h', b'i', // publish
+        0b01000000, 2, 0, 10, // puback
+    ];
+    let mut slot = None;
+    let mut offset = 0;
+    let mut payloads = 0;
+    loop {
+        match decode_into(&data[offset..], &mut slot) {
+            Ok(0) => break,
+            Ok(len) => offset += len,
+            Err(e) => panic!("Failed decode: {:?}", e),
+        }
+        if let Some(Packet::Publish(p)) = &slot {
+            assert_eq!(p.payload, b"hi");
+            payloads += 1;
+        }
+    }
+    assert_eq!(offset, 13);
+    assert_eq!(payloads, 1);
+    match slot {
+        Some(Packet::Puback(a)) => assert_eq!(a.pid.get(), 10),
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Errors leave the slot alone
+    assert_eq!(Err(Error::InvalidHeader), decode_into(&[0, 0], &mut slot));
+    assert_eq!(Some(PacketType::Puback), slot.map(|p| p.get_type()));
+}
+
 #[test]
 fn test_connect() {
     let mut data: &[u8] = &[
@@ -248,9 +847,11 @@ fn test_connect() {
             message: b"offline",
             qos: QoS::AtLeastOnce,
             retain: false,
+            properties: None,
         }),
         username: Some("rust"),
         password: Some(b"mq"),
+        properties: None,
     };
 
     let packet_buf = &mut [0u8; 64];
@@ -259,6 +860,71 @@ fn test_connect() {
     // assert_eq!(data.len(), 0);
 }
 
+/// Embedded users rely on `Connect` borrowing its strings and bytes from the input buffer instead
+/// of copying them.
+#[test]
+fn test_connect_borrows_buffer() {
+    let data: &[u8] = &[
+        0b00010000, 39, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0b11001110, 0x00, 0x0a, //
+        0x00, 0x04, b't', b'e', b's', b't', // client_id
+        0x00, 0x02, b'/', b'a', // will topic
+        0x00, 0x07, b'o', b'f', b'f', b'l', b'i', b'n', b'e', // will msg
+        0x00, 0x04, b'r', b'u', b's', b't', // username
+        0x00, 0x02, b'm', b'q', // password
+    ];
+    let c = match decode_slice(data) {
+        Ok(Some(Packet::Connect(c))) => c,
+        other => panic!("Failed decode: {:?}", other),
+    };
+    let will = c.last_will.unwrap();
+    assert_eq!(c.client_id.as_ptr(), data[14..].as_ptr());
+    assert_eq!(will.topic.as_ptr(), data[20..].as_ptr());
+    assert_eq!(will.message.as_ptr(), data[24..].as_ptr());
+    assert_eq!(c.username.unwrap().as_ptr(), data[33..].as_ptr());
+    assert_eq!(c.password.unwrap().as_ptr(), data[39..].as_ptr());
+}
+
+#[test]
+fn test_connect_lenient_username() {
+    let data: &[u8] = &[
+        0b00010000, 22, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04,
+        0b11000010, // +username, +password, +clean_session
+        0x00, 0x0a, // 10 sec
+        0x00, 0x01, b'c', // client_id
+        0x00, 0x03, 0xFF, 0x00, 0xFE, // binary username
+        0x00, 0x02, b'm', b'q', // password = 'mq'
+    ];
+    // Strict by default
+    match decode_slice(data) {
+        Err(Error::InvalidString(_)) => (),
+        other => panic!("Expected InvalidString, got {:?}", other),
+    }
+    match decode_connect_lenient(data) {
+        Ok(Some((24, c, username))) => {
+            assert_eq!(username, Some(&[0xFF, 0x00, 0xFE][..]));
+            assert_eq!(c.username, None);
+            assert_eq!(c.client_id, "c");
+            assert_eq!(c.password, Some(&b"mq"[..]));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Valid usernames are decoded as usual
+    let mut data = data.to_vec();
+    data[17..20].copy_from_slice(b"bob");
+    match decode_connect_lenient(&data) {
+        Ok(Some((24, c, username))) => {
+            assert_eq!(username, Some(&b"bob"[..]));
+            assert_eq!(c.username, Some("bob"));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Other packets are rejected
+    assert_eq!(Err(Error::ProtocolViolation), decode_connect_lenient(&[0b11000000, 0]));
+    assert_eq!(Ok(None), decode_connect_lenient(&data[..10]));
+}
+
 #[test]
 fn test_connack() {
     let mut data: &[u8] = &[0b00100000, 2, 0b00000000, 0b00000001];
@@ -268,12 +934,96 @@ fn test_connack() {
             let o = Connack {
                 session_present: false,
                 code: ConnectReturnCode::RefusedProtocolVersion,
+                properties: None,
             };
             assert_eq!(c.session_present, o.session_present);
             assert_eq!(c.code, o.code);
+            assert!(!c.is_acceptable());
         }
         _ => panic!(),
     }
+
+    let data: &[u8] = &[0b00100000, 2, 0b00000000, 0b00000000];
+    match decode_slice(data) {
+        Ok(Some(Packet::Connack(c))) => assert!(c.is_acceptable()),
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // MQTT 5 reason codes are invalid in 3.1.1
+    let data: &[u8] = &[0b00100000, 2, 0b00000000, 6];
+    assert_eq!(Err(Error::InvalidConnectReturnCode(6)), decode_slice(data));
+    let data: &[u8] = &[0b00100000, 2, 0b00000000, 0x87];
+    assert_eq!(Err(Error::InvalidConnectReturnCode(0x87)), decode_slice(data));
+}
+
+#[test]
+fn test_connack_reason_code() {
+    let data: &[u8] = &[0b00100000, 3, 0b00000000, 0x87, 0];
+    match decode_slice_with_protocol(data, Protocol::MQTT5) {
+        Ok(Some((5, Packet::Connack(c)))) => {
+            assert_eq!(c.code, ConnectReturnCode::Reason(ReasonCode::NotAuthorized));
+            assert!(!c.is_acceptable());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    let data: &[u8] = &[0b00100000, 3, 0b00000000, 0x97, 0];
+    match decode_slice_with_protocol(data, Protocol::MQTT5) {
+        Ok(Some((5, Packet::Connack(c)))) => {
+            assert_eq!(c.code, ConnectReturnCode::Reason(ReasonCode::QuotaExceeded));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // 3.1.1 return codes and reason codes of other packets are invalid in MQTT 5
+    for &code in [5, 0x04, 0x10, 0x18, 0x8B, 0xA2, 0xFF].iter() {
+        let data: &[u8] = &[0b00100000, 3, 0b00000000, code, 0];
+        assert_eq!(
+            Err(Error::InvalidConnectReturnCode(code)),
+            decode_slice_with_protocol(data, Protocol::MQTT5)
+        );
+    }
+
+    // Only Success is acceptable, not every reason code below 0x80
+    let mut c = Connack {
+        session_present: false,
+        code: ConnectReturnCode::Reason(ReasonCode::Success),
+        properties: Some(Properties::default()),
+    };
+    assert!(c.is_acceptable());
+    c.code = ConnectReturnCode::Reason(ReasonCode::GrantedQoS1);
+    assert!(!c.is_acceptable());
+    c.code = ConnectReturnCode::Reason(ReasonCode::ContinueAuthentication);
+    assert!(!c.is_acceptable());
+}
+
+#[test]
+fn test_connack_mqtt5() {
+    // No properties: everything is supported
+    let data: &[u8] = &[0b00100000, 3, 0b00000001, 0, 0];
+    match decode_slice_with_protocol(data, Protocol::MQTT5) {
+        Ok(Some((5, Packet::Connack(c)))) => {
+            assert!(c.session_present);
+            assert_eq!(c.properties, Some(Properties::default()));
+            assert_eq!(c.max_qos(), QoS::ExactlyOnce);
+            assert!(c.retain_available());
+            assert!(c.is_acceptable());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // MaximumQoS = 2 is a protocol error
+    let data: &[u8] = &[0b00100000, 5, 0, 0, 2, 0x24, 2];
+    assert_eq!(
+        Err(Error::InvalidProperty(0x24)),
+        decode_slice_with_protocol(data, Protocol::MQTT5)
+    );
+
+    // SubscriptionIdentifier isn't allowed in a CONNACK
+    let data: &[u8] = &[0b00100000, 5, 0, 0, 2, 0x0B, 1];
+    assert_eq!(
+        Err(Error::InvalidProperty(0x0B)),
+        decode_slice_with_protocol(data, Protocol::MQTT5)
+    );
 }
 
 #[test]
@@ -288,29 +1038,133 @@ fn test_ping_resp() {
     assert_eq!(Ok(Some(Packet::Pingresp)), decode_slice(&mut data));
 }
 
+#[test]
+fn test_disconnect_trailing_data() {
+    let data: &[u8] = &[0b11100000, 0, 0x7F, 0x13, 0xA2, 0x00, 0xFF];
+    // Lenient by default
+    assert_eq!(
+        Ok(Some((2, Packet::Disconnect(Disconnect::default())))),
+        decode_slice_with_len(data)
+    );
+    assert_eq!(Err(Error::TrailingData(5)), decode_slice_strict(data, Protocol::MQTT311));
+    assert_eq!(
+        Ok(Some((2, Packet::Disconnect(Disconnect::default())))),
+        decode_slice_strict(&data[..2], Protocol::MQTT311)
+    );
+    // Other packets can be followed by more packets
+    let data: &[u8] = &[0b11000000, 0, 0b11100000, 0];
+    assert_eq!(
+        Ok(Some((2, Packet::Pingreq))),
+        decode_slice_strict(data, Protocol::MQTT311)
+    );
+}
+
 #[test]
 fn test_disconnect() {
     let mut data: &[u8] = &[0b11100000, 0b00000000];
-    assert_eq!(Ok(Some(Packet::Disconnect)), decode_slice(&mut data));
+    assert_eq!(Ok(Some(Packet::Disconnect(Disconnect::default()))), decode_slice(&mut data));
 }
 
+/// `clone_packet()` gives the same bytes as decoding and re-encoding, for minimal encodings.
 #[test]
-#[ignore]
-fn test_offset_start() {
-    let mut data: &[u8] = &[
-        1, 2, 3, 0b00110000, 10, 0x00, 0x03, 'a' as u8, '/' as u8, 'b' as u8, 'h' as u8, 'e' as u8,
-        'l' as u8, 'l' as u8, 'o' as u8, //
-        0b00111000, 10, 0x00, 0x03, 'a' as u8, '/' as u8, 'b' as u8, 'h' as u8, 'e' as u8,
-        'l' as u8, 'l' as u8, 'o' as u8, //
-        0b00111101, 12, 0x00, 0x03, 'a' as u8, '/' as u8, 'b' as u8, 0, 10, 'h' as u8, 'e' as u8,
-        'l' as u8, 'l' as u8, 'o' as u8,
-    ];
+fn test_clone_packet_with_status() {
+    let mut output = [0u8; 8];
+    assert_eq!(Ok(CloneStatus::Empty), clone_packet_with_status(&[], &mut output));
+    assert_eq!(Ok(0), clone_packet(&[], &mut output));
 
-    let packet_buf = &mut [0u8; 64];
-    assert_eq!(clone_packet(&mut data, &mut packet_buf[..]).unwrap(), 12);
-    assert_eq!(data.len(), 29);
+    // One byte of a Puback: the length is unknown
+    assert_eq!(Ok(CloneStatus::Incomplete), clone_packet_with_status(&[0b01000000], &mut output));
+    assert_eq!(Ok(0), clone_packet(&[0b01000000], &mut output));
 
-    match decode_slice(packet_buf) {
+    let input = [0b01000000, 2, 0, 10, 0b11000000];
+    assert_eq!(Ok(CloneStatus::Copied(4)), clone_packet_with_status(&input, &mut output));
+    assert_eq!(&output[..4], &input[..4]);
+    assert_eq!(Ok(4), clone_packet(&input, &mut output));
+
+    assert_eq!(Err(Error::WriteZero), clone_packet_with_status(&input, &mut output[..3]));
+}
+
+#[test]
+fn test_clone_packet_equivalence() {
+    let mut long = [b'x'; 131];
+    long[..3].copy_from_slice(&[0b00110000, 128, 1]);
+    long[3..8].copy_from_slice(&[0, 3, b'a', b'/', b'b']);
+    let packets: &[&[u8]] = &[
+        &[0b00010000, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00000010, 0, 10, 0, 1, b'c'],
+        &[0b00100000, 2, 0, 0],
+        &[0b00110000, 5, 0, 3, b'a', b'/', b'b'],
+        &[0b00111101, 7, 0, 3, b'a', b'/', b'b', 0, 10],
+        &long,
+        &[0b01000000, 2, 0, 10],
+        &[0b01100010, 2, 0, 10],
+        &[0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'b', 1],
+        &[0b10010000, 3, 0, 10, 0x80],
+        &[0b10100010, 7, 0, 10, 0, 3, b'a', b'/', b'b'],
+        &[0b10110000, 2, 0, 10],
+        &[0b11000000, 0],
+        &[0b11100000, 0],
+    ];
+    let mut cloned = [0u8; 256];
+    let mut encoded = [0u8; 256];
+    for &packet in packets {
+        let len = clone_packet(packet, &mut cloned).unwrap();
+        let decoded = decode_slice(packet).unwrap().unwrap();
+        let encoded_len = encode_slice(&decoded, &mut encoded).unwrap();
+        assert_eq!(&cloned[..len], &encoded[..encoded_len], "{:?}", decoded);
+        assert_eq!(Err(Error::WriteZero), clone_packet(packet, &mut cloned[..len - 1]));
+    }
+
+    // A non-minimal remaining length is copied as is, but re-encoded minimally
+    let packet = [0b11000000, 0x80, 0x00];
+    assert_eq!(Ok(3), clone_packet(&packet, &mut cloned));
+    assert_eq!(Ok(Some(Packet::Pingreq)), decode_slice(&packet));
+    assert_eq!(Ok(2), encode_slice(&Packet::Pingreq, &mut encoded));
+}
+
+proptest::proptest! {
+    /// Same as `test_clone_packet_equivalence()`, with random publish packets.
+    #[test]
+    fn test_clone_packet_equivalence_publish(topic in "[a-z0-9/]{1,100}",
+                                             payload in proptest::collection::vec(0u8.., 0..300),
+                                             qos in 0u8..=2,
+                                             pid in 1u16..,
+                                             retain: bool) {
+        let packet = Packet::Publish(Publish {
+            dup: false,
+            qospid: QosPid::from_u8u16(qos, pid),
+            retain,
+            topic_name: &topic,
+            payload: &payload,
+            properties: None,
+        });
+        let mut input = [0u8; 512];
+        let mut cloned = [0u8; 512];
+        let mut encoded = [0u8; 512];
+        let len = encode_slice(&packet, &mut input).unwrap();
+        proptest::prop_assert_eq!(Ok(len), clone_packet(&input[..len], &mut cloned));
+        let decoded = decode_slice(&cloned[..len]).unwrap().unwrap();
+        let encoded_len = encode_slice(&decoded, &mut encoded).unwrap();
+        proptest::prop_assert_eq!(&cloned[..len], &encoded[..encoded_len]);
+    }
+}
+
+#[test]
+#[ignore]
+fn test_offset_start() {
+    let mut data: &[u8] = &[
+        1, 2, 3, 0b00110000, 10, 0x00, 0x03, 'a' as u8, '/' as u8, 'b' as u8, 'h' as u8, 'e' as u8,
+        'l' as u8, 'l' as u8, 'o' as u8, //
+        0b00111000, 10, 0x00, 0x03, 'a' as u8, '/' as u8, 'b' as u8, 'h' as u8, 'e' as u8,
+        'l' as u8, 'l' as u8, 'o' as u8, //
+        0b00111101, 12, 0x00, 0x03, 'a' as u8, '/' as u8, 'b' as u8, 0, 10, 'h' as u8, 'e' as u8,
+        'l' as u8, 'l' as u8, 'o' as u8,
+    ];
+
+    let packet_buf = &mut [0u8; 64];
+    assert_eq!(clone_packet(&mut data, &mut packet_buf[..]).unwrap(), 12);
+    assert_eq!(data.len(), 29);
+
+    match decode_slice(packet_buf) {
         Ok(Some(Packet::Publish(p))) => {
             assert_eq!(p.dup, false);
             assert_eq!(p.retain, false);
@@ -386,11 +1240,72 @@ fn test_publish() {
     }
 }
 
+/// QoS 0 has no pid, so the payload starts right after the topic.
+#[test]
+fn test_publish_pid_consumption() {
+    // "ab" topic, then bytes that would be a valid pid
+    let data: &[u8] = &[0b00110000, 6, 0, 2, b'a', b'b', 0, 10];
+    match decode_slice(data) {
+        Ok(Some(Packet::Publish(p))) => {
+            assert_eq!(p.qospid, QosPid::AtMostOnce);
+            assert_eq!(p.topic_name, "ab");
+            assert_eq!(p.payload, &[0, 10]);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    for &(header, qospid) in [
+        (0b00110010, QosPid::from_u8u16(1, 10)),
+        (0b00110100, QosPid::from_u8u16(2, 10)),
+    ]
+    .iter()
+    {
+        let data: &[u8] = &[header, 8, 0, 2, b'a', b'b', 0, 10, b'h', b'i'];
+        match decode_slice(data) {
+            Ok(Some(Packet::Publish(p))) => {
+                assert_eq!(p.qospid, qospid);
+                assert_eq!(p.topic_name, "ab");
+                assert_eq!(p.payload, b"hi");
+            }
+            other => panic!("Failed decode: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_publish_payload_str() {
+    // QoS 2 with nothing after the pid: the pid isn't part of the payload
+    let data: &[u8] = &[0b00110100, 6, 0, 2, b'a', b'b', 0, 10];
+    match decode_slice(data) {
+        Ok(Some(Packet::Publish(p))) => {
+            assert_eq!(p.qospid, QosPid::from_u8u16(2, 10));
+            assert!(p.is_empty());
+            assert_eq!(p.payload_str(), Ok(""));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    let data: &[u8] = &[0b00110100, 11, 0, 2, b'a', b'b', 0, 10, b'h', 0xC3, 0xA9, b'h', b'o'];
+    match decode_slice(data) {
+        Ok(Some(Packet::Publish(p))) => {
+            assert!(!p.is_empty());
+            assert_eq!(p.payload_str(), Ok("h\u{e9}ho"));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Truncated UTF-8 sequence
+    let data: &[u8] = &[0b00110000, 5, 0, 2, b'a', b'b', 0xC3];
+    match decode_slice(data) {
+        Ok(Some(Packet::Publish(p))) => assert!(p.payload_str().is_err()),
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
 #[test]
 fn test_pub_ack() {
     let mut data: &[u8] = &[0b01000000, 0b00000010, 0, 10];
     match decode_slice(&mut data) {
-        Ok(Some(Packet::Puback(a))) => assert_eq!(a.get(), 10),
+        Ok(Some(Packet::Puback(a))) => assert_eq!(a.pid.get(), 10),
         other => panic!("Failed decode: {:?}", other),
     };
 }
@@ -399,7 +1314,7 @@ fn test_pub_ack() {
 fn test_pub_rec() {
     let mut data: &[u8] = &[0b01010000, 0b00000010, 0, 10];
     match decode_slice(&mut data) {
-        Ok(Some(Packet::Pubrec(a))) => assert_eq!(a.get(), 10),
+        Ok(Some(Packet::Pubrec(a))) => assert_eq!(a.pid.get(), 10),
         other => panic!("Failed decode: {:?}", other),
     };
 }
@@ -408,7 +1323,7 @@ fn test_pub_rec() {
 fn test_pub_rel() {
     let mut data: &[u8] = &[0b01100010, 0b00000010, 0, 10];
     match decode_slice(&mut data) {
-        Ok(Some(Packet::Pubrel(a))) => assert_eq!(a.get(), 10),
+        Ok(Some(Packet::Pubrel(a))) => assert_eq!(a.pid.get(), 10),
         other => panic!("Failed decode: {:?}", other),
     };
 }
@@ -417,11 +1332,45 @@ fn test_pub_rel() {
 fn test_pub_comp() {
     let mut data: &[u8] = &[0b01110000, 0b00000010, 0, 10];
     match decode_slice(&mut data) {
-        Ok(Some(Packet::Pubcomp(a))) => assert_eq!(a.get(), 10),
+        Ok(Some(Packet::Pubcomp(a))) => assert_eq!(a.pid.get(), 10),
         other => panic!("Failed decode: {:?}", other),
     };
 }
 
+#[test]
+fn test_pub_ack_mqtt5() {
+    // Short form, success
+    let data: &[u8] = &[0b01110000, 2, 0, 10];
+    match decode_slice_with_protocol(data, Protocol::MQTT5) {
+        Ok(Some((4, Packet::Pubcomp(a)))) => {
+            assert_eq!(a.reason_code, ReasonCode::Success);
+            assert_eq!(a.properties, None);
+            assert_eq!(a.reason_string(), None);
+            assert_eq!(a.user_properties().count(), 0);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Reason code and empty properties
+    let data: &[u8] = &[0b01110000, 4, 0, 10, 0x92, 0];
+    match decode_slice_with_protocol(data, Protocol::MQTT5) {
+        Ok(Some((6, Packet::Pubcomp(a)))) => {
+            assert_eq!(a.reason_code, ReasonCode::PacketIdentifierNotFound);
+            assert_eq!(a.properties, Some(Properties::default()));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Unknown reason code, or not valid for this packet type
+    let data: &[u8] = &[0b01000000, 3, 0, 10, 0x03];
+    assert_eq!(Err(Error::InvalidReasonCode(0x03)), decode_slice_with_protocol(data, Protocol::MQTT5));
+    let data: &[u8] = &[0b01000000, 3, 0, 10, 0x92];
+    assert_eq!(Err(Error::InvalidReasonCode(0x92)), decode_slice_with_protocol(data, Protocol::MQTT5));
+    // Property not allowed in acks
+    let data: &[u8] = &[0b01000000, 6, 0, 10, 0x80, 2, 0x01, 1];
+    assert_eq!(Err(Error::InvalidProperty(0x01)), decode_slice_with_protocol(data, Protocol::MQTT5));
+}
+
 #[cfg(feature = "std")]
 #[test]
 fn test_subscribe() {
@@ -434,6 +1383,9 @@ fn test_subscribe() {
             let t = SubscribeTopic {
                 topic_path: LimitedString::from("a/b"),
                 qos: QoS::AtMostOnce,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: RetainHandling::SendAtSubscribe,
             };
             assert_eq!(s.topics.get(0), Some(&t));
         }
@@ -453,6 +1405,9 @@ fn test_subscribe() {
             let t = SubscribeTopic {
                 topic_path: LimitedString::from_str("a/b").unwrap(),
                 qos: QoS::AtMostOnce,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: RetainHandling::SendAtSubscribe,
             };
             assert_eq!(s.topics.get(0), Some(&t));
         }
@@ -460,6 +1415,22 @@ fn test_subscribe() {
     }
 }
 
+#[test]
+fn test_subscribe_topic_eq_str() {
+    let data: &[u8] = &[0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'b', 0];
+    match decode_slice(data) {
+        Ok(Some(Packet::Subscribe(s))) => {
+            let t = &s.topics[0];
+            assert!(*t == "a/b");
+            assert!(t == "a/b");
+            assert!(*t != "a/c");
+            assert!(t != "a/b/c");
+            assert!(s.topics.iter().any(|t| *t == "a/b"));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
 #[test]
 fn test_suback() {
     let mut data: &[u8] = &[0b10010000, 3, 0, 10, 0b00000010];
@@ -501,6 +1472,279 @@ fn test_unsubscribe() {
     }
 }
 
+#[test]
+fn test_topic_null_char() {
+    // "a\0b" is valid UTF-8, but MQTT forbids U+0000
+    let cases: [&[u8]; 3] = [
+        &[0b00110000, 5, 0x00, 0x03, b'a', 0, b'b'],
+        &[0b10000010, 8, 0x00, 0x0a, 0x00, 0x03, b'a', 0, b'b', 0],
+        &[0b10100010, 7, 0x00, 0x0a, 0x00, 0x03, b'a', 0, b'b'],
+    ];
+    for case in cases.iter() {
+        assert_eq!(Err(Error::InvalidTopic), decode_slice(case), "{:?}", case);
+    }
+    assert_eq!(Err(Error::InvalidTopic), decode_subscribe_ref(cases[1], Protocol::MQTT311));
+
+    // Will topic
+    let data: &[u8] = &[
+        0b00010000, 20, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00000110, 0, 10, //
+        0, 1, b'c', 0, 3, b'a', 0, b'b', 0, 0,
+    ];
+    assert_eq!(Err(Error::InvalidTopic), decode_slice(data));
+
+    // Other strings aren't topics
+    let data: &[u8] = &[
+        0b00010000, 15, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00000010, 0, 10, //
+        0, 3, b'a', 0, b'b',
+    ];
+    assert_eq!(Err(Error::InvalidNullCharacter), decode_slice(data));
+}
+
+#[test]
+fn test_publish_wildcard_topic() {
+    let data: &[u8] = &[0b00110000, 7, 0x00, 0x05, b'a', b'/', b'+', b'/', b'b'];
+    assert_eq!(Err(Error::InvalidTopic), decode_slice(data));
+    let data: &[u8] = &[0b00110000, 5, 0x00, 0x03, b'a', b'/', b'#'];
+    assert_eq!(Err(Error::InvalidTopic), decode_slice(data));
+}
+
+#[test]
+fn test_suback_return_codes_iter() {
+    let data: &[u8] = &[
+        0b10010000, 10, 0, 10, 0x00, 0x01, 0x02, 0x80, 0x00, 0x01, 0x02, 0x80, 0b11000000, 0,
+    ];
+    let (len, pid, codes) = Suback::return_codes_iter(data).unwrap().unwrap();
+    assert_eq!(len, 12);
+    assert_eq!(pid, Pid::try_from(10).unwrap());
+    let expected = [
+        SubscribeReturnCodes::Success(QoS::AtMostOnce),
+        SubscribeReturnCodes::Success(QoS::AtLeastOnce),
+        SubscribeReturnCodes::Success(QoS::ExactlyOnce),
+        SubscribeReturnCodes::Failure,
+    ];
+    assert!(codes.map(Result::unwrap).eq(expected.iter().chain(expected.iter()).copied()));
+
+    // The allocating path is limited to 5 return codes on no_std
+    #[cfg(feature = "std")]
+    assert!(decode_slice(data).is_ok());
+    #[cfg(not(feature = "std"))]
+    assert_eq!(Err(Error::InvalidLength), decode_slice(data));
+
+    // Invalid codes are returned by the iterator
+    let data: &[u8] = &[0b10010000, 4, 0, 10, 0x01, 0x03];
+    let (_, _, mut codes) = Suback::return_codes_iter(data).unwrap().unwrap();
+    assert_eq!(Some(Ok(SubscribeReturnCodes::Success(QoS::AtLeastOnce))), codes.next());
+    assert_eq!(Some(Err(Error::InvalidQos(3))), codes.next());
+
+    assert_eq!(Ok(None), Suback::return_codes_iter(&data[..5]).map(|r| r.map(|_| ())));
+    assert_eq!(
+        Err(Error::ProtocolViolation),
+        Suback::return_codes_iter(&[0b11000000, 0]).map(|r| r.map(|_| ()))
+    );
+    assert_eq!(
+        Err(Error::InvalidLength),
+        Suback::return_codes_iter(&[0b10010000, 1, 0, 0]).map(|r| r.map(|_| ()))
+    );
+    assert_eq!(Err(Error::InvalidLength), decode_slice(&[0b10010000, 1, 0, 0]));
+}
+
+#[test]
+fn test_subscribe_topics_iter() {
+    let data: &[u8] = &[
+        0b10000010, 31, 0, 10, //
+        0, 1, b'a', 0, //
+        0, 1, b'b', 1, //
+        0, 1, b'c', 2, //
+        0, 1, b'd', 0, //
+        0, 1, b'e', 1, //
+        0, 1, b'f', 2, //
+        0, 2, b'g', b'/', 0, //
+        0b11000000, 0,
+    ];
+    let (len, pid, topics) = Subscribe::topics_iter(data, Protocol::MQTT311).unwrap().unwrap();
+    assert_eq!(len, 33);
+    assert_eq!(pid, Pid::try_from(10).unwrap());
+    let expected = [
+        ("a", QoS::AtMostOnce),
+        ("b", QoS::AtLeastOnce),
+        ("c", QoS::ExactlyOnce),
+        ("d", QoS::AtMostOnce),
+        ("e", QoS::AtLeastOnce),
+        ("f", QoS::ExactlyOnce),
+        ("g/", QoS::AtMostOnce),
+    ];
+    assert!(topics.eq(expected.iter().copied()));
+
+    // The allocating path is limited to 5 topics on no_std
+    #[cfg(feature = "std")]
+    assert!(decode_slice(data).is_ok());
+    #[cfg(not(feature = "std"))]
+    assert_eq!(Err(Error::InvalidLength), decode_slice(data));
+
+    // MQTT 5 properties and subscription options
+    let data: &[u8] = &[0b10000010, 13, 0, 10, 2, 0x0B, 3, 0, 1, b'a', 0b100001, 0, 1, b'b', 2];
+    let (len, _, topics) = Subscribe::topics_iter(data, Protocol::MQTT5).unwrap().unwrap();
+    assert_eq!(len, 15);
+    assert!(topics.eq([("a", QoS::AtLeastOnce), ("b", QoS::ExactlyOnce)].iter().copied()));
+
+    // Invalid topics are reported before iterating
+    let data: &[u8] = &[0b10000010, 10, 0, 10, 0, 1, b'a', 1, 0, 1, 0, 3];
+    let topics_iter =
+        |buf: &[u8], protocol| Subscribe::topics_iter(buf, protocol).map(|r| r.map(|_| ()));
+    assert_eq!(Err(Error::InvalidTopic), topics_iter(data, Protocol::MQTT311));
+    assert_eq!(Ok(None), topics_iter(&data[..5], Protocol::MQTT311));
+    assert_eq!(Err(Error::ProtocolViolation), topics_iter(&[0b11000000, 0], Protocol::MQTT311));
+    assert_eq!(
+        Err(Error::InvalidLength),
+        topics_iter(&[0b10000010, 2, 0, 10], Protocol::MQTT311)
+    );
+    // Remaining length too short for the pid, followed by more data
+    let data: &[u8] = &[0b10000010, 1, 0, 3, 0, 1, b'a', 0];
+    assert_eq!(Err(Error::InvalidLength), topics_iter(data, Protocol::MQTT311));
+}
+
+#[test]
+fn test_subscribe_from_buffer_into() {
+    let data: &[u8] = &[
+        0b10000010, 31, 0, 10, //
+        0, 1, b'a', 0, //
+        0, 1, b'b', 1, //
+        0, 1, b'c', 2, //
+        0, 1, b'd', 0, //
+        0, 1, b'e', 1, //
+        0, 1, b'f', 2, //
+        0, 2, b'g', b'/', 0, //
+        0b11000000, 0,
+    ];
+    let empty = SubscribeTopic {
+        topic_path: LimitedString::from_str("").unwrap(),
+        qos: QoS::AtMostOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_handling: RetainHandling::SendAtSubscribe,
+    };
+    let mut out = [(); 8].map(|_| empty.clone());
+    let (len, pid, count) = Subscribe::from_buffer_into(data, &mut out).unwrap().unwrap();
+    assert_eq!((len, pid, count), (33, Pid::try_from(10).unwrap(), 7));
+    let expected = [
+        ("a", QoS::AtMostOnce),
+        ("b", QoS::AtLeastOnce),
+        ("c", QoS::ExactlyOnce),
+        ("d", QoS::AtMostOnce),
+        ("e", QoS::AtLeastOnce),
+        ("f", QoS::ExactlyOnce),
+        ("g/", QoS::AtMostOnce),
+    ];
+    for (topic, &(path, qos)) in out.iter().zip(expected.iter()) {
+        assert_eq!(topic, path);
+        assert_eq!(topic.qos, qos);
+    }
+    assert_eq!(out[7], empty);
+
+    // Not enough room
+    assert_eq!(Err(Error::InvalidLength), Subscribe::from_buffer_into(data, &mut out[..6]));
+
+    assert_eq!(Ok(None), Subscribe::from_buffer_into(&data[..10], &mut out));
+    assert_eq!(
+        Err(Error::ProtocolViolation),
+        Subscribe::from_buffer_into(&[0b11000000, 0], &mut out)
+    );
+    assert_eq!(
+        Err(Error::InvalidLength),
+        Subscribe::from_buffer_into(&[0b10000010, 2, 0, 10], &mut out)
+    );
+    let data: &[u8] = &[0b10000010, 6, 0, 10, 0, 1, b'a', 3];
+    assert_eq!(Err(Error::InvalidQos(3)), Subscribe::from_buffer_into(data, &mut out));
+    // Remaining length too short for the pid, followed by more data
+    let data: &[u8] = &[0b10000010, 1, 0, 3, 0, 1, b'a', 0];
+    assert_eq!(Err(Error::InvalidLength), Subscribe::from_buffer_into(data, &mut out));
+}
+
+#[test]
+fn test_subscribe_topic_too_long() {
+    // A 300 bytes topic doesn't fit in a no_std `LimitedString`
+    let mut data = [b'a'; 308];
+    data[..7].copy_from_slice(&[0b10000010, 0b10110001, 0b10, 0, 10, 0x01, 0x2C]);
+    data[307] = 1;
+    let mut unsubscribe = [b'a'; 307];
+    unsubscribe[..7].copy_from_slice(&[0b10100010, 0b10110000, 0b10, 0, 10, 0x01, 0x2C]);
+    let empty = SubscribeTopic {
+        topic_path: LimitedString::from_str("").unwrap(),
+        qos: QoS::AtMostOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_handling: RetainHandling::SendAtSubscribe,
+    };
+    let mut out = [empty];
+
+    #[cfg(feature = "std")]
+    {
+        match decode_slice(&data) {
+            Ok(Some(Packet::Subscribe(s))) => assert_eq!(s.topics[0].topic_path.len(), 300),
+            other => panic!("Failed decode: {:?}", other),
+        }
+        assert_eq!(
+            Ok(Some((308, Pid::try_from(10).unwrap(), 1))),
+            Subscribe::from_buffer_into(&data, &mut out)
+        );
+        match decode_slice(&unsubscribe) {
+            Ok(Some(Packet::Unsubscribe(u))) => assert_eq!(u.topics[0].len(), 300),
+            other => panic!("Failed decode: {:?}", other),
+        }
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        assert_eq!(Err(Error::InvalidLength), decode_slice(&data));
+        assert_eq!(Err(Error::InvalidLength), Subscribe::from_buffer_into(&data, &mut out));
+        assert_eq!(Err(Error::InvalidLength), decode_slice(&unsubscribe));
+    }
+}
+
+#[test]
+fn test_subscribe_no_topics() {
+    // Only a pid, no topic filters
+    let data: &[u8] = &[0b10000010, 2, 0, 10];
+    assert_eq!(Err(Error::InvalidLength), decode_slice(data));
+    assert_eq!(Err(Error::InvalidLength), decode_subscribe_ref(data, Protocol::MQTT311));
+    let data: &[u8] = &[0b10100010, 2, 0, 10];
+    assert_eq!(Err(Error::InvalidLength), decode_slice(data));
+}
+
+#[test]
+fn test_topic_collections_len() {
+    let data: &[u8] = &[
+        0b10000010, 14, 0, 10, 0, 3, b'a', b'/', b'b', 0, 0, 3, b'c', b'/', b'd', 1,
+    ];
+    match decode_slice(data) {
+        Ok(Some(Packet::Subscribe(s))) => {
+            assert_eq!(s.len(), 2);
+            assert!(!s.is_empty());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    let data: &[u8] = &[0b10100010, 5, 0, 10, 0, 1, b'a'];
+    match decode_slice(data) {
+        Ok(Some(Packet::Unsubscribe(u))) => {
+            assert_eq!(u.len(), 1);
+            assert!(!u.is_empty());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    let data: &[u8] = &[0b10010000, 5, 0, 10, 0, 1, 0x80];
+    match decode_slice(data) {
+        Ok(Some(Packet::Suback(s))) => {
+            assert_eq!(s.len(), 3);
+            assert!(!s.is_empty());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    let pid = Pid::try_from(10).unwrap();
+    assert!(Subscribe::new(pid, LimitedVec::new()).is_empty());
+    assert!(Unsubscribe::new(pid, LimitedVec::new()).is_empty());
+    assert_eq!(Suback::new(pid, LimitedVec::new()).len(), 0);
+}
+
 #[test]
 fn test_unsub_ack() {
     let mut data: &[u8] = &[0b10110000, 2, 0, 10];
@@ -511,3 +1755,657 @@ fn test_unsub_ack() {
         other => panic!("Failed decode: {:?}", other),
     }
 }
+
+#[test]
+fn test_subscribe_mqtt5() {
+    let data: &[u8] = &[
+        0b10000010, 13, 0, 10, // pid
+        4, 0x0B, 0x80, 0x01, 0x26, // properties: subscription id 128, truncated user property
+        0, 3, b'a', b'/', b'b', 0,
+    ];
+    assert_eq!(Err(Error::InvalidLength), decode_slice_with_protocol(data, Protocol::MQTT5));
+
+    let data: &[u8] = &[
+        0b10000010, 12, 0, 10, // pid
+        3, 0x0B, 0x80, 0x01, // properties: subscription id 128
+        0, 3, b'a', b'/', b'b', 0,
+    ];
+    match decode_slice_with_protocol(data, Protocol::MQTT5) {
+        Ok(Some((14, Packet::Subscribe(s)))) => {
+            assert_eq!(s.subscription_id(), Some(128));
+            assert_eq!(s.topics.len(), 1);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Subscription identifier 0 and repeated subscription identifiers are invalid
+    let data: &[u8] = &[0b10000010, 5, 0, 10, 2, 0x0B, 0];
+    assert_eq!(Err(Error::InvalidProperty(0x0B)), decode_slice_with_protocol(data, Protocol::MQTT5));
+    let data: &[u8] = &[0b10000010, 7, 0, 10, 4, 0x0B, 1, 0x0B, 2];
+    assert_eq!(Err(Error::InvalidProperty(0x0B)), decode_slice_with_protocol(data, Protocol::MQTT5));
+    // Properties not allowed in a SUBSCRIBE
+    let data: &[u8] = &[0b10000010, 6, 0, 10, 3, 0x23, 0, 1];
+    assert_eq!(Err(Error::InvalidProperty(0x23)), decode_slice_with_protocol(data, Protocol::MQTT5));
+}
+
+#[test]
+fn test_subscribe_ref() {
+    let data: &[u8] = &[
+        0b10000010, 16, 0, 10, // pid
+        2, 0x0B, 42, // properties: subscription id 42
+        0, 3, b'a', b'/', b'b', 0, // topic
+        0, 2, b'c', b'/', 2, // topic
+    ];
+    let s = match decode_subscribe_ref(data, Protocol::MQTT5) {
+        Ok(Some((18, s))) => s,
+        other => panic!("Failed decode: {:?}", other),
+    };
+    assert_eq!(s.pid.get(), 10);
+    assert_eq!(s.properties.unwrap().iter().next(), Some(Property::SubscriptionIdentifier(42)));
+    let mut topics = s.topics();
+    assert_eq!(topics.next(), Some(("a/b", QoS::AtMostOnce)));
+    assert_eq!(topics.next(), Some(("c/", QoS::ExactlyOnce)));
+    assert_eq!(topics.next(), None);
+
+    // Same topics as the allocating decoder
+    match decode_slice_with_protocol(data, Protocol::MQTT5) {
+        Ok(Some((18, Packet::Subscribe(sub)))) => {
+            assert!(sub
+                .topics
+                .iter()
+                .map(|t| (t.topic_path.as_str(), t.qos))
+                .eq(s.topics()));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Not limited by the LimitedVec capacity
+    let data: &[u8] = &[
+        0b10000010, 26, 0, 10, 0, 1, b'a', 0, 0, 1, b'b', 0, 0, 1, b'c', 0, 0, 1, b'd', 0, 0, 1,
+        b'e', 0, 0, 1, b'f', 0,
+    ];
+    match decode_subscribe_ref(data, Protocol::MQTT311) {
+        Ok(Some((28, s))) => assert_eq!(s.topics().count(), 6),
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Errors are reported when decoding, not when iterating
+    let data: &[u8] = &[0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'b', 3];
+    assert_eq!(Err(Error::InvalidQos(3)), decode_subscribe_ref(data, Protocol::MQTT311));
+    let data: &[u8] = &[0b10000010, 7, 0, 10, 0, 3, b'a', b'/', b'b'];
+    assert_eq!(Err(Error::InvalidLength), decode_subscribe_ref(data, Protocol::MQTT311));
+    let data: &[u8] = &[0b10000010, 8, 0, 10, 0, 4, b'a', b'/', b'b', 1];
+    assert_eq!(Err(Error::InvalidLength), decode_subscribe_ref(data, Protocol::MQTT311));
+    // Remaining length too short for the pid, followed by more data
+    for &len in [0, 1].iter() {
+        let data: &[u8] = &[0b10000010, len, 0, 3, 0, 1, b'a', 0];
+        assert_eq!(Err(Error::InvalidLength), decode_subscribe_ref(data, Protocol::MQTT311));
+        assert_eq!(Err(Error::InvalidLength), decode_subscribe_ref(data, Protocol::MQTT5));
+    }
+
+    // Incomplete or other packets
+    let data: &[u8] = &[0b10000010, 8, 0, 10, 0, 3, b'a'];
+    assert_eq!(Ok(None), decode_subscribe_ref(data, Protocol::MQTT311));
+    let data: &[u8] = &[0b11000000, 0];
+    assert_eq!(Err(Error::ProtocolViolation), decode_subscribe_ref(data, Protocol::MQTT311));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_drain_packets() {
+    let mut buf = bm(&[
+        0b00110000, 7, 0, 3, b'a', b'/', b'b', b'h', b'i', // publish
+        0b11000000, 0, // pingreq
+        0b00110000, 7, 0, 3, b'a', // half a publish
+    ]);
+    let frames = drain_packets(&mut buf).unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(&frames[0][..], &[0b00110000, 7, 0, 3, b'a', b'/', b'b', b'h', b'i']);
+    match decode_slice(&frames[0]) {
+        Ok(Some(Packet::Publish(p))) => assert_eq!(p.payload, b"hi"),
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(Ok(Some(Packet::Pingreq)), decode_slice(&frames[1]));
+    assert_eq!(&buf[..], &[0b00110000, 7, 0, 3, b'a']);
+
+    // Nothing more to drain until the rest of the publish arrives.
+    assert_eq!(Ok(vec![]), drain_packets(&mut buf));
+    buf.extend_from_slice(b"/bhi");
+    assert_eq!(1, drain_packets(&mut buf).unwrap().len());
+    assert!(buf.is_empty());
+
+    // Errors leave the buffer untouched.
+    let mut buf = bm(&[0b11000000, 0, 0, 0]);
+    assert_eq!(Err(Error::InvalidHeader), drain_packets(&mut buf));
+    assert_eq!(buf.len(), 4);
+}
+
+#[test]
+fn test_publish_mqtt5() {
+    let data: &[u8] = &[
+        0b00110010, 24, // QoS 1
+        0, 3, b'a', b'/', b'b', // topic
+        0, 10, // pid
+        11, // properties length
+        0x08, 0, 3, b'r', b'/', b'1', // response topic
+        0x09, 0, 2, 0xCA, 0xFE, // correlation data
+        b'h', b'e', b'l', b'l', b'o', // payload
+    ];
+    match decode_slice_with_protocol(data, Protocol::MQTT5) {
+        Ok(Some((26, Packet::Publish(p)))) => {
+            assert_eq!(p.qospid, QosPid::from_u8u16(1, 10));
+            assert_eq!(p.topic_name, "a/b");
+            assert_eq!(p.response_topic(), Some("r/1"));
+            assert_eq!(p.correlation_data(), Some(&[0xCA, 0xFE][..]));
+            assert_eq!(p.payload, b"hello");
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // The same bytes decoded as MQTT 3.1.1 have no properties
+    match decode_slice(data) {
+        Ok(Some(Packet::Publish(p))) => {
+            assert_eq!(p.properties, None);
+            assert_eq!(p.response_topic(), None);
+            assert_eq!(p.payload.len(), 17);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
+#[test]
+fn test_decode_limits() {
+    // 1000 user properties of 6 bytes each
+    let user_properties = vec![Property::UserProperty("k", ""); 1000];
+    let packet = Publish {
+        dup: false,
+        qospid: QosPid::AtMostOnce,
+        retain: false,
+        topic_name: "a",
+        payload: b"",
+        properties: Some(Properties::new(&user_properties)),
+    }
+    .into();
+    let mut buf = vec![0; 8000];
+    let len = encode_slice(&packet, &mut buf).unwrap();
+    let buf = &buf[..len];
+
+    let unlimited = DecodeLimits::default();
+    assert!(decode_slice_with_limits(buf, Protocol::MQTT5, &unlimited).unwrap().is_some());
+    for &(max_properties, max_properties_len, ok) in [
+        (1000, 6000, true),
+        (999, 6000, false),
+        (1000, 5999, false),
+    ]
+    .iter()
+    {
+        let limits = DecodeLimits {
+            max_properties,
+            max_properties_len,
+            ..DecodeLimits::default()
+        };
+        match decode_slice_with_limits(buf, Protocol::MQTT5, &limits) {
+            Ok(Some((l, p))) if ok => assert_eq!((l, p), (len, packet.clone())),
+            Err(Error::LimitExceeded) if !ok => (),
+            other => panic!("Unexpected {:?} for {:?}", other, limits),
+        }
+    }
+
+    // Property limits only apply to MQTT 5 properties
+    let limits = DecodeLimits {
+        max_properties: 0,
+        max_properties_len: 0,
+        ..DecodeLimits::default()
+    };
+    assert!(decode_slice_with_limits(buf, Protocol::MQTT311, &limits).unwrap().is_some());
+
+    // The packet size is checked from the fixed header, before the body is received
+    for &(max_packet_size, ok) in [(len, true), (len - 1, false)].iter() {
+        let limits = DecodeLimits {
+            max_packet_size,
+            ..DecodeLimits::default()
+        };
+        match decode_slice_with_limits(buf, Protocol::MQTT5, &limits) {
+            Ok(Some((l, _))) if ok => assert_eq!(l, len),
+            Err(Error::LimitExceeded) if !ok => (),
+            other => panic!("Unexpected {:?} for {:?}", other, limits),
+        }
+        match decode_slice_with_limits(&buf[..4], Protocol::MQTT5, &limits) {
+            Ok(None) if ok => (),
+            Err(Error::LimitExceeded) if !ok => (),
+            other => panic!("Unexpected {:?} for {:?}", other, limits),
+        }
+    }
+    assert_eq!(Ok(None), decode_slice_with_limits(&buf[..1], Protocol::MQTT5, &limits));
+}
+
+/// The fast path for fixed-size packets must give the same result as the generic one.
+#[test]
+fn test_decode_fixed() {
+    let mut fast = 0;
+    for hd in 0..=255 {
+        for len in 0..=3 {
+            for &(a, b) in [(0, 0), (0, 1), (0xFF, 0xFF)].iter() {
+                let buf = [hd, len, a, b];
+                for end in 2..=4 {
+                    let res = decoder::decode_fixed(&buf[..end]);
+                    if let Ok(None) = res {
+                        continue;
+                    }
+                    fast += 1;
+                    for &protocol in [Protocol::MQTT311, Protocol::MQTT5].iter() {
+                        let generic = decoder::decode_generic(&buf[..end], protocol);
+                        assert_eq!(res.clone().map(Option::unwrap), generic.map(Option::unwrap));
+                    }
+                }
+            }
+        }
+    }
+    // 3 pings * 3 pids * 3 ends + 5 acks * 3 pids * 1 end
+    assert_eq!(fast, 42);
+}
+
+#[test]
+fn test_dispatch() {
+    let handlers = PacketHandlers {
+        connect: &|_| PacketType::Connect,
+        connack: &|_| PacketType::Connack,
+        publish: &|_| PacketType::Publish,
+        puback: &|_| PacketType::Puback,
+        pubrec: &|_| PacketType::Pubrec,
+        pubrel: &|_| PacketType::Pubrel,
+        pubcomp: &|_| PacketType::Pubcomp,
+        subscribe: &|_| PacketType::Subscribe,
+        suback: &|_| PacketType::Suback,
+        unsubscribe: &|_| PacketType::Unsubscribe,
+        unsuback: &|_| PacketType::Unsuback,
+        pingreq: &|| PacketType::Pingreq,
+        pingresp: &|| PacketType::Pingresp,
+        disconnect: &|_| PacketType::Disconnect,
+        auth: &|_| PacketType::Auth,
+    };
+    let pid = Pid::try_from(10).unwrap();
+    let packets: Vec<Packet> = vec![
+        Connect::builder("c").build().unwrap().into(),
+        Connack {
+            session_present: false,
+            code: ConnectReturnCode::Accepted,
+            properties: None,
+        }
+        .into(),
+        Publish {
+            dup: false,
+            qospid: QosPid::AtMostOnce,
+            retain: false,
+            topic_name: "a",
+            payload: b"",
+            properties: None,
+        }
+        .into(),
+        Packet::Puback(pid.into()),
+        Packet::Pubrec(pid.into()),
+        Packet::Pubrel(pid.into()),
+        Packet::Pubcomp(pid.into()),
+        Subscribe {
+            pid,
+            topics: LimitedVec::new(),
+            properties: None,
+        }
+        .into(),
+        Suback {
+            pid,
+            return_codes: LimitedVec::new(),
+        }
+        .into(),
+        Unsubscribe {
+            pid,
+            topics: LimitedVec::new(),
+        }
+        .into(),
+        Packet::Unsuback(pid),
+        Packet::Pingreq,
+        Packet::Pingresp,
+        Disconnect::default().into(),
+        Auth::default().into(),
+    ];
+    for p in packets.iter() {
+        assert_eq!(p.get_type(), dispatch(p, &handlers));
+    }
+}
+
+#[test]
+fn test_packet_summary() {
+    let data: &[u8] = &[0b00110010, 9, 0x00, 0x03, b'a', b'/', b'b', 0x00, 0x0a, b'h', b'i'];
+    let summary = match decode_slice(data) {
+        Ok(Some(p)) => p.summary(),
+        other => panic!("Failed decode: {:?}", other),
+    };
+    let expected = PacketSummary {
+        typ: PacketType::Publish,
+        qos: Some(QoS::AtLeastOnce),
+        pid: Some(10),
+        topic_len: Some(3),
+        payload_len: Some(2),
+    };
+    assert_eq!(expected, summary);
+
+    let summary = Packet::Unsuback(Pid::try_from(7).unwrap()).summary();
+    assert_eq!(PacketType::Unsuback, summary.typ);
+    assert_eq!((None, Some(7), None), (summary.qos, summary.pid, summary.topic_len));
+}
+
+#[test]
+fn test_packet_topic_pid() {
+    let data: &[u8] = &[0b00110010, 9, 0x00, 0x03, b'a', b'/', b'b', 0x00, 0x0a, b'h', b'i'];
+    match decode_slice(data) {
+        Ok(Some(p)) => {
+            assert_eq!(p.topic(), Some("a/b"));
+            assert_eq!(p.pid(), Some(Pid::try_from(10).unwrap()));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    let data: &[u8] = &[0b01000000, 2, 0x00, 0x0a];
+    match decode_slice(data) {
+        Ok(Some(p)) => {
+            assert_eq!(p.topic(), None);
+            assert_eq!(p.pid(), Some(Pid::try_from(10).unwrap()));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // QoS 0 publishes have a topic but no pid
+    let data: &[u8] = &[0b00110000, 5, 0x00, 0x03, b'a', b'/', b'b'];
+    match decode_slice(data) {
+        Ok(Some(p)) => assert_eq!((p.topic(), p.pid()), (Some("a/b"), None)),
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
+#[test]
+fn test_packet_eq_inner() {
+    let publish = Publish {
+        dup: false,
+        qospid: QosPid::AtMostOnce,
+        retain: false,
+        topic_name: "a/b",
+        payload: b"",
+        properties: None,
+    };
+    let connack = Connack {
+        session_present: false,
+        code: ConnectReturnCode::Accepted,
+        properties: None,
+    };
+    let data: &[u8] = &[0b00110000, 5, 0x00, 0x03, b'a', b'/', b'b'];
+    match decode_slice(data) {
+        Ok(Some(p)) => {
+            assert_eq!(p, publish);
+            assert_ne!(p, connack);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    let data: &[u8] = &[0b00100000, 2, 0, 0];
+    match decode_slice(data) {
+        Ok(Some(p)) => {
+            assert_eq!(p, connack);
+            assert_ne!(p, publish);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    let suback = Suback::new(Pid::try_from(10).unwrap(), LimitedVec::new());
+    assert_ne!(Packet::Pingreq, suback);
+}
+
+#[test]
+fn test_packet_cmp_by_type_then_pid() {
+    let publish = |topic, pid| {
+        Packet::Publish(Publish {
+            dup: false,
+            qospid: QosPid::from_u8u16(1, pid),
+            retain: false,
+            topic_name: topic,
+            payload: b"",
+            properties: None,
+        })
+    };
+    let pid = |pid| Pid::try_from(pid).unwrap();
+    let mut packets = [
+        Packet::Pingreq,
+        Packet::Puback(pid(2).into()),
+        publish("b", 1),
+        Packet::Puback(pid(1).into()),
+        publish("a", 2),
+        publish("a", 1),
+        Packet::Connack(Connack {
+            session_present: false,
+            code: ConnectReturnCode::Accepted,
+            properties: None,
+        }),
+    ];
+    packets.sort_by(Packet::cmp_by_type_then_pid);
+    let sorted: [(PacketType, Option<u16>); 7] = [
+        (PacketType::Connack, None),
+        (PacketType::Publish, Some(1)),
+        (PacketType::Publish, Some(1)),
+        (PacketType::Publish, Some(2)),
+        (PacketType::Puback, Some(1)),
+        (PacketType::Puback, Some(2)),
+        (PacketType::Pingreq, None),
+    ];
+    for (packet, (typ, pid)) in packets.iter().zip(sorted.iter()) {
+        assert_eq!((*typ, *pid), (packet.get_type(), packet.summary().pid));
+    }
+    assert_eq!(publish("a", 1), packets[1]);
+    assert_eq!(publish("b", 1), packets[2]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_owned_packet() {
+    let buf = std::vec![0b00110010, 11, 0, 3, b'a', b'/', b'b', 0, 10, b'h', b'e', b'l', b'l'];
+    let (owned, encoded) = {
+        let packet = decode_slice(&buf).unwrap().unwrap();
+        let owned = packet.to_owned_packet().unwrap();
+        assert_eq!(packet, owned.as_packet());
+        (owned, buf.clone())
+    };
+    drop(buf);
+    match &owned {
+        OwnedPacket::Publish(p) => {
+            assert_eq!("a/b", p.topic_name);
+            assert_eq!(b"hell", &p.payload[..]);
+            assert_eq!(QosPid::from_u8u16(1, 10), p.qospid);
+        }
+        other => panic!("Not a publish: {:?}", other),
+    }
+    let mut slice = [0u8; 16];
+    let len = encode_slice(&owned.as_packet(), &mut slice).unwrap();
+    assert_eq!(&encoded[..], &slice[..len]);
+
+    // MQTT 5 connect with will and properties
+    let props = [Property::SessionExpiryInterval(60), Property::UserProperty("k", "v")];
+    let will_props = [Property::WillDelayInterval(5)];
+    let packet: Packet = Connect {
+        protocol: Protocol::MQTT5,
+        keep_alive: 10,
+        client_id: "test",
+        clean_session: true,
+        last_will: Some(LastWill {
+            topic: "/a",
+            message: b"offline",
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            properties: Some(Properties::new(&will_props)),
+        }),
+        username: Some("rust"),
+        password: Some(b"mq"),
+        properties: Some(Properties::new(&props)),
+    }
+    .into();
+    let owned = packet.to_owned_packet().unwrap();
+    assert_eq!(packet, owned.as_packet());
+    let owned_connect = match &owned {
+        OwnedPacket::Connect(c) => c,
+        other => panic!("Not a connect: {:?}", other),
+    };
+    assert_eq!(Some("rust".into()), owned_connect.username);
+    let connect = Connect::from(owned_connect);
+    let mut props = connect.properties.unwrap().iter();
+    assert_eq!(Some(Property::SessionExpiryInterval(60)), props.next());
+    assert_eq!(Some(Property::UserProperty("k", "v")), props.next());
+
+    // Invalid properties can't be copied
+    let props = [Property::SubscriptionIdentifier(0)];
+    let packet: Packet = Subscribe {
+        pid: Pid::new(),
+        topics: LimitedVec::new(),
+        properties: Some(Properties::new(&props)),
+    }
+    .into();
+    assert_eq!(Err(Error::InvalidProperty(0x0B)), packet.to_owned_packet());
+}
+
+/// An empty username or password is not the same as none, on the wire and through serde.
+#[cfg(all(feature = "std", feature = "derive"))]
+#[test]
+fn test_owned_connect_serde() {
+    let will = LastWill::new("/a", b"").unwrap();
+    let props = [Property::SessionExpiryInterval(60)];
+    for (last_will, username, password, properties) in [
+        (None, None, None, None),
+        (None, Some(""), None, None),
+        (None, Some("rust"), Some(&b""[..]), None),
+        (Some(will), Some(""), Some(&b"mq"[..]), Some(Properties::new(&props))),
+        (None, None, None, Some(Properties::default())),
+    ]
+    .iter()
+    {
+        let protocol = match properties {
+            Some(_) => Protocol::MQTT5,
+            None => Protocol::MQTT311,
+        };
+        let packet: Packet = Connect {
+            protocol,
+            keep_alive: 10,
+            client_id: "test",
+            clean_session: true,
+            last_will: last_will.clone(),
+            username: *username,
+            password: *password,
+            properties: *properties,
+        }
+        .into();
+        let owned = match packet.to_owned_packet() {
+            Ok(OwnedPacket::Connect(c)) => c,
+            other => panic!("Not a connect: {:?}", other),
+        };
+        let json = serde_json::to_string(&owned).unwrap();
+        let back: OwnedConnect = serde_json::from_str(&json).unwrap();
+        assert_eq!(owned, back, "{}", json);
+        assert_eq!(packet, Packet::Connect((&back).into()));
+
+        // The wire format agrees
+        let mut slice = [0u8; 64];
+        let len = encode_slice(&packet, &mut slice).unwrap();
+        match decode_slice_with_protocol(&slice[..len], protocol) {
+            Ok(Some((_, Packet::Connect(c)))) => {
+                assert_eq!((*username, *password), (c.username, c.password))
+            }
+            other => panic!("Failed decode: {:?}", other),
+        }
+    }
+}
+
+#[cfg(all(feature = "std", feature = "derive"))]
+#[test]
+fn test_packet_serde() {
+    let data: &[u8] = &[
+        0b00110010, 24, // QoS 1
+        0, 3, b'a', b'/', b'b', // topic
+        0, 10, // pid
+        11, // properties length
+        0x08, 0, 3, b'r', b'/', b'1', // response topic
+        0x09, 0, 2, 0xCA, 0xFE, // correlation data
+        b'h', b'e', b'l', b'l', b'o', // payload
+    ];
+    let packet = decode_slice_with_protocol(data, Protocol::MQTT5).unwrap().unwrap().1;
+    let json = serde_json::to_string(&packet).unwrap();
+    let back: OwnedPacket = serde_json::from_str(&json).unwrap();
+    assert_eq!(packet, back.as_packet(), "{}", json);
+
+    // Properties built from a list serialize like decoded ones
+    let props = [Property::ResponseTopic("r/1"), Property::CorrelationData(&[0xCA, 0xFE])];
+    let built: Packet = Publish {
+        dup: false,
+        qospid: QosPid::from_u8u16(1, 10),
+        retain: false,
+        topic_name: "a/b",
+        payload: b"hello",
+        properties: Some(Properties::new(&props)),
+    }
+    .into();
+    assert_eq!(json, serde_json::to_string(&built).unwrap());
+
+    // Packets without bytes can borrow their strings from JSON
+    let packet: Packet = Connect::builder("client").keep_alive(10).build().unwrap().into();
+    let json = serde_json::to_string(&packet).unwrap();
+    assert_eq!(Ok(packet), serde_json::from_str::<Packet>(&json).map_err(|e| e.to_string()));
+}
+
+/// Fails to compile if a public packet type doesn't implement `defmt::Format`.
+#[cfg(feature = "defmt")]
+#[test]
+fn test_defmt_format() {
+    fn assert_format<T: defmt::Format>() {}
+    assert_format::<Packet>();
+    assert_format::<PacketType>();
+    assert_format::<Connect>();
+    assert_format::<LastWill>();
+    assert_format::<Protocol>();
+    assert_format::<Connack>();
+    assert_format::<ConnectReturnCode>();
+    assert_format::<Publish>();
+    assert_format::<Ack>();
+    assert_format::<Subscribe>();
+    assert_format::<SubscribeRef>();
+    assert_format::<SubscribeTopic>();
+    assert_format::<RetainHandling>();
+    assert_format::<Suback>();
+    assert_format::<SubscribeReturnCodes>();
+    assert_format::<Unsubscribe>();
+    assert_format::<Disconnect>();
+    assert_format::<Auth>();
+    assert_format::<Properties>();
+    assert_format::<Property>();
+    assert_format::<Pid>();
+    assert_format::<QoS>();
+    assert_format::<QosPid>();
+    assert_format::<ReasonCode>();
+    assert_format::<Error>();
+    assert_format::<DecodeLimits>();
+    assert_format::<Progress>();
+}
+
+/// Packets are moved around by value and servers keep many of them in flight, so a variant that
+/// grows a large inline field (like a property array) makes every `Packet` bigger. If this fails,
+/// box the new field rather than raising the bound. The no_std layout stores topics inline and is
+/// much bigger by design, so it isn't checked here.
+///
+/// On 64-bit targets, `Connect` is the largest variant at 256 bytes, which is also the size of
+/// `Packet`. The other variants are bounded just above their own size (120 bytes for `Publish`,
+/// 112 for `Subscribe`, and 88 for the others), because they could otherwise grow up to
+/// `Connect` without changing the size of `Packet`.
+#[cfg(all(feature = "std", target_pointer_width = "64"))]
+#[test]
+fn test_packet_size() {
+    use core::mem::size_of;
+    assert!(size_of::<Packet>() <= 256);
+    assert!(size_of::<Connect>() <= 256);
+    assert!(size_of::<Publish>() <= 128);
+    assert!(size_of::<Subscribe>() <= 120);
+    assert!(size_of::<Connack>() <= 96);
+    assert!(size_of::<Ack>() <= 96);
+    assert!(size_of::<Disconnect>() <= 96);
+    assert!(size_of::<Auth>() <= 96);
+}
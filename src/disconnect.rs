@@ -0,0 +1,121 @@
+#[cfg(feature = "defmt")]
+use defmt::Format;
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
+use crate::{decoder::*, encoder::*, properties::*, *};
+
+/// Disconnect packet ([MQTT 3.14]).
+///
+/// MQTT 3.1.1 disconnects are empty, and can be created with `Disconnect::default()`. MQTT 5 adds
+/// a reason code and properties, and lets the server send a disconnect too.
+///
+/// ```
+/// # use mqttrs::*;
+/// let pkt = Packet::Disconnect(Disconnect::default());
+/// ```
+///
+/// [MQTT 3.14]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718090
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct Disconnect<'a> {
+    /// MQTT 5 reason code. Must be `ReasonCode::Success` for MQTT 3.1.1.
+    pub reason_code: ReasonCode,
+    /// MQTT 5 properties.
+    ///
+    /// With `None` and `ReasonCode::Success`, the packet is encoded in the MQTT 3.1.1 format.
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    pub properties: Option<Properties<'a>>,
+}
+
+/// Reason codes allowed in Disconnect packets.
+const DISCONNECT_REASON_CODES: &[u8] = &[
+    0x00, 0x04, 0x80, 0x81, 0x82, 0x83, 0x87, 0x89, 0x8B, 0x8D, 0x8E, 0x8F, 0x90, 0x93, 0x94, 0x95,
+    0x96, 0x97, 0x98, 0x99, 0x9A, 0x9B, 0x9C, 0x9D, 0x9E, 0x9F, 0xA0, 0xA1, 0xA2,
+];
+
+impl<'a> Default for Disconnect<'a> {
+    fn default() -> Self {
+        Disconnect {
+            reason_code: ReasonCode::Success,
+            properties: None,
+        }
+    }
+}
+
+impl<'a> Disconnect<'a> {
+    /// Return the MQTT 5 `ServerReference` property, if any.
+    pub fn server_reference(&self) -> Option<&'a str> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::ServerReference(s) => Some(s),
+            _ => None,
+        })
+    }
+
+    /// Return the server that the client should connect to instead, if the server redirected it.
+    ///
+    /// This is the `ServerReference` property, when the reason code is
+    /// `ReasonCode::UseAnotherServer` or `ReasonCode::ServerMoved`.
+    pub fn redirect_target(&self) -> Option<&'a str> {
+        match self.reason_code {
+            ReasonCode::UseAnotherServer | ReasonCode::ServerMoved => self.server_reference(),
+            _ => None,
+        }
+    }
+
+    fn check_reason_code(&self) -> Result<(), Error> {
+        let code = self.reason_code.to_u8();
+        if DISCONNECT_REASON_CODES.contains(&code) {
+            Ok(())
+        } else {
+            Err(Error::InvalidReasonCode(code))
+        }
+    }
+
+    pub(crate) fn from_buffer(
+        remaining_len: usize,
+        buf: &'a [u8],
+        offset: &mut usize,
+        protocol: Protocol,
+    ) -> Result<Self, Error> {
+        let end = *offset + remaining_len;
+        let mut disconnect = Disconnect::default();
+        if protocol == Protocol::MQTT5 && remaining_len > 0 {
+            disconnect.reason_code = ReasonCode::from_u8(read_u8(buf, offset)?)?;
+            disconnect.check_reason_code()?;
+            if remaining_len > 1 {
+                disconnect.properties = Some(Properties::from_buffer(
+                    &buf[..end],
+                    offset,
+                    &DISCONNECT_PROPERTIES,
+                )?);
+            }
+        }
+        Ok(disconnect)
+    }
+
+    /// Length of the packet after the fixed header: reason code (0/1) + properties.
+    pub(crate) fn remaining_len(&self) -> usize {
+        match (self.reason_code, self.properties) {
+            (ReasonCode::Success, None) => 0,
+            (_, None) => 1,
+            (_, Some(p)) => 1 + p.len_with_prefix(),
+        }
+    }
+
+    pub(crate) fn to_buffer(self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        self.check_reason_code()?;
+        let length = self.remaining_len();
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, 0b11100000)?;
+        let write_len = write_length(buf, offset, length)? + 1;
+
+        if length > 0 {
+            write_u8(buf, offset, self.reason_code.to_u8())?;
+        }
+        if let Some(properties) = self.properties {
+            properties.to_buffer(buf, offset, &DISCONNECT_PROPERTIES)?;
+        }
+        Ok(write_len)
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{Error, Packet};
+use crate::{Error, Packet, PacketType};
+#[cfg(feature = "std")]
+use bytes::{Bytes, BytesMut};
 
 /// Encode a [Packet] enum into a [BufMut] buffer.
 ///
@@ -12,6 +14,7 @@ use crate::{Error, Packet};
 ///    retain: false,
 ///    topic_name: "test",
 ///    payload: b"hello",
+///    properties: None,
 /// }.into();
 ///
 /// // Allocate buffer (should be appropriately-sized or able to grow as needed).
@@ -39,42 +42,10 @@ pub fn encode_slice(packet: &Packet, buf: &mut [u8]) -> Result<usize, Error> {
         Packet::Connect(connect) => connect.to_buffer(buf, &mut offset),
         Packet::Connack(connack) => connack.to_buffer(buf, &mut offset),
         Packet::Publish(publish) => publish.to_buffer(buf, &mut offset),
-        Packet::Puback(pid) => {
-            check_remaining(buf, &mut offset, 4)?;
-            let header: u8 = 0b01000000;
-            let length: u8 = 2;
-            write_u8(buf, &mut offset, header)?;
-            write_u8(buf, &mut offset, length)?;
-            pid.to_buffer(buf, &mut offset)?;
-            Ok(4)
-        }
-        Packet::Pubrec(pid) => {
-            check_remaining(buf, &mut offset, 4)?;
-            let header: u8 = 0b01010000;
-            let length: u8 = 2;
-            write_u8(buf, &mut offset, header)?;
-            write_u8(buf, &mut offset, length)?;
-            pid.to_buffer(buf, &mut offset)?;
-            Ok(4)
-        }
-        Packet::Pubrel(pid) => {
-            check_remaining(buf, &mut offset, 4)?;
-            let header: u8 = 0b01100010;
-            let length: u8 = 2;
-            write_u8(buf, &mut offset, header)?;
-            write_u8(buf, &mut offset, length)?;
-            pid.to_buffer(buf, &mut offset)?;
-            Ok(4)
-        }
-        Packet::Pubcomp(pid) => {
-            check_remaining(buf, &mut offset, 4)?;
-            let header: u8 = 0b01110000;
-            let length: u8 = 2;
-            write_u8(buf, &mut offset, header)?;
-            write_u8(buf, &mut offset, length)?;
-            pid.to_buffer(buf, &mut offset)?;
-            Ok(4)
-        }
+        Packet::Puback(ack) => ack.to_buffer(PacketType::Puback, buf, &mut offset),
+        Packet::Pubrec(ack) => ack.to_buffer(PacketType::Pubrec, buf, &mut offset),
+        Packet::Pubrel(ack) => ack.to_buffer(PacketType::Pubrel, buf, &mut offset),
+        Packet::Pubcomp(ack) => ack.to_buffer(PacketType::Pubcomp, buf, &mut offset),
         Packet::Subscribe(subscribe) => subscribe.to_buffer(buf, &mut offset),
         Packet::Suback(suback) => suback.to_buffer(buf, &mut offset),
         Packet::Unsubscribe(unsub) => unsub.to_buffer(buf, &mut offset),
@@ -103,13 +74,107 @@ pub fn encode_slice(packet: &Packet, buf: &mut [u8]) -> Result<usize, Error> {
             write_u8(buf, &mut offset, length)?;
             Ok(2)
         }
-        Packet::Disconnect => {
-            check_remaining(buf, &mut offset, 2)?;
-            let header: u8 = 0b11100000;
-            let length: u8 = 0;
-            write_u8(buf, &mut offset, header)?;
-            write_u8(buf, &mut offset, length)?;
-            Ok(2)
+        Packet::Disconnect(disconnect) => disconnect.to_buffer(buf, &mut offset),
+        Packet::Auth(auth) => auth.to_buffer(buf, &mut offset),
+    }
+}
+
+/// Encode a [Packet] enum like [encode_slice()], and also return the unused end of `buf`, where
+/// the next packet can be encoded.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut buf = [0u8; 8];
+/// let (ping_len, rest) = encode_slice_into(&Packet::Pingreq, &mut buf).unwrap();
+/// let (pong_len, rest) = encode_slice_into(&Packet::Pingresp, rest).unwrap();
+/// assert_eq!((ping_len, pong_len, rest.len()), (2, 2, 4));
+/// assert_eq!(&buf[..4], &[0b11000000, 0, 0b11010000, 0]);
+/// ```
+///
+/// [Packet]: ../enum.Packet.html
+/// [encode_slice()]: fn.encode_slice.html
+pub fn encode_slice_into<'b>(
+    packet: &Packet,
+    buf: &'b mut [u8],
+) -> Result<(usize, &'b mut [u8]), Error> {
+    let len = encode_slice(packet, buf)?;
+    Ok((len, &mut buf[len..]))
+}
+
+/// Encode a [Packet] enum into a newly allocated [Bytes] buffer.
+///
+/// `Bytes` are cheap to clone, so this is convenient to send the same packet to many clients.
+///
+/// ```
+/// # use mqttrs::*;
+/// let bytes = encode_to_bytes(&Packet::Pingreq).unwrap();
+/// assert_eq!(&bytes[..], &[0b11000000, 0]);
+/// ```
+///
+/// [Packet]: ../enum.Packet.html
+/// [Bytes]: https://docs.rs/bytes/1.0.0/bytes/struct.Bytes.html
+#[cfg(feature = "std")]
+pub fn encode_to_bytes(packet: &Packet) -> Result<Bytes, Error> {
+    encode_and_split(packet, &mut BytesMut::new())
+}
+
+/// Encode a [Packet] enum at the end of a [BytesMut] buffer, and split the encoded bytes off as
+/// [Bytes].
+///
+/// This is meant for per-connection write queues: encode into a shared outbound buffer, and
+/// enqueue the returned packet. Data already in `buf` is left as it is. On error, nothing is
+/// added to `buf`.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use bytes::BytesMut;
+/// let mut buf = BytesMut::new();
+/// let ping = encode_and_split(&Packet::Pingreq, &mut buf).unwrap();
+/// assert_eq!(&ping[..], &[0b11000000, 0]);
+/// assert!(buf.is_empty());
+/// ```
+///
+/// [Packet]: ../enum.Packet.html
+/// [BytesMut]: https://docs.rs/bytes/1.0.0/bytes/struct.BytesMut.html
+/// [Bytes]: https://docs.rs/bytes/1.0.0/bytes/struct.Bytes.html
+#[cfg(feature = "std")]
+pub fn encode_and_split(packet: &Packet, buf: &mut BytesMut) -> Result<Bytes, Error> {
+    let start = buf.len();
+    encode(packet, buf)?;
+    Ok(buf.split_off(start).freeze())
+}
+
+/// Encode a [Packet] enum at the end of a [BytesMut] buffer, and return the number of bytes
+/// written.
+///
+/// The buffer grows by the packet's [encoded_size()], so there is no buffer size to guess and no
+/// `Error::WriteZero`. Data already in `buf` is kept, so several packets can be batched. On error,
+/// `buf` is left as it was.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use bytes::BytesMut;
+/// let mut buf = BytesMut::new();
+/// assert_eq!(Ok(2), encode(&Packet::Pingreq, &mut buf));
+/// assert_eq!(Ok(2), encode(&Packet::Pingresp, &mut buf));
+/// assert_eq!(&buf[..], &[0b11000000, 0, 0b11010000, 0]);
+/// ```
+///
+/// [Packet]: ../enum.Packet.html
+/// [BytesMut]: https://docs.rs/bytes/1.0.0/bytes/struct.BytesMut.html
+/// [encoded_size()]: enum.Packet.html#method.encoded_size
+#[cfg(feature = "std")]
+pub fn encode(packet: &Packet, buf: &mut BytesMut) -> Result<usize, Error> {
+    let start = buf.len();
+    buf.resize(start + packet.encoded_size(), 0);
+    match encode_slice(packet, &mut buf[start..]) {
+        Ok(len) => {
+            buf.truncate(start + len);
+            Ok(len)
+        }
+        Err(e) => {
+            buf.truncate(start);
+            Err(e)
         }
     }
 }
@@ -126,35 +191,80 @@ pub(crate) fn check_remaining(buf: &mut [u8], offset: &mut usize, len: usize) ->
 
 /// http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718023
 pub(crate) fn write_length(buf: &mut [u8], offset: &mut usize, len: usize) -> Result<usize, Error> {
-    let write_len = match len {
-        0..=127 => {
-            check_remaining(buf, offset, len + 1)?;
-            len + 1
-        }
-        128..=16383 => {
-            check_remaining(buf, offset, len + 2)?;
-            len + 2
-        }
-        16384..=2097151 => {
-            check_remaining(buf, offset, len + 3)?;
-            len + 3
-        }
-        2097152..=268435455 => {
-            check_remaining(buf, offset, len + 4)?;
-            len + 4
+    write_length_padded(buf, offset, len, 1)
+}
+
+/// Write a packet's remaining length at `offset`, on at least `min_bytes` bytes, and return the
+/// length of the rest of the packet including the remaining length itself.
+///
+/// The spec allows non-minimal encodings, like `0x80, 0x00` for 0, which are useful to test a
+/// peer's decoder. Returns `Error::InvalidLength` if the length needs more than 4 bytes, and
+/// `Error::WriteZero` if `buf` has no room for the whole packet after `offset`.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut buf = [0b11000000, 0, 0];
+/// let mut offset = 1;
+/// assert_eq!(Ok(2), write_length_padded(&mut buf, &mut offset, 0, 2));
+/// assert_eq!(buf, [0b11000000, 0x80, 0]);
+/// assert_eq!(Ok(Some(Packet::Pingreq)), decode_slice(&buf));
+/// ```
+pub fn write_length_padded(
+    buf: &mut [u8],
+    offset: &mut usize,
+    len: usize,
+    min_bytes: usize,
+) -> Result<usize, Error> {
+    let len_bytes = variable_int_len(len).max(min_bytes);
+    if len > 268435455 || len_bytes > 4 {
+        return Err(Error::InvalidLength);
+    }
+    check_remaining(buf, offset, len_bytes + len)?;
+    for i in 0..len_bytes {
+        let mut byte = ((len >> (7 * i)) & 0x7F) as u8;
+        if i + 1 < len_bytes {
+            // Continuation bit
+            byte |= 0x80;
         }
-        _ => return Err(Error::InvalidLength),
-    };
+        write_u8(buf, offset, byte)?;
+    }
+    Ok(len_bytes + len)
+}
+
+/// Number of bytes needed to encode `value` as a variable byte integer.
+pub(crate) fn variable_int_len(value: usize) -> usize {
+    match value {
+        0..=127 => 1,
+        128..=16383 => 2,
+        16384..=2097151 => 3,
+        _ => 4,
+    }
+}
+
+/// Write an MQTT 5 [Variable Byte Integer]. Unlike [`write_length()`], this only checks that the
+/// integer itself fits in the buffer.
+///
+/// [Variable Byte Integer]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901011
+pub(crate) fn write_variable_int(
+    buf: &mut [u8],
+    offset: &mut usize,
+    value: usize,
+) -> Result<usize, Error> {
+    if value > 268435455 {
+        return Err(Error::InvalidLength);
+    }
+    let write_len = variable_int_len(value);
+    check_remaining(buf, offset, write_len)?;
     let mut done = false;
-    let mut x = len;
+    let mut x = value;
     while !done {
         let mut byte = (x % 128) as u8;
-        x = x / 128;
+        x /= 128;
         if x > 0 {
-            byte = byte | 128;
+            byte |= 128;
         }
         write_u8(buf, offset, byte)?;
-        done = x <= 0;
+        done = x == 0;
     }
     Ok(write_len)
 }
@@ -170,6 +280,11 @@ pub(crate) fn write_u16(buf: &mut [u8], offset: &mut usize, val: u16) -> Result<
     write_u8(buf, offset, (val & 0xFF) as u8)
 }
 
+pub(crate) fn write_u32(buf: &mut [u8], offset: &mut usize, val: u32) -> Result<(), Error> {
+    write_u16(buf, offset, (val >> 16) as u16)?;
+    write_u16(buf, offset, (val & 0xFFFF) as u16)
+}
+
 pub(crate) fn write_bytes(buf: &mut [u8], offset: &mut usize, bytes: &[u8]) -> Result<(), Error> {
     write_u16(buf, offset, bytes.len() as u16)?;
 
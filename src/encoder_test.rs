@@ -49,12 +49,131 @@ fn test_connect() {
         last_will: None,
         username: None,
         password: None,
+        properties: None,
     }
     .into();
     // assert_decode!(Packet::Connect(_), &packet);
     assert_decode_slice!(Packet::Connect(_), &packet, 18);
 }
 
+#[test]
+fn test_connect_mqtt31() {
+    let packet = Connect {
+        protocol: Protocol::MQTT31,
+        keep_alive: 120,
+        client_id: "imvj",
+        clean_session: true,
+        last_will: None,
+        username: None,
+        password: None,
+        properties: None,
+    }
+    .into();
+    let mut slice = [0u8; 32];
+    let len = encode_slice(&packet, &mut slice).unwrap();
+    assert_eq!(&slice[2..9], &[0, 4, b'M', b'Q', b'T', b'T', 3]);
+    assert_eq!(Ok(Some(packet)), decode_slice(&slice[..len]));
+}
+
+#[test]
+fn test_connect_client_id_validation() {
+    let connect = |protocol, client_id, clean_session| {
+        Packet::Connect(Connect {
+            protocol,
+            keep_alive: 120,
+            client_id,
+            clean_session,
+            last_will: None,
+            username: None,
+            password: None,
+            properties: None,
+        })
+    };
+    let mut slice = [0u8; 64];
+    // An empty client id requires a clean session before MQTT 5
+    assert_eq!(
+        Err(Error::InvalidClientId),
+        encode_slice(&connect(Protocol::MQTT311, "", false), &mut slice)
+    );
+    assert_eq!(Ok(14), encode_slice(&connect(Protocol::MQTT311, "", true), &mut slice));
+    assert_eq!(Ok(15), encode_slice(&connect(Protocol::MQTT5, "", false), &mut slice));
+
+    // The client id length must fit in 2 bytes
+    #[cfg(feature = "std")]
+    {
+        let long = "a".repeat(70000);
+        let mut buf = vec![0u8; 70100];
+        assert_eq!(
+            Err(Error::InvalidClientId),
+            encode_slice(&connect(Protocol::MQTT311, &long, true), &mut buf)
+        );
+        let max = "a".repeat(65535);
+        assert_eq!(Ok(65551), encode_slice(&connect(Protocol::MQTT311, &max, true), &mut buf));
+    }
+}
+
+#[test]
+fn test_connect_builder() {
+    let will = LastWill::new("a/b", b"bye").unwrap().qos(QoS::AtLeastOnce);
+    let built = Connect::builder("imvj")
+        .keep_alive(120)
+        .clean_session(false)
+        .will(will.clone())
+        .credentials("user", b"pass")
+        .build()
+        .unwrap();
+    let manual = Connect {
+        protocol: Protocol::MQTT311,
+        keep_alive: 120,
+        client_id: "imvj",
+        clean_session: false,
+        last_will: Some(will),
+        username: Some("user"),
+        password: Some(b"pass"),
+        properties: None,
+    };
+    assert_eq!(manual, built);
+
+    let mut built_buf = [0u8; 64];
+    let mut manual_buf = [0u8; 64];
+    let len = encode_slice(&built.into(), &mut built_buf).unwrap();
+    assert_eq!(Ok(len), encode_slice(&manual.into(), &mut manual_buf));
+    assert_eq!(&built_buf[..len], &manual_buf[..len]);
+}
+
+#[test]
+fn test_encode_slice_into() {
+    let connect = Connect::builder("imvj").keep_alive(120).build().unwrap().into();
+    let topics: LimitedVec<SubscribeTopic> = [SubscribeTopic {
+        topic_path: LimitedString::from_str("a/b").unwrap(),
+        qos: QoS::AtLeastOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_handling: RetainHandling::SendAtSubscribe,
+    }]
+    .iter()
+    .cloned()
+    .collect();
+    let subscribe = Subscribe::new(Pid::try_from(10).unwrap(), topics).into();
+
+    let mut buf = [0u8; 64];
+    let (connect_len, rest) = encode_slice_into(&connect, &mut buf).unwrap();
+    let (subscribe_len, rest) = encode_slice_into(&subscribe, rest).unwrap();
+    assert_eq!(rest.len(), 64 - connect_len - subscribe_len);
+    // Errors are the same as encode_slice()
+    assert_eq!(
+        encode_slice(&connect, &mut rest[..10]),
+        encode_slice_into(&connect, &mut rest[..10]).map(|(len, _)| len)
+    );
+
+    let end = connect_len + subscribe_len;
+    assert_eq!(Ok(Some((connect_len, connect))), decode_slice_with_len(&buf[..end]));
+    assert_eq!(
+        Ok(Some((subscribe_len, subscribe))),
+        decode_slice_with_len(&buf[connect_len..end])
+    );
+}
+
 #[test]
 fn test_write_zero() {
     let packet = Connect {
@@ -65,6 +184,7 @@ fn test_write_zero() {
         last_will: None,
         username: None,
         password: None,
+        properties: None,
     }
     .into();
 
@@ -79,11 +199,211 @@ fn test_write_zero() {
     assert_eq!(written, 18);
 }
 
+#[test]
+fn test_last_will_new() {
+    let will = LastWill::new("a/b", b"bye").unwrap();
+    assert_eq!(
+        will,
+        LastWill {
+            topic: "a/b",
+            message: b"bye",
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: None,
+        }
+    );
+    let will = will.qos(QoS::ExactlyOnce).retain(true);
+    assert_eq!(will.qos, QoS::ExactlyOnce);
+    assert!(will.retain);
+
+    for topic in ["", "a/+", "a/#", "#", "a\0b"].iter() {
+        assert_eq!(Err(Error::InvalidTopic), LastWill::new(topic, b"bye"), "{:?}", topic);
+    }
+}
+
+/// Encode and decode a `Connect` with all optional fields set, to check the field ordering.
+#[test]
+fn test_connect_all_fields() {
+    let will_properties = [Property::WillDelayInterval(10)];
+    let properties = [Property::SessionExpiryInterval(60)];
+    for &protocol in [Protocol::MQTT311, Protocol::MQTT5].iter() {
+        let v5 = protocol == Protocol::MQTT5;
+        let packet = Connect {
+            protocol,
+            keep_alive: 300,
+            client_id: "client",
+            clean_session: false,
+            last_will: Some(LastWill {
+                topic: "will/topic",
+                message: b"gone",
+                qos: QoS::ExactlyOnce,
+                retain: true,
+                properties: if v5 { Some(Properties::new(&will_properties)) } else { None },
+            }),
+            username: Some("user"),
+            password: Some(b"p\x00ss"),
+            properties: if v5 { Some(Properties::new(&properties)) } else { None },
+        };
+        let mut slice = [0u8; 128];
+        let written = encode_slice(&packet.clone().into(), &mut slice).unwrap();
+        // Flags: username, password, will retain, will QoS 2, will
+        assert_eq!(slice[9], 0b11110100);
+        match decode_slice(&slice[..written]) {
+            Ok(Some(Packet::Connect(c))) => assert_eq!(c, packet),
+            other => panic!("Failed decode: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_connect_mqtt5_properties() {
+    let properties = [
+        Property::SessionExpiryInterval(3600),
+        Property::ReceiveMaximum(20),
+        Property::MaximumPacketSize(4096),
+        Property::TopicAliasMaximum(10),
+    ];
+    let mut packet = Connect {
+        protocol: Protocol::MQTT5,
+        keep_alive: 60,
+        client_id: "c",
+        clean_session: true,
+        last_will: None,
+        username: None,
+        password: None,
+        properties: Some(Properties::new(&properties)),
+    };
+    let mut slice = [0u8; 64];
+    let written = encode_slice(&packet.clone().into(), &mut slice).unwrap();
+    assert_eq!(
+        &slice[..written],
+        &[
+            0b00010000, 30, 0, 4, b'M', b'Q', b'T', b'T', 5, 0b00000010, 0, 60, // header
+            16, // properties length
+            0x11, 0, 0, 0x0e, 0x10, // session expiry
+            0x21, 0, 20, // receive maximum
+            0x27, 0, 0, 0x10, 0, // maximum packet size
+            0x22, 0, 10, // topic alias maximum
+            0, 1, b'c', // client id
+        ]
+    );
+    match decode_slice(&slice[..written]) {
+        Ok(Some(Packet::Connect(c))) => assert_eq!(c, packet),
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Without properties, MQTT 5 writes an empty property block and MQTT 3.1.1 writes none.
+    packet.properties = None;
+    let written = encode_slice(&packet.clone().into(), &mut slice).unwrap();
+    assert_eq!(
+        &slice[..written],
+        &[0b00010000, 14, 0, 4, b'M', b'Q', b'T', b'T', 5, 0b00000010, 0, 60, 0, 0, 1, b'c']
+    );
+    match decode_slice(&slice[..written]) {
+        Ok(Some(Packet::Connect(c))) => {
+            assert!(c.properties.unwrap().is_empty());
+            assert_eq!(Connect { properties: None, ..c }, packet);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    packet.protocol = Protocol::MQTT311;
+    let written = encode_slice(&packet.clone().into(), &mut slice).unwrap();
+    assert_eq!(
+        &slice[..written],
+        &[0b00010000, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b00000010, 0, 60, 0, 1, b'c']
+    );
+    match decode_slice(&slice[..written]) {
+        Ok(Some(Packet::Connect(c))) => assert_eq!(c, packet),
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
+#[test]
+fn test_connect_client_id_len() {
+    let id23 = "abcdefghijklmnopqrstuvw";
+    let id24 = "abcdefghijklmnopqrstuvwx";
+    assert_eq!(Ok(()), Connect::builder(id23).build().unwrap().validate_client_id_len(23));
+    assert_eq!(
+        Err(Error::InvalidClientId),
+        Connect::builder(id24).build().unwrap().validate_client_id_len(23)
+    );
+
+    // Bytes, not chars
+    let id = "\u{e9}\u{e9}\u{e9}";
+    assert_eq!(
+        Err(Error::InvalidClientId),
+        Connect::builder(id).build().unwrap().validate_client_id_len(5)
+    );
+
+    let packet = Connect::builder(id23).max_client_id_len(23).build().unwrap().into();
+    assert_decode_slice!(Packet::Connect(_), &packet, 37);
+    assert_eq!(
+        Err(Error::InvalidClientId),
+        Connect::builder(id24).max_client_id_len(23).build()
+    );
+    // No limit by default
+    assert!(Connect::builder(id24).build().is_ok());
+}
+
+#[test]
+fn test_connect_credentials() {
+    let connect = Connect::builder("c").build().unwrap();
+    assert_eq!(Ok(Credentials::Anonymous), connect.credentials());
+    let user = Connect {
+        username: Some("u"),
+        ..connect.clone()
+    };
+    assert_eq!(Ok(Credentials::Username("u")), user.credentials());
+    let user_pass = Connect {
+        username: Some("u"),
+        password: Some(b"p"),
+        ..connect.clone()
+    };
+    assert_eq!(Ok(Credentials::UsernamePassword("u", b"p")), user_pass.credentials());
+
+    let mut slice = [0u8; 64];
+    for c in [connect.clone(), user, user_pass].iter() {
+        let len = encode_slice(&c.clone().into(), &mut slice).unwrap();
+        match decode_slice(&slice[..len]) {
+            Ok(Some(Packet::Connect(d))) => assert_eq!(d.credentials(), c.credentials()),
+            other => panic!("Failed decode: {:?}", other),
+        }
+    }
+
+    // Password without username is only allowed in MQTT 5
+    let pass = Connect {
+        password: Some(b"p"),
+        ..connect
+    };
+    assert_eq!(Err(Error::InvalidCredentials), pass.credentials());
+    let pass5 = Connect {
+        protocol: Protocol::MQTT5,
+        ..pass.clone()
+    };
+    assert_eq!(Ok(Credentials::Password(b"p")), pass5.credentials());
+
+    // Encoding and decoding reject a password without username before MQTT 5
+    assert_eq!(Err(Error::InvalidCredentials), encode_slice(&pass.into(), &mut slice));
+    let data: &[u8] = &[
+        0b00010000, 16, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b01000010, 0, 10, //
+        0, 1, b'c', 0, 1, b'p',
+    ];
+    assert_eq!(Err(Error::InvalidCredentials), decode_slice(data));
+    let len = encode_slice(&pass5.into(), &mut slice).unwrap();
+    match decode_slice_with_protocol(&slice[..len], Protocol::MQTT5) {
+        Ok(Some((_, Packet::Connect(d)))) => {
+            assert_eq!(Ok(Credentials::Password(b"p")), d.credentials())
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
 #[test]
 fn test_connack() {
     let packet = Connack {
         session_present: true,
         code: ConnectReturnCode::Accepted,
+        properties: None,
     }
     .into();
     // assert_decode!(Packet::Connack(_), &packet);
@@ -98,6 +418,7 @@ fn test_publish() {
         retain: true,
         topic_name: "asdf",
         payload: &['h' as u8, 'e' as u8, 'l' as u8, 'l' as u8, 'o' as u8],
+        properties: None,
     }
     .into();
     // assert_decode!(Packet::Publish(_), &packet);
@@ -106,28 +427,28 @@ fn test_publish() {
 
 #[test]
 fn test_puback() {
-    let packet = Packet::Puback(Pid::try_from(19).unwrap());
+    let packet = Packet::Puback(Pid::try_from(19).unwrap().into());
     // assert_decode!(Packet::Puback(_), &packet);
     assert_decode_slice!(Packet::Puback(_), &packet, 4);
 }
 
 #[test]
 fn test_pubrec() {
-    let packet = Packet::Pubrec(Pid::try_from(19).unwrap());
+    let packet = Packet::Pubrec(Pid::try_from(19).unwrap().into());
     // assert_decode!(Packet::Pubrec(_), &packet);
     assert_decode_slice!(Packet::Pubrec(_), &packet, 4);
 }
 
 #[test]
 fn test_pubrel() {
-    let packet = Packet::Pubrel(Pid::try_from(19).unwrap());
+    let packet = Packet::Pubrel(Pid::try_from(19).unwrap().into());
     // assert_decode!(Packet::Pubrel(_), &packet);
     assert_decode_slice!(Packet::Pubrel(_), &packet, 4);
 }
 
 #[test]
 fn test_pubcomp() {
-    let packet = Packet::Pubcomp(Pid::try_from(19).unwrap());
+    let packet = Packet::Pubcomp(Pid::try_from(19).unwrap().into());
     // assert_decode!(Packet::Pubcomp(_), &packet);
     assert_decode_slice!(Packet::Pubcomp(_), &packet, 4);
 }
@@ -137,6 +458,9 @@ fn test_subscribe() {
     let stopic = SubscribeTopic {
         topic_path: LimitedString::from("a/b"),
         qos: QoS::ExactlyOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_handling: RetainHandling::SendAtSubscribe,
     };
     let topics: LimitedVec<SubscribeTopic> = [stopic].iter().cloned().collect();
     let packet = Subscribe::new(Pid::try_from(345).unwrap(), topics).into();
@@ -150,6 +474,9 @@ fn test_subscribe() {
     let stopic = SubscribeTopic {
         topic_path: LimitedString::from_str("a/b").unwrap(),
         qos: QoS::ExactlyOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_handling: RetainHandling::SendAtSubscribe,
     };
     let topics: LimitedVec<SubscribeTopic> = [stopic].iter().cloned().collect();
     let packet = Subscribe::new(Pid::try_from(345).unwrap(), topics).into();
@@ -180,6 +507,39 @@ fn test_unsubscribe() {
     assert_decode_slice!(Packet::Unsubscribe(_), &packet, 9);
 }
 
+#[test]
+fn test_subscribe_dedup_highest_qos() {
+    let topic = |path, qos| SubscribeTopic {
+        topic_path: LimitedString::from_str(path).unwrap(),
+        qos,
+        no_local: false,
+        retain_as_published: false,
+        retain_handling: RetainHandling::SendAtSubscribe,
+    };
+    let topics = [
+        topic("a/b", QoS::AtMostOnce),
+        topic("c", QoS::AtLeastOnce),
+        topic("a/b", QoS::ExactlyOnce),
+        topic("c", QoS::AtMostOnce),
+    ];
+    let mut packet = Subscribe::new(Pid::new(), topics.iter().cloned().collect());
+    packet.dedup_highest_qos();
+    assert_eq!(
+        &[topic("a/b", QoS::ExactlyOnce), topic("c", QoS::AtLeastOnce)],
+        &packet.topics[..]
+    );
+}
+
+#[test]
+fn test_subscribe_no_topics() {
+    let mut slice = [0u8; 16];
+    let pid = Pid::try_from(10).unwrap();
+    let packet = Subscribe::new(pid, LimitedVec::new()).into();
+    assert_eq!(Err(Error::InvalidLength), encode_slice(&packet, &mut slice));
+    let packet = Unsubscribe::new(pid, LimitedVec::new()).into();
+    assert_eq!(Err(Error::InvalidLength), encode_slice(&packet, &mut slice));
+}
+
 #[test]
 fn test_unsuback() {
     let packet = Packet::Unsuback(Pid::try_from(19).unwrap());
@@ -202,5 +562,1126 @@ fn test_ping_resp() {
 #[test]
 fn test_disconnect() {
     // assert_decode!(Packet::Disconnect, &Packet::Disconnect);
-    assert_decode_slice!(Packet::Disconnect, &Packet::Disconnect, 2);
+    assert_decode_slice!(Packet::Disconnect(_), &Disconnect::default().into(), 2);
+}
+
+#[test]
+fn test_disconnect_reason_code_roundtrip() {
+    let mut slice = [0u8; 8];
+    for &(data, code) in [
+        (&[0b11100000, 0][..], ReasonCode::Success),
+        (&[0b11100000, 1, 0x04][..], ReasonCode::DisconnectWithWillMessage),
+    ]
+    .iter()
+    {
+        let packet = match decode_slice_with_protocol(data, Protocol::MQTT5) {
+            Ok(Some((_, Packet::Disconnect(d)))) => d,
+            other => panic!("Failed decode: {:?}", other),
+        };
+        let expected = Disconnect {
+            reason_code: code,
+            properties: None,
+        };
+        assert_eq!(expected, packet);
+        let len = encode_slice(&packet.into(), &mut slice).unwrap();
+        assert_eq!(data, &slice[..len]);
+    }
+}
+
+#[test]
+fn test_disconnect_server_reference() {
+    let properties = [Property::ServerReference("other:1883"), Property::ReasonString("bye")];
+    let packet = Disconnect {
+        reason_code: ReasonCode::UseAnotherServer,
+        properties: Some(Properties::new(&properties)),
+    };
+    assert_eq!(Some("other:1883"), packet.redirect_target());
+    let mut slice = [0u8; 64];
+    let len = encode_slice(&packet.into(), &mut slice).unwrap();
+    assert_eq!(&slice[..4], &[0b11100000, 21, 0x9C, 19]);
+    match decode_slice_with_protocol(&slice[..len], Protocol::MQTT5) {
+        Ok(Some((23, Packet::Disconnect(d)))) => {
+            assert_eq!(d, packet);
+            assert_eq!(Some("other:1883"), d.redirect_target());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Only a redirect with the matching reason code
+    let packet = Disconnect {
+        reason_code: ReasonCode::ServerShuttingDown,
+        ..packet
+    };
+    assert_eq!(Some("other:1883"), packet.server_reference());
+    assert_eq!(None, packet.redirect_target());
+    assert_eq!(None, Disconnect::default().redirect_target());
+
+    // Not a Disconnect reason code
+    let packet = Disconnect {
+        reason_code: ReasonCode::GrantedQoS1,
+        properties: None,
+    };
+    assert_eq!(Err(Error::InvalidReasonCode(0x01)), encode_slice(&packet.into(), &mut slice));
+}
+
+#[test]
+fn test_auth_constructors() {
+    let mut slice = [0u8; 64];
+    for &(auth, code, data) in [
+        (Auth::reauthenticate("SCRAM-SHA-1", None), 0x19, None),
+        (Auth::reauthenticate("SCRAM-SHA-1", Some(b"first")), 0x19, Some(&b"first"[..])),
+        (Auth::continue_auth("SCRAM-SHA-1", b"next"), 0x18, Some(&b"next"[..])),
+    ]
+    .iter()
+    {
+        assert_eq!(auth.method(), Some("SCRAM-SHA-1"));
+        assert_eq!(auth.data(), data);
+        let len = encode_slice(&auth.into(), &mut slice).unwrap();
+        assert_eq!(slice[0], 0b11110000);
+        assert_eq!(slice[2], code);
+        match decode_slice_with_protocol(&slice[..len], Protocol::MQTT5) {
+            Ok(Some((l, Packet::Auth(a)))) => {
+                assert_eq!(l, len);
+                assert_eq!(a, auth);
+                assert_eq!(a.method(), Some("SCRAM-SHA-1"));
+                assert_eq!(a.data(), data);
+            }
+            other => panic!("Failed decode: {:?}", other),
+        }
+        // Auth doesn't exist in MQTT 3.1.1
+        assert_eq!(Err(Error::InvalidHeader), decode_slice(&slice[..len]));
+    }
+}
+
+#[test]
+fn test_auth_binary_data() {
+    // Authentication data isn't UTF-8
+    let data = [0x00, 0xFF, 0xC3, 0x80, 0x7F];
+    let auth = Auth::continue_auth("SCRAM", &data);
+    let mut slice = [0u8; 64];
+    let len = encode_slice(&auth.into(), &mut slice).unwrap();
+    #[rustfmt::skip]
+    let expected: &[u8] = &[
+        0b11110000, 18,
+        0x18, // continue authentication
+        16, // properties length
+        0x15, 0, 5, b'S', b'C', b'R', b'A', b'M', // authentication method
+        0x16, 0, 5, 0x00, 0xFF, 0xC3, 0x80, 0x7F, // authentication data
+    ];
+    assert_eq!(&slice[..len], expected);
+    match decode_slice_with_protocol(expected, Protocol::MQTT5) {
+        Ok(Some((20, Packet::Auth(a)))) => {
+            assert_eq!(a.method(), Some("SCRAM"));
+            assert_eq!(a.data(), Some(&data[..]));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // A remaining length of 0 means success without properties, both ways
+    let len = encode_slice(&Auth::default().into(), &mut slice).unwrap();
+    assert_eq!(&slice[..len], &[0b11110000, 0]);
+    match decode_slice_with_protocol(&[0b11110000, 0], Protocol::MQTT5) {
+        Ok(Some((2, Packet::Auth(a)))) => {
+            assert_eq!(a.reason_code, ReasonCode::Success);
+            assert_eq!(a.properties, None);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
+#[test]
+fn test_connack_server_reference() {
+    let properties = [Property::ServerReference("other:1883")];
+    let packet = Connack {
+        session_present: false,
+        code: ConnectReturnCode::ServerUnavailable,
+        properties: Some(Properties::new(&properties)),
+    };
+    let mut slice = [0u8; 64];
+    let len = encode_slice(&packet.into(), &mut slice).unwrap();
+    match decode_slice_with_protocol(&slice[..len], Protocol::MQTT5) {
+        Ok(Some((_, Packet::Connack(c)))) => {
+            assert_eq!(Some("other:1883"), c.server_reference())
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
+#[test]
+fn test_subscribe_mqtt5() {
+    let topics: LimitedVec<SubscribeTopic> = [SubscribeTopic {
+        topic_path: LimitedString::from_str("a/b").unwrap(),
+        qos: QoS::AtLeastOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_handling: RetainHandling::SendAtSubscribe,
+    }]
+    .iter()
+    .cloned()
+    .collect();
+    let packet = Subscribe {
+        pid: Pid::try_from(10).unwrap(),
+        topics: topics.clone(),
+        properties: Some(Properties::new(&[
+            Property::SubscriptionIdentifier(268435455),
+            Property::UserProperty("k", "v"),
+        ])),
+    }
+    .into();
+
+    let mut slice = [0u8; 64];
+    let written = encode_slice(&packet, &mut slice).unwrap();
+    assert_eq!(written, 23);
+    assert_eq!(
+        Ok(Some((written, packet.clone()))),
+        decode_slice_with_protocol(&slice[..written], Protocol::MQTT5)
+    );
+    match packet {
+        Packet::Subscribe(s) => assert_eq!(s.subscription_id(), Some(268435455)),
+        _ => unreachable!(),
+    }
+
+    // Subscription identifiers must be in 1..=268435455
+    for id in [0, 268435456].iter() {
+        let properties = [Property::SubscriptionIdentifier(*id)];
+        let packet = Subscribe {
+            pid: Pid::try_from(10).unwrap(),
+            topics: topics.clone(),
+            properties: Some(Properties::new(&properties)),
+        }
+        .into();
+        assert_eq!(
+            Err(Error::InvalidProperty(0x0B)),
+            encode_slice(&packet, &mut slice)
+        );
+    }
+}
+
+#[test]
+fn test_subscribe_options_mqtt5() {
+    let mut slice = [0u8; 64];
+    for &qos in [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce].iter() {
+        for &no_local in [false, true].iter() {
+            for &retain_as_published in [false, true].iter() {
+                for &(retain_handling, bits) in [
+                    (RetainHandling::SendAtSubscribe, 0),
+                    (RetainHandling::SendAtNewSubscribe, 1),
+                    (RetainHandling::DoNotSend, 2),
+                ]
+                .iter()
+                {
+                    let topic = SubscribeTopic {
+                        topic_path: LimitedString::from_str("a/b").unwrap(),
+                        qos,
+                        no_local,
+                        retain_as_published,
+                        retain_handling,
+                    };
+                    let packet = Subscribe {
+                        pid: Pid::try_from(10).unwrap(),
+                        topics: [topic].iter().cloned().collect(),
+                        properties: Some(Properties::new(&[])),
+                    }
+                    .into();
+                    let written = encode_slice(&packet, &mut slice).unwrap();
+                    let options = qos.to_u8()
+                        | (no_local as u8) << 2
+                        | (retain_as_published as u8) << 3
+                        | bits << 4;
+                    assert_eq!(slice[written - 1], options);
+                    assert_eq!(
+                        Ok(Some((written, packet))),
+                        decode_slice_with_protocol(&slice[..written], Protocol::MQTT5)
+                    );
+                }
+            }
+        }
+    }
+
+    // MQTT 3.1.1 only has the QoS bits
+    let topic = SubscribeTopic {
+        topic_path: LimitedString::from_str("a/b").unwrap(),
+        qos: QoS::AtLeastOnce,
+        no_local: true,
+        retain_as_published: false,
+        retain_handling: RetainHandling::SendAtSubscribe,
+    };
+    let packet = Subscribe {
+        pid: Pid::try_from(10).unwrap(),
+        topics: [topic].iter().cloned().collect(),
+        properties: None,
+    }
+    .into();
+    assert_eq!(
+        Err(Error::InvalidSubscribeOptions(0b101)),
+        encode_slice(&packet, &mut slice)
+    );
+    let data = [0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'b', 0b101];
+    assert_eq!(Err(Error::InvalidQos(0b101)), decode_slice(&data));
+
+    // Retain handling 3 and the reserved bits are invalid
+    for &options in [0b110001, 0b1000001, 0b10000001].iter() {
+        let data = [0b10000010, 9, 0, 10, 0, 0, 3, b'a', b'/', b'b', options];
+        assert_eq!(
+            Err(Error::InvalidSubscribeOptions(options)),
+            decode_slice_with_protocol(&data, Protocol::MQTT5)
+        );
+    }
+}
+
+/// Remaining length encoding, using only stack buffers so that it runs the same on no_std.
+#[test]
+fn test_write_length() {
+    // (len, encoded bytes), at the boundaries of each band
+    let cases: [(usize, &[u8]); 8] = [
+        (0, &[0x00]),
+        (127, &[0x7F]),
+        (128, &[0x80, 0x01]),
+        (16383, &[0xFF, 0x7F]),
+        (16384, &[0x80, 0x80, 0x01]),
+        (2097151, &[0xFF, 0xFF, 0x7F]),
+        (2097152, &[0x80, 0x80, 0x80, 0x01]),
+        (268435455, &[0xFF, 0xFF, 0xFF, 0x7F]),
+    ];
+    for (len, bytes) in cases.iter() {
+        let mut buf = [0u8; 4];
+        let mut offset = 0;
+        assert_eq!(Ok(bytes.len()), encoder::write_variable_int(&mut buf, &mut offset, *len));
+        assert_eq!(offset, bytes.len());
+        assert_eq!(&buf[..offset], *bytes, "len {}", len);
+
+        // write_length() also checks that there is room for the rest of the packet.
+        let mut offset = 0;
+        assert_eq!(Err(Error::WriteZero), encoder::write_length(&mut buf, &mut offset, (*len).max(4)));
+    }
+
+    let mut buf = [0u8; 16387];
+    for (len, bytes) in cases[..5].iter() {
+        let mut offset = 0;
+        assert_eq!(Ok(len + bytes.len()), encoder::write_length(&mut buf, &mut offset, *len));
+        assert_eq!(&buf[..offset], *bytes, "len {}", len);
+        let mut offset = 0;
+        assert_eq!(
+            Err(Error::WriteZero),
+            encoder::write_length(&mut buf[..len + bytes.len() - 1], &mut offset, *len)
+        );
+    }
+
+    let mut offset = 0;
+    assert_eq!(Err(Error::InvalidLength), encoder::write_length(&mut buf, &mut offset, 268435456));
+    assert_eq!(Err(Error::WriteZero), encoder::write_variable_int(&mut buf[..3], &mut offset, 2097152));
+    assert_eq!(offset, 0);
+}
+
+#[test]
+fn test_write_length_padded() {
+    let mut buf = [0u8; 16384 + 5];
+    // (len, min_bytes, expected length bytes)
+    for &(len, min_bytes, len_bytes) in [
+        (0, 1, 1),
+        (0, 2, 2),
+        (0, 4, 4),
+        (5, 3, 3),
+        (127, 2, 2),
+        (128, 1, 2),
+        (128, 3, 3),
+        (16383, 4, 4),
+        (16384, 2, 3),
+    ]
+    .iter()
+    {
+        buf[0] = 0b00110000;
+        let mut offset = 1;
+        assert_eq!(
+            Ok(len_bytes + len),
+            write_length_padded(&mut buf, &mut offset, len, min_bytes)
+        );
+        assert_eq!(offset, 1 + len_bytes);
+        let mut offset = 0;
+        match decoder::read_header(&buf[..1 + len_bytes + len], &mut offset) {
+            Ok(Some((header, l))) => assert_eq!((header.typ, l), (PacketType::Publish, len)),
+            other => panic!("Failed decode: {:?}", other),
+        }
+        assert_eq!(offset, 1 + len_bytes);
+    }
+
+    let mut offset = 0;
+    assert_eq!(Err(Error::InvalidLength), write_length_padded(&mut buf, &mut offset, 0, 5));
+    assert_eq!(Err(Error::WriteZero), write_length_padded(&mut buf[..3], &mut offset, 1, 3));
+    assert_eq!(offset, 0);
+}
+
+#[test]
+fn test_publish_mqtt5() {
+    let packet = Publish {
+        dup: false,
+        qospid: QosPid::from_u8u16(2, 10),
+        retain: false,
+        topic_name: "req",
+        payload: b"ping",
+        properties: Some(Properties::new(&[
+            Property::ResponseTopic("resp/1"),
+            Property::CorrelationData(b"42"),
+            Property::SubscriptionIdentifier(1),
+            Property::SubscriptionIdentifier(2),
+        ])),
+    }
+    .into();
+    let mut slice = [0u8; 64];
+    let written = encode_slice(&packet, &mut slice).unwrap();
+    assert_eq!(written, 32);
+    assert_eq!(
+        Ok(Some((written, packet))),
+        decode_slice_with_protocol(&slice[..written], Protocol::MQTT5)
+    );
+
+    // Properties that are valid elsewhere but not in a PUBLISH
+    let packet = Publish {
+        dup: false,
+        qospid: QosPid::AtMostOnce,
+        retain: false,
+        topic_name: "req",
+        payload: b"ping",
+        properties: Some(Properties::new(&[Property::ServerKeepAlive(10)])),
+    }
+    .into();
+    assert_eq!(Err(Error::InvalidProperty(0x13)), encode_slice(&packet, &mut slice));
+}
+
+#[test]
+fn test_connack_mqtt5() {
+    // A broker that only supports QoS 1 and no retained messages
+    let packet = Connack {
+        session_present: false,
+        code: ConnectReturnCode::Accepted,
+        properties: Some(Properties::new(&[
+            Property::MaximumQoS(QoS::AtLeastOnce),
+            Property::RetainAvailable(false),
+        ])),
+    }
+    .into();
+    let mut slice = [0u8; 16];
+    let written = encode_slice(&packet, &mut slice).unwrap();
+    assert_eq!(written, 9);
+    assert_eq!(&slice[..written], &[0x20, 7, 0, 0, 4, 0x24, 1, 0x25, 0]);
+    match decode_slice_with_protocol(&slice[..written], Protocol::MQTT5) {
+        Ok(Some((9, Packet::Connack(c)))) => {
+            assert_eq!(Packet::Connack(c), packet);
+            assert_eq!(c.max_qos(), QoS::AtLeastOnce);
+            assert!(!c.retain_available());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // MaximumQoS can't be 2, the property is omitted instead.
+    let packet = Connack {
+        session_present: false,
+        code: ConnectReturnCode::Accepted,
+        properties: Some(Properties::new(&[Property::MaximumQoS(QoS::ExactlyOnce)])),
+    }
+    .into();
+    assert_eq!(Err(Error::InvalidProperty(0x24)), encode_slice(&packet, &mut slice));
+}
+
+#[test]
+fn test_connack_reason_code() {
+    let mut slice = [0u8; 16];
+    let codes = [
+        (ConnectReturnCode::Accepted, 0, 0x00),
+        (ConnectReturnCode::RefusedProtocolVersion, 1, 0x84),
+        (ConnectReturnCode::BadUsernamePassword, 4, 0x86),
+        (ConnectReturnCode::NotAuthorized, 5, 0x87),
+    ];
+    for &(code, v3, v5) in codes.iter() {
+        let mut packet = Connack {
+            session_present: false,
+            code,
+            properties: None,
+        };
+        assert_eq!(Ok(4), encode_slice(&packet.into(), &mut slice));
+        assert_eq!(slice[3], v3);
+        packet.properties = Some(Properties::default());
+        assert_eq!(Ok(5), encode_slice(&packet.into(), &mut slice));
+        assert_eq!(slice[3], v5);
+    }
+
+    let packet = Connack {
+        session_present: true,
+        code: ConnectReturnCode::Reason(ReasonCode::QuotaExceeded),
+        properties: Some(Properties::default()),
+    };
+    let written = encode_slice(&packet.into(), &mut slice).unwrap();
+    assert_eq!(&slice[..written], &[0x20, 3, 1, 0x97, 0]);
+    match decode_slice_with_protocol(&slice[..written], Protocol::MQTT5) {
+        Ok(Some((5, p))) => assert_eq!(p, packet),
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Reason codes can't be encoded in 3.1.1
+    let packet = Connack {
+        properties: None,
+        ..packet
+    };
+    assert_eq!(
+        Err(Error::InvalidConnectReturnCode(0x97)),
+        encode_slice(&packet.into(), &mut slice)
+    );
+
+    // Nor reason codes of other packets
+    let packet = Connack {
+        code: ConnectReturnCode::Reason(ReasonCode::DisconnectWithWillMessage),
+        properties: Some(Properties::default()),
+        ..packet
+    };
+    assert_eq!(
+        Err(Error::InvalidConnectReturnCode(0x04)),
+        encode_slice(&packet.into(), &mut slice)
+    );
+}
+
+#[test]
+fn test_publish_setters() {
+    let mut slice = [0u8; 64];
+    let original: Packet = Publish {
+        dup: false,
+        qospid: QosPid::from_u8u16(2, 10),
+        retain: true,
+        topic_name: "a/b",
+        payload: b"hi",
+        properties: None,
+    }
+    .into();
+    let len = encode_slice(&original, &mut slice).unwrap();
+
+    // Decode, tweak, and re-encode
+    let publish = match decode_slice(&slice[..len]) {
+        Ok(Some(Packet::Publish(p))) => p,
+        other => panic!("Failed decode: {:?}", other),
+    };
+    let publish = publish
+        .set_dup(true)
+        .and_then(|p| p.set_retain(false))
+        .and_then(|p| p.set_qospid(QosPid::from_u8u16(1, 11)))
+        .unwrap();
+    let mut out = [0u8; 64];
+    let len = encode_slice(&publish.into(), &mut out).unwrap();
+    match decode_slice(&out[..len]) {
+        Ok(Some(Packet::Publish(p))) => {
+            assert!(p.dup);
+            assert!(!p.retain);
+            assert_eq!(p.qospid, QosPid::from_u8u16(1, 11));
+            assert_eq!(p.topic_name, "a/b");
+            assert_eq!(p.payload, b"hi");
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // No dup flag on QoS 0, in either order
+    let qos0 = Publish {
+        dup: false,
+        qospid: QosPid::AtMostOnce,
+        retain: false,
+        topic_name: "a/b",
+        payload: b"hi",
+        properties: None,
+    };
+    assert_eq!(Err(Error::InvalidHeader), qos0.clone().set_dup(true));
+    assert_eq!(Ok(qos0.clone()), qos0.clone().set_dup(false));
+    let dup = qos0.set_qospid(QosPid::from_u8u16(1, 1)).unwrap().set_dup(true).unwrap();
+    assert_eq!(Err(Error::InvalidHeader), dup.set_qospid(QosPid::AtMostOnce));
+}
+
+#[test]
+fn test_publish_content_type() {
+    let publish = Publish {
+        dup: false,
+        qospid: QosPid::AtMostOnce,
+        retain: false,
+        topic_name: "a/b",
+        payload: b"{}",
+        properties: None,
+    };
+    assert_eq!(None, publish.content_type());
+    let properties = [Property::ContentType("application/json")];
+    let publish = publish.set_properties(Properties::new(&properties)).unwrap();
+
+    let mut slice = [0u8; 64];
+    let len = encode_slice(&publish.clone().into(), &mut slice).unwrap();
+    match decode_slice_with_protocol(&slice[..len], Protocol::MQTT5) {
+        Ok(Some((_, Packet::Publish(p)))) => {
+            assert_eq!(Some("application/json"), p.content_type());
+            assert_eq!(publish, p);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    let invalid = [Property::SessionExpiryInterval(10)];
+    assert_eq!(
+        Err(Error::InvalidProperty(0x11)),
+        publish.set_properties(Properties::new(&invalid))
+    );
+}
+
+#[test]
+fn test_publish_with_retain() {
+    let stored = Publish {
+        dup: false,
+        qospid: QosPid::from_u8u16(1, 10),
+        retain: true,
+        topic_name: "a/b",
+        payload: b"hi",
+        properties: None,
+    };
+    let mut slice = [0u8; 64];
+    for &retain in [false, true].iter() {
+        let packet = stored.with_retain(retain);
+        assert_eq!(packet.retain, retain);
+        assert_eq!(packet.with_retain(true), stored);
+        let len = encode_slice(&packet.clone().into(), &mut slice).unwrap();
+        assert_eq!(slice[0], 0b00110010 | retain as u8);
+        assert_eq!(Ok(Some(packet.into())), decode_slice(&slice[..len]));
+    }
+}
+
+#[test]
+fn test_publish_downgrade_qos() {
+    let pid = Pid::try_from(10).unwrap();
+    let publish = Publish {
+        dup: true,
+        qospid: QosPid::ExactlyOnce(pid),
+        retain: false,
+        topic_name: "a/b",
+        payload: b"hi",
+        properties: None,
+    };
+    let qos0 = publish.downgrade_qos(QoS::AtMostOnce);
+    assert_eq!(qos0.qospid, QosPid::AtMostOnce);
+    assert_eq!(qos0.qospid.pid(), None);
+    assert!(!qos0.dup);
+    let mut slice = [0u8; 64];
+    let len = encode_slice(&qos0.clone().into(), &mut slice).unwrap();
+    assert_eq!(Ok(Some(qos0.clone().into())), decode_slice(&slice[..len]));
+
+    let qos1 = publish.downgrade_qos(QoS::AtLeastOnce);
+    assert_eq!(qos1.qospid, QosPid::AtLeastOnce(pid));
+    assert!(qos1.dup);
+    assert_eq!(publish.downgrade_qos(QoS::ExactlyOnce), publish);
+    // Never upgrades
+    assert_eq!(qos0.downgrade_qos(QoS::ExactlyOnce), qos0);
+    assert_eq!(qos1.downgrade_qos(QoS::ExactlyOnce), qos1);
+}
+
+#[test]
+fn test_publish_wildcard_topic() {
+    let mut slice = [0u8; 64];
+    let topics = [("a/+/b", false), ("a/#", false), ("a\0b", false), ("a/b/c", true)];
+    for &(topic, valid) in topics.iter() {
+        let packet = Publish {
+            dup: false,
+            qospid: QosPid::AtMostOnce,
+            retain: false,
+            topic_name: topic,
+            payload: b"hi",
+            properties: None,
+        };
+        let res = encode_slice(&packet.clone().into(), &mut slice);
+        if valid {
+            assert_eq!(Ok(Some(packet.into())), decode_slice(&slice[..res.unwrap()]));
+        } else {
+            assert_eq!(Err(Error::InvalidTopic), res, "{:?}", topic);
+            let qospid = QosPid::AtMostOnce;
+            assert_eq!(
+                Err(Error::InvalidTopic),
+                Publish::encode_streaming_header(topic, qospid, false, false, 2, &mut slice)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_publish_topic_alias() {
+    let properties = [Property::TopicAlias(5)];
+    let packet = Publish {
+        dup: false,
+        qospid: QosPid::from_u8u16(1, 10),
+        retain: false,
+        topic_name: "",
+        payload: b"hi",
+        properties: Some(Properties::new(&properties)),
+    };
+    let mut slice = [0u8; 32];
+    let len = encode_slice(&packet.clone().into(), &mut slice).unwrap();
+    assert_eq!(&slice[..len], &[0b00110010, 10, 0, 0, 0, 10, 3, 0x23, 0, 5, b'h', b'i']);
+    match decode_slice_with_protocol(&slice[..len], Protocol::MQTT5) {
+        Ok(Some((12, Packet::Publish(p)))) => {
+            assert_eq!(p.topic_alias(), Some(5));
+            assert_eq!(p, packet);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_publish_resolve_alias() {
+    let mut aliases = TopicAliasMap::default();
+    aliases.insert(3, "a/b");
+
+    // Empty topic, alias 3, content type "t"
+    let data = [0b00110000, 12, 0, 0, 7, 0x23, 0, 3, 0x03, 0, 1, b't', b'h', b'i'];
+    let publish = match decode_slice_with_protocol(&data, Protocol::MQTT5) {
+        Ok(Some((_, Packet::Publish(p)))) => p,
+        other => panic!("Failed decode: {:?}", other),
+    };
+    assert_eq!(Some(3), publish.topic_alias());
+    let resolved = publish.resolve_alias(&aliases).unwrap();
+    assert_eq!("a/b", resolved.topic_name);
+    assert_eq!(None, resolved.topic_alias());
+    assert_eq!(Some("t"), resolved.content_type());
+    let mut slice = [0u8; 64];
+    let len = encode_slice(&resolved.into(), &mut slice).unwrap();
+    assert_eq!(
+        &slice[..len],
+        &[0b00110000, 12, 0, 3, b'a', b'/', b'b', 4, 0x03, 0, 1, b't', b'h', b'i']
+    );
+
+    // Unknown alias
+    let mut slice = data;
+    slice[7] = 4;
+    match decode_slice_with_protocol(&slice, Protocol::MQTT5) {
+        Ok(Some((_, Packet::Publish(p)))) => {
+            assert_eq!(Err(Error::ProtocolViolation), p.resolve_alias(&aliases))
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // A topic and an alias: keep the topic, drop the alias
+    let properties = [Property::TopicAlias(3)];
+    let publish = Publish {
+        topic_name: "c/d",
+        properties: Some(Properties::new(&properties)),
+        ..publish
+    };
+    let resolved = publish.resolve_alias(&aliases).unwrap();
+    assert_eq!("c/d", resolved.topic_name);
+    assert!(resolved.properties.unwrap().is_empty());
+
+    // No alias
+    let publish = Publish {
+        properties: None,
+        ..publish
+    };
+    assert_eq!(Ok(publish.clone()), publish.resolve_alias(&aliases));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_encode_to_bytes() {
+    // Bigger than the initial buffer
+    let payload = [b'x'; 1000];
+    let packet = Publish {
+        dup: false,
+        qospid: QosPid::from_u8u16(1, 10),
+        retain: false,
+        topic_name: "a/b",
+        payload: &payload,
+        properties: None,
+    }
+    .into();
+    let bytes = encode_to_bytes(&packet).unwrap();
+    assert_eq!(bytes.len(), 1010);
+    assert_eq!(Ok(Some(packet)), decode_slice(&bytes.clone()));
+
+    let bytes = encode_to_bytes(&Packet::Pingresp).unwrap();
+    assert_eq!(Ok(Some(Packet::Pingresp)), decode_slice(&bytes));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_encode_and_split() {
+    let payload = [b'x'; 100];
+    let publish = Publish {
+        dup: false,
+        qospid: QosPid::from_u8u16(1, 10),
+        retain: false,
+        topic_name: "a/b",
+        payload: &payload,
+        properties: None,
+    }
+    .into();
+    let ack = Packet::Puback(Pid::try_from(10).unwrap().into());
+
+    let mut buf = BytesMut::new();
+    let first = encode_and_split(&publish, &mut buf).unwrap();
+    let second = encode_and_split(&ack, &mut buf).unwrap();
+    assert!(buf.is_empty());
+    assert_eq!(Ok(Some((first.len(), publish))), decode_slice_with_len(&first));
+    assert_eq!(Ok(Some((second.len(), ack))), decode_slice_with_len(&second));
+
+    // Existing data is kept, and left alone on error
+    buf.extend_from_slice(b"queued");
+    let ping = encode_and_split(&Packet::Pingreq, &mut buf).unwrap();
+    assert_eq!(&ping[..], &[0b11000000, 0]);
+    let empty = Subscribe::new(Pid::new(), LimitedVec::new()).into();
+    assert_eq!(Err(Error::InvalidLength), encode_and_split(&empty, &mut buf));
+    assert_eq!(&buf[..], b"queued");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_encode_bytes_mut() {
+    let payload = [b'x'; 200];
+    let packets = [
+        Packet::Pingreq,
+        Publish {
+            dup: false,
+            qospid: QosPid::from_u8u16(1, 10),
+            retain: false,
+            topic_name: "a/b",
+            payload: &payload,
+            properties: None,
+        }
+        .into(),
+        Packet::Puback(Pid::try_from(10).unwrap().into()),
+    ];
+    let mut buf = BytesMut::new();
+    for packet in packets.iter() {
+        assert_eq!(Ok(packet.encoded_size()), encode(packet, &mut buf));
+    }
+    assert_eq!(buf.len(), 2 + 210 + 4);
+    let mut offset = 0;
+    for packet in packets.iter() {
+        match decode_slice_with_len(&buf[offset..]) {
+            Ok(Some((len, p))) => {
+                assert_eq!(&p, packet);
+                offset += len;
+            }
+            other => panic!("Failed decode: {:?}", other),
+        }
+    }
+    assert_eq!(offset, buf.len());
+
+    // Left alone on error
+    let empty = Subscribe::new(Pid::new(), LimitedVec::new()).into();
+    assert_eq!(Err(Error::InvalidLength), encode(&empty, &mut buf));
+    assert_eq!(offset, buf.len());
+}
+
+#[test]
+fn test_publish_streaming_header() {
+    let payload = [b'x'; 200];
+    for &(qospid, retain, dup) in [
+        (QosPid::AtMostOnce, false, false),
+        (QosPid::from_u8u16(1, 10), true, false),
+        (QosPid::from_u8u16(2, 300), false, true),
+    ]
+    .iter()
+    {
+        let packet = Publish {
+            dup,
+            qospid,
+            retain,
+            topic_name: "a/b",
+            payload: &payload,
+            properties: None,
+        };
+        let mut full = [0u8; 256];
+        let full_len = encode_slice(&packet.into(), &mut full).unwrap();
+
+        // The header buffer doesn't need room for the payload
+        let mut streamed = [0u8; 16];
+        let len = Publish::encode_streaming_header("a/b", qospid, retain, dup, 200, &mut streamed)
+            .unwrap();
+        assert_eq!(full_len, len + payload.len());
+        assert_eq!(&full[..len], &streamed[..len]);
+        assert_eq!(&full[len..full_len], &payload[..]);
+    }
+
+    // Topic and length take 5 bytes, leaving 268435450 for the payload
+    let mut buf = [0u8; 16];
+    let header = |len, buf: &mut [u8]| {
+        Publish::encode_streaming_header("a/b", QosPid::AtMostOnce, false, false, len, buf)
+    };
+    assert_eq!(Ok(10), header(268435450, &mut buf));
+    assert_eq!(Err(Error::InvalidLength), header(268435451, &mut buf));
+    assert_eq!(Err(Error::WriteZero), header(0, &mut buf[..6]));
+}
+
+#[test]
+fn test_pubrec_mqtt5() {
+    let packet = Packet::Pubrec(Ack {
+        pid: Pid::try_from(10).unwrap(),
+        reason_code: ReasonCode::PayloadFormatInvalid,
+        properties: Some(Properties::new(&[
+            Property::ReasonString("not utf8"),
+            Property::UserProperty("k", "v"),
+        ])),
+    });
+    let mut slice = [0u8; 64];
+    let written = encode_slice(&packet, &mut slice).unwrap();
+    assert_eq!(written, 24);
+    assert_eq!(&slice[..5], &[0b01010000, 22, 0, 10, 0x99]);
+    match decode_slice_with_protocol(&slice[..written], Protocol::MQTT5) {
+        Ok(Some((24, Packet::Pubrec(ack)))) => {
+            assert_eq!(Packet::Pubrec(ack), packet);
+            assert_eq!(ack.reason_code, ReasonCode::PayloadFormatInvalid);
+            assert_eq!(ack.reason_string(), Some("not utf8"));
+            assert!(ack.user_properties().eq([("k", "v")].iter().copied()));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Reason code without properties
+    let packet = Packet::Puback(Ack {
+        pid: Pid::try_from(10).unwrap(),
+        reason_code: ReasonCode::NoMatchingSubscribers,
+        properties: None,
+    });
+    let written = encode_slice(&packet, &mut slice).unwrap();
+    assert_eq!(&slice[..written], &[0b01000000, 3, 0, 10, 0x10]);
+    assert_eq!(
+        Ok(Some((written, packet))),
+        decode_slice_with_protocol(&slice[..written], Protocol::MQTT5)
+    );
+
+    // Reason code not allowed in a PUBREL
+    let packet = Packet::Pubrel(Ack {
+        pid: Pid::try_from(10).unwrap(),
+        reason_code: ReasonCode::PayloadFormatInvalid,
+        properties: None,
+    });
+    assert_eq!(Err(Error::InvalidReasonCode(0x99)), encode_slice(&packet, &mut slice));
+}
+
+#[test]
+fn test_maximum_packet_size() {
+    let mut slice = [0u8; 64];
+    let properties = [Property::MaximumPacketSize(1024)];
+    let packet = Connect {
+        protocol: Protocol::MQTT5,
+        properties: Some(Properties::new(&properties)),
+        ..Connect::builder("c").build().unwrap()
+    };
+    let written = encode_slice(&packet.clone().into(), &mut slice).unwrap();
+    match decode_slice(&slice[..written]) {
+        Ok(Some(Packet::Connect(c))) => {
+            assert_eq!(c, packet);
+            assert_eq!(c.maximum_packet_size(), Some(1024));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    let packet = Connack {
+        session_present: false,
+        code: ConnectReturnCode::Accepted,
+        properties: Some(Properties::new(&properties)),
+    };
+    let written = encode_slice(&packet.into(), &mut slice).unwrap();
+    match decode_slice_with_protocol(&slice[..written], Protocol::MQTT5) {
+        Ok(Some((_, Packet::Connack(c)))) => {
+            assert_eq!(c, packet);
+            assert_eq!(c.maximum_packet_size(), Some(1024));
+            assert_eq!(c.effective_max_packet_size(), 1024);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    let packet = Connack {
+        properties: None,
+        ..packet
+    };
+    assert_eq!(packet.maximum_packet_size(), None);
+    assert_eq!(packet.effective_max_packet_size(), 268435460);
+
+    // 0 is invalid, when encoding or decoding
+    let packet = Connack {
+        properties: Some(Properties::new(&[Property::MaximumPacketSize(0)])),
+        ..packet
+    };
+    assert_eq!(Err(Error::InvalidProperty(0x27)), encode_slice(&packet.into(), &mut slice));
+    let data = [0b00100000, 8, 0, 0, 5, 0x27, 0, 0, 0, 0];
+    assert_eq!(
+        Err(Error::InvalidProperty(0x27)),
+        decode_slice_with_protocol(&data, Protocol::MQTT5)
+    );
+}
+
+#[test]
+fn test_request_response_information() {
+    let mut slice = [0u8; 128];
+
+    // The client asks for response information...
+    let packet = Connect {
+        protocol: Protocol::MQTT5,
+        keep_alive: 30,
+        client_id: "c",
+        clean_session: true,
+        last_will: Some(LastWill {
+            topic: "w",
+            message: b"bye",
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: Some(Properties::new(&[Property::WillDelayInterval(10)])),
+        }),
+        username: None,
+        password: None,
+        properties: Some(Properties::new(&[Property::RequestResponseInformation(true)])),
+    }
+    .into();
+    let written = encode_slice(&packet, &mut slice).unwrap();
+    assert_eq!(written, 32);
+    assert_eq!(&slice[2..14], &[0, 4, b'M', b'Q', b'T', b'T', 5, 0b110, 0, 30, 2, 0x19]);
+    match decode_slice(&slice[..written]) {
+        Ok(Some(Packet::Connect(c))) => {
+            assert_eq!(Packet::Connect(c.clone()), packet);
+            assert!(c.request_response_information());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // ...and the server provides it.
+    let packet = Connack {
+        session_present: false,
+        code: ConnectReturnCode::Accepted,
+        properties: Some(Properties::new(&[Property::ResponseInformation("resp/")])),
+    }
+    .into();
+    let written = encode_slice(&packet, &mut slice).unwrap();
+    match decode_slice_with_protocol(&slice[..written], Protocol::MQTT5) {
+        Ok(Some((13, Packet::Connack(c)))) => {
+            assert_eq!(Packet::Connack(c), packet);
+            assert_eq!(c.response_information(), Some("resp/"));
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+
+    // Defaults
+    let connect = Connect::builder("c").build().unwrap();
+    assert!(!connect.request_response_information());
+
+    // MQTT 3.1.1 can't have properties
+    let packet = Connect {
+        protocol: Protocol::MQTT311,
+        properties: Some(Properties::new(&[Property::RequestResponseInformation(true)])),
+        ..connect
+    }
+    .into();
+    assert_eq!(Err(Error::InvalidProperty(0x19)), encode_slice(&packet, &mut slice));
+}
+
+#[test]
+fn test_encoded_size() {
+    let topics: LimitedVec<SubscribeTopic> = [SubscribeTopic {
+        topic_path: LimitedString::from_str("a/b").unwrap(),
+        qos: QoS::AtLeastOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_handling: RetainHandling::SendAtSubscribe,
+    }]
+    .iter()
+    .cloned()
+    .collect();
+    let unsub_topics: LimitedVec<LimitedString> =
+        [LimitedString::from_str("a/b").unwrap()].iter().cloned().collect();
+    let return_codes: LimitedVec<SubscribeReturnCodes> =
+        [SubscribeReturnCodes::Failure].iter().cloned().collect();
+    let props = [Property::ReasonString("why"), Property::UserProperty("k", "v")];
+    let pid = Pid::try_from(10).unwrap();
+    let will = LastWill::new("/a", b"bye").unwrap().qos(QoS::AtLeastOnce);
+    let packets: [Packet; 16] = [
+        Connect::builder("client").build().unwrap().into(),
+        Connect {
+            protocol: Protocol::MQIsdp,
+            ..Connect::builder("client").build().unwrap()
+        }
+        .into(),
+        Connect {
+            protocol: Protocol::MQTT5,
+            keep_alive: 60,
+            client_id: "client",
+            clean_session: false,
+            last_will: Some(will),
+            username: Some("user"),
+            password: Some(b"pass"),
+            properties: Some(Properties::new(&props[1..])),
+        }
+        .into(),
+        Connack {
+            session_present: true,
+            code: ConnectReturnCode::Accepted,
+            properties: Some(Properties::new(&props)),
+        }
+        .into(),
+        Publish {
+            dup: false,
+            qospid: QosPid::ExactlyOnce(pid),
+            retain: true,
+            topic_name: "a/b",
+            payload: b"payload",
+            properties: Some(Properties::default()),
+        }
+        .into(),
+        Packet::Puback(pid.into()),
+        Packet::Pubrec(Ack {
+            pid,
+            reason_code: ReasonCode::NoMatchingSubscribers,
+            properties: None,
+        }),
+        Packet::Pubrel(Ack {
+            pid,
+            reason_code: ReasonCode::Success,
+            properties: Some(Properties::new(&props)),
+        }),
+        Subscribe {
+            pid,
+            topics,
+            properties: Some(Properties::new(&[Property::SubscriptionIdentifier(200)])),
+        }
+        .into(),
+        Suback::new(pid, return_codes).into(),
+        Unsubscribe::new(pid, unsub_topics).into(),
+        Packet::Unsuback(pid),
+        Packet::Pingreq,
+        Packet::Pingresp,
+        Packet::Disconnect(Disconnect {
+            reason_code: ReasonCode::ServerMoved,
+            properties: Some(Properties::new(&props)),
+        }),
+        Auth::continue_auth("SCRAM", b"data").into(),
+    ];
+    for packet in packets.iter() {
+        let mut buf = [0u8; 128];
+        let size = packet.encoded_size();
+        assert_eq!(Ok(size), encode_slice(packet, &mut buf[..size]), "{:?}", packet);
+        assert_eq!(Err(Error::WriteZero), encode_slice(packet, &mut buf[..size - 1]));
+    }
+}
+
+/// Remaining lengths at the boundaries of the variable length field sizes.
+#[cfg(feature = "std")]
+#[test]
+fn test_encoded_size_boundaries() {
+    for &(len, header_len) in [
+        (127, 2),
+        (128, 3),
+        (16383, 3),
+        (16384, 4),
+        (2097151, 4),
+        (2097152, 5),
+    ]
+    .iter()
+    {
+        // Topic "a" takes 3 bytes
+        let payload = std::vec![0u8; len - 3];
+        let packet = Packet::Publish(Publish {
+            dup: false,
+            qospid: QosPid::AtMostOnce,
+            retain: false,
+            topic_name: "a",
+            payload: &payload,
+            properties: None,
+        });
+        assert_eq!(header_len + len, packet.encoded_size());
+        let mut buf = std::vec![0u8; packet.encoded_size()];
+        assert_eq!(Ok(buf.len()), encode_slice(&packet, &mut buf));
+    }
 }
@@ -21,7 +21,8 @@
 //!                                     clean_session: true,
 //!                                     last_will: None,
 //!                                     username: None,
-//!                                     password: None });
+//!                                     password: None,
+//!                                     properties: None });
 //! let len = encode_slice(&pkt, &mut buf).unwrap();
 //! assert_eq!(&buf[14..len], b"doc_client");
 //! let mut encoded = buf.clone();
@@ -49,13 +50,25 @@
 #[cfg(feature = "std")]
 extern crate std;
 
+mod auth;
+#[cfg(feature = "codec")]
+mod codec;
 mod connect;
 mod decoder;
+mod disconnect;
 mod encoder;
+#[cfg(feature = "std")]
+mod owned;
 mod packet;
+mod properties;
 mod publish;
+#[cfg(feature = "std")]
+mod reader;
 mod subscribe;
+mod topic;
 mod utils;
+#[cfg(feature = "std")]
+mod writer;
 
 // Proptest does not currently support borrowed data in strategies:
 // https://github.com/AltSysrq/proptest/issues/9
@@ -68,11 +81,42 @@ mod decoder_test;
 mod encoder_test;
 
 pub use crate::{
-    connect::{Connack, Connect, ConnectReturnCode, LastWill, Protocol},
-    decoder::{clone_packet, decode_slice, decode_slice_with_len},
-    encoder::encode_slice,
-    packet::{Packet, PacketType},
-    publish::Publish,
-    subscribe::{Suback, Subscribe, SubscribeReturnCodes, SubscribeTopic, Unsubscribe},
-    utils::{Error, Pid, QoS, QosPid},
+    auth::Auth,
+    connect::{
+        Connack, Connect, ConnectBuilder, ConnectReturnCode, Credentials, LastWill, Protocol,
+    },
+    decoder::{
+        bytes_needed, clone_packet, clone_packet_with_status, decode_at, decode_connect,
+        decode_connect_lenient, decode_connect_with_allowed, decode_into, decode_slice,
+        decode_slice_filtered, decode_slice_max, decode_slice_resync, decode_slice_strict,
+        decode_slice_with_len, decode_slice_with_limits, decode_slice_with_protocol,
+        decode_subscribe_ref, expect_connect, packet_progress, CloneStatus, DecodeLimits, Decoder,
+        Progress,
+    },
+    disconnect::Disconnect,
+    encoder::{encode_slice, encode_slice_into, write_length_padded},
+    packet::{dispatch, Packet, PacketHandlers, PacketSummary, PacketType},
+    properties::{Properties, PropertiesIter, Property},
+    publish::{Ack, Publish},
+    subscribe::{
+        RetainHandling, Suback, SubackReturnCodesIter, Subscribe, SubscribeRef, SubscribeReturnCodes,
+        SubscribeTopic, SubscribeTopicsIter, Unsubscribe,
+    },
+    topic::{topic_levels, topic_matches},
+    utils::{Error, Pid, QoS, QosPid, ReasonCode},
+};
+#[cfg(feature = "std")]
+pub use crate::{
+    decoder::drain_packets,
+    encoder::{encode, encode_and_split, encode_to_bytes},
+    publish::TopicAliasMap,
+    reader::MqttReader,
+    utils::PidPool,
+    writer::MqttWriter,
+    owned::{
+        OwnedAck, OwnedAuth, OwnedConnack, OwnedConnect, OwnedDisconnect, OwnedLastWill,
+        OwnedPacket, OwnedProperties, OwnedPublish, OwnedSubscribe,
+    },
 };
+#[cfg(feature = "codec")]
+pub use crate::codec::MqttCodec;
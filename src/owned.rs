@@ -0,0 +1,440 @@
+use crate::{subscribe::LimitedVec, *};
+use core::convert::TryFrom;
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
+use std::{string::String, vec::Vec};
+
+/// Owned version of a [Packet], that doesn't borrow from the decoding buffer (std only).
+///
+/// This is useful to keep a packet after the buffer is reused, for example to send it through a
+/// channel. Convert a `Packet` with [Packet::to_owned_packet()], and get a borrowing `Packet`
+/// back with [as_packet()].
+///
+/// ```
+/// # use mqttrs::*;
+/// let owned = {
+///     let buf = [0b00110000, 7, 0, 3, b'a', b'/', b'b', b'h', b'i'];
+///     decode_slice(&buf).unwrap().unwrap().to_owned_packet().unwrap()
+/// };
+/// match owned {
+///     OwnedPacket::Publish(ref p) => assert_eq!(p.topic_name, "a/b"),
+///     _ => panic!(),
+/// }
+/// assert_eq!(PacketType::Publish, owned.as_packet().get_type());
+/// ```
+///
+/// [Packet]: enum.Packet.html
+/// [Packet::to_owned_packet()]: enum.Packet.html#method.to_owned_packet
+/// [as_packet()]: #method.as_packet
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub enum OwnedPacket {
+    Connect(OwnedConnect),
+    Connack(OwnedConnack),
+    Publish(OwnedPublish),
+    Puback(OwnedAck),
+    Pubrec(OwnedAck),
+    Pubrel(OwnedAck),
+    Pubcomp(OwnedAck),
+    Subscribe(OwnedSubscribe),
+    Suback(Suback),
+    Unsubscribe(Unsubscribe),
+    Unsuback(Pid),
+    Pingreq,
+    Pingresp,
+    Disconnect(OwnedDisconnect),
+    Auth(OwnedAuth),
+}
+
+/// Owned version of [Properties], see [OwnedPacket].
+///
+/// [Properties]: struct.Properties.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct OwnedProperties(Vec<u8>);
+
+/// Owned version of [Connect], see [OwnedPacket].
+///
+/// With the `derive` feature, every field is serialized as is: `None` and `Some` of an empty
+/// string or password are different on the wire, and stay different after a serde round trip.
+///
+/// [Connect]: struct.Connect.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct OwnedConnect {
+    pub protocol: Protocol,
+    pub keep_alive: u16,
+    pub client_id: String,
+    pub clean_session: bool,
+    pub last_will: Option<OwnedLastWill>,
+    pub username: Option<String>,
+    pub password: Option<Vec<u8>>,
+    pub properties: Option<OwnedProperties>,
+}
+
+/// Owned version of [LastWill], see [OwnedPacket].
+///
+/// [LastWill]: struct.LastWill.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct OwnedLastWill {
+    pub topic: String,
+    pub message: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+    pub properties: Option<OwnedProperties>,
+}
+
+/// Owned version of [Connack], see [OwnedPacket].
+///
+/// [Connack]: struct.Connack.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct OwnedConnack {
+    pub session_present: bool,
+    pub code: ConnectReturnCode,
+    pub properties: Option<OwnedProperties>,
+}
+
+/// Owned version of [Publish], see [OwnedPacket].
+///
+/// [Publish]: struct.Publish.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct OwnedPublish {
+    pub dup: bool,
+    pub qospid: QosPid,
+    pub retain: bool,
+    pub topic_name: String,
+    pub payload: Vec<u8>,
+    pub properties: Option<OwnedProperties>,
+}
+
+/// Owned version of [Ack], see [OwnedPacket].
+///
+/// [Ack]: struct.Ack.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct OwnedAck {
+    pub pid: Pid,
+    pub reason_code: ReasonCode,
+    pub properties: Option<OwnedProperties>,
+}
+
+/// Owned version of [Subscribe], see [OwnedPacket].
+///
+/// [Subscribe]: struct.Subscribe.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct OwnedSubscribe {
+    pub pid: Pid,
+    pub topics: LimitedVec<SubscribeTopic>,
+    pub properties: Option<OwnedProperties>,
+}
+
+/// Owned version of [Disconnect], see [OwnedPacket].
+///
+/// [Disconnect]: struct.Disconnect.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct OwnedDisconnect {
+    pub reason_code: ReasonCode,
+    pub properties: Option<OwnedProperties>,
+}
+
+/// Owned version of [Auth], see [OwnedPacket].
+///
+/// [Auth]: struct.Auth.html
+/// [OwnedPacket]: enum.OwnedPacket.html
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct OwnedAuth {
+    pub reason_code: ReasonCode,
+    pub properties: Option<OwnedProperties>,
+}
+
+impl OwnedPacket {
+    /// Return a `Packet` borrowing from this one.
+    pub fn as_packet(&self) -> Packet<'_> {
+        self.into()
+    }
+}
+
+impl OwnedProperties {
+    /// Return `Properties` borrowing from these ones.
+    pub fn as_properties(&self) -> Properties<'_> {
+        Properties::encoded(&self.0)
+    }
+}
+
+impl<'a> Packet<'a> {
+    /// Copy this packet into an [OwnedPacket].
+    ///
+    /// Returns `Error::InvalidProperty` for MQTT 5 properties with an invalid value, which can
+    /// only happen with properties built by `Properties::new()`.
+    ///
+    /// [OwnedPacket]: enum.OwnedPacket.html
+    pub fn to_owned_packet(&self) -> Result<OwnedPacket, Error> {
+        OwnedPacket::try_from(self)
+    }
+}
+
+fn own_properties(properties: &Option<Properties>) -> Result<Option<OwnedProperties>, Error> {
+    match properties {
+        Some(p) => Ok(Some(OwnedProperties(p.to_vec()?))),
+        None => Ok(None),
+    }
+}
+
+fn view_properties(properties: &Option<OwnedProperties>) -> Option<Properties<'_>> {
+    properties.as_ref().map(OwnedProperties::as_properties)
+}
+
+impl<'a> TryFrom<&Packet<'a>> for OwnedPacket {
+    type Error = Error;
+
+    fn try_from(packet: &Packet<'a>) -> Result<Self, Error> {
+        Ok(match packet {
+            Packet::Connect(c) => OwnedPacket::Connect(OwnedConnect::try_from(c)?),
+            Packet::Connack(c) => OwnedPacket::Connack(OwnedConnack::try_from(c)?),
+            Packet::Publish(p) => OwnedPacket::Publish(OwnedPublish::try_from(p)?),
+            Packet::Puback(a) => OwnedPacket::Puback(OwnedAck::try_from(a)?),
+            Packet::Pubrec(a) => OwnedPacket::Pubrec(OwnedAck::try_from(a)?),
+            Packet::Pubrel(a) => OwnedPacket::Pubrel(OwnedAck::try_from(a)?),
+            Packet::Pubcomp(a) => OwnedPacket::Pubcomp(OwnedAck::try_from(a)?),
+            Packet::Subscribe(s) => OwnedPacket::Subscribe(OwnedSubscribe::try_from(s)?),
+            Packet::Suback(s) => OwnedPacket::Suback(s.clone()),
+            Packet::Unsubscribe(u) => OwnedPacket::Unsubscribe(u.clone()),
+            Packet::Unsuback(pid) => OwnedPacket::Unsuback(*pid),
+            Packet::Pingreq => OwnedPacket::Pingreq,
+            Packet::Pingresp => OwnedPacket::Pingresp,
+            Packet::Disconnect(d) => OwnedPacket::Disconnect(OwnedDisconnect::try_from(d)?),
+            Packet::Auth(a) => OwnedPacket::Auth(OwnedAuth::try_from(a)?),
+        })
+    }
+}
+
+impl<'a> From<&'a OwnedPacket> for Packet<'a> {
+    fn from(packet: &'a OwnedPacket) -> Self {
+        match packet {
+            OwnedPacket::Connect(c) => Packet::Connect(c.into()),
+            OwnedPacket::Connack(c) => Packet::Connack(c.into()),
+            OwnedPacket::Publish(p) => Packet::Publish(p.into()),
+            OwnedPacket::Puback(a) => Packet::Puback(a.into()),
+            OwnedPacket::Pubrec(a) => Packet::Pubrec(a.into()),
+            OwnedPacket::Pubrel(a) => Packet::Pubrel(a.into()),
+            OwnedPacket::Pubcomp(a) => Packet::Pubcomp(a.into()),
+            OwnedPacket::Subscribe(s) => Packet::Subscribe(s.into()),
+            OwnedPacket::Suback(s) => Packet::Suback(s.clone()),
+            OwnedPacket::Unsubscribe(u) => Packet::Unsubscribe(u.clone()),
+            OwnedPacket::Unsuback(pid) => Packet::Unsuback(*pid),
+            OwnedPacket::Pingreq => Packet::Pingreq,
+            OwnedPacket::Pingresp => Packet::Pingresp,
+            OwnedPacket::Disconnect(d) => Packet::Disconnect(d.into()),
+            OwnedPacket::Auth(a) => Packet::Auth(a.into()),
+        }
+    }
+}
+
+impl<'a> TryFrom<&Connect<'a>> for OwnedConnect {
+    type Error = Error;
+
+    fn try_from(c: &Connect<'a>) -> Result<Self, Error> {
+        Ok(OwnedConnect {
+            protocol: c.protocol,
+            keep_alive: c.keep_alive,
+            client_id: c.client_id.into(),
+            clean_session: c.clean_session,
+            last_will: match &c.last_will {
+                Some(w) => Some(OwnedLastWill::try_from(w)?),
+                None => None,
+            },
+            username: c.username.map(String::from),
+            password: c.password.map(Vec::from),
+            properties: own_properties(&c.properties)?,
+        })
+    }
+}
+
+impl<'a> From<&'a OwnedConnect> for Connect<'a> {
+    fn from(c: &'a OwnedConnect) -> Self {
+        Connect {
+            protocol: c.protocol,
+            keep_alive: c.keep_alive,
+            client_id: &c.client_id,
+            clean_session: c.clean_session,
+            last_will: c.last_will.as_ref().map(LastWill::from),
+            username: c.username.as_deref(),
+            password: c.password.as_deref(),
+            properties: view_properties(&c.properties),
+        }
+    }
+}
+
+impl<'a> TryFrom<&LastWill<'a>> for OwnedLastWill {
+    type Error = Error;
+
+    fn try_from(w: &LastWill<'a>) -> Result<Self, Error> {
+        Ok(OwnedLastWill {
+            topic: w.topic.into(),
+            message: w.message.into(),
+            qos: w.qos,
+            retain: w.retain,
+            properties: own_properties(&w.properties)?,
+        })
+    }
+}
+
+impl<'a> From<&'a OwnedLastWill> for LastWill<'a> {
+    fn from(w: &'a OwnedLastWill) -> Self {
+        LastWill {
+            topic: &w.topic,
+            message: &w.message,
+            qos: w.qos,
+            retain: w.retain,
+            properties: view_properties(&w.properties),
+        }
+    }
+}
+
+impl<'a> TryFrom<&Connack<'a>> for OwnedConnack {
+    type Error = Error;
+
+    fn try_from(c: &Connack<'a>) -> Result<Self, Error> {
+        Ok(OwnedConnack {
+            session_present: c.session_present,
+            code: c.code,
+            properties: own_properties(&c.properties)?,
+        })
+    }
+}
+
+impl<'a> From<&'a OwnedConnack> for Connack<'a> {
+    fn from(c: &'a OwnedConnack) -> Self {
+        Connack {
+            session_present: c.session_present,
+            code: c.code,
+            properties: view_properties(&c.properties),
+        }
+    }
+}
+
+impl<'a> TryFrom<&Publish<'a>> for OwnedPublish {
+    type Error = Error;
+
+    fn try_from(p: &Publish<'a>) -> Result<Self, Error> {
+        Ok(OwnedPublish {
+            dup: p.dup,
+            qospid: p.qospid,
+            retain: p.retain,
+            topic_name: p.topic_name.into(),
+            payload: p.payload.into(),
+            properties: own_properties(&p.properties)?,
+        })
+    }
+}
+
+impl<'a> From<&'a OwnedPublish> for Publish<'a> {
+    fn from(p: &'a OwnedPublish) -> Self {
+        Publish {
+            dup: p.dup,
+            qospid: p.qospid,
+            retain: p.retain,
+            topic_name: &p.topic_name,
+            payload: &p.payload,
+            properties: view_properties(&p.properties),
+        }
+    }
+}
+
+impl<'a> TryFrom<&Ack<'a>> for OwnedAck {
+    type Error = Error;
+
+    fn try_from(a: &Ack<'a>) -> Result<Self, Error> {
+        Ok(OwnedAck {
+            pid: a.pid,
+            reason_code: a.reason_code,
+            properties: own_properties(&a.properties)?,
+        })
+    }
+}
+
+impl<'a> From<&'a OwnedAck> for Ack<'a> {
+    fn from(a: &'a OwnedAck) -> Self {
+        Ack {
+            pid: a.pid,
+            reason_code: a.reason_code,
+            properties: view_properties(&a.properties),
+        }
+    }
+}
+
+impl<'a> TryFrom<&Subscribe<'a>> for OwnedSubscribe {
+    type Error = Error;
+
+    fn try_from(s: &Subscribe<'a>) -> Result<Self, Error> {
+        Ok(OwnedSubscribe {
+            pid: s.pid,
+            topics: s.topics.clone(),
+            properties: own_properties(&s.properties)?,
+        })
+    }
+}
+
+impl<'a> From<&'a OwnedSubscribe> for Subscribe<'a> {
+    fn from(s: &'a OwnedSubscribe) -> Self {
+        Subscribe {
+            pid: s.pid,
+            topics: s.topics.clone(),
+            properties: view_properties(&s.properties),
+        }
+    }
+}
+
+impl<'a> TryFrom<&Disconnect<'a>> for OwnedDisconnect {
+    type Error = Error;
+
+    fn try_from(d: &Disconnect<'a>) -> Result<Self, Error> {
+        Ok(OwnedDisconnect {
+            reason_code: d.reason_code,
+            properties: own_properties(&d.properties)?,
+        })
+    }
+}
+
+impl<'a> From<&'a OwnedDisconnect> for Disconnect<'a> {
+    fn from(d: &'a OwnedDisconnect) -> Self {
+        Disconnect {
+            reason_code: d.reason_code,
+            properties: view_properties(&d.properties),
+        }
+    }
+}
+
+impl<'a> TryFrom<&Auth<'a>> for OwnedAuth {
+    type Error = Error;
+
+    fn try_from(a: &Auth<'a>) -> Result<Self, Error> {
+        Ok(OwnedAuth {
+            reason_code: a.reason_code,
+            properties: own_properties(&a.properties)?,
+        })
+    }
+}
+
+impl<'a> From<&'a OwnedAuth> for Auth<'a> {
+    fn from(a: &'a OwnedAuth) -> Self {
+        Auth {
+            reason_code: a.reason_code,
+            properties: view_properties(&a.properties),
+        }
+    }
+}
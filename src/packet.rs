@@ -1,7 +1,10 @@
 #[cfg(feature = "defmt")]
 use defmt::Format;
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
 
-use crate::*;
+use crate::{encoder::variable_int_len, *};
+use core::cmp::Ordering;
 
 /// Base enum for all MQTT packet types.
 ///
@@ -13,39 +16,58 @@ use crate::*;
 /// # use core::convert::TryFrom;
 /// // Simplest form
 /// let pkt = Packet::Connack(Connack { session_present: false,
-///                                     code: ConnectReturnCode::Accepted });
+///                                     code: ConnectReturnCode::Accepted,
+///                                     properties: None });
 /// // Using `Into` trait
 /// let publish = Publish { dup: false,
 ///                         qospid: QosPid::AtMostOnce,
 ///                         retain: false,
 ///                         topic_name: "to/pic",
-///                         payload: b"payload" };
+///                         payload: b"payload",
+///                         properties: None };
 /// let pkt: Packet = publish.into();
 /// // Identifyer-only packets
-/// let pkt = Packet::Puback(Pid::try_from(42).unwrap());
+/// let pkt = Packet::Puback(Pid::try_from(42).unwrap().into());
 /// ```
 ///
+/// A `Packet` can be compared with the struct of its variant, like `Publish` or `Connect`, which
+/// is `false` for other variants.
+///
+/// With the `derive` feature, packets implement serde traits. Deserializing borrows strings and
+/// bytes from the input, which formats like JSON can't do for bytes: deserialize an
+/// [OwnedPacket] instead, which has the same serialized form.
+///
+/// [OwnedPacket]: enum.OwnedPacket.html
 /// [`encode()`]: fn.encode.html
 /// [`decode_slice()`]: fn.decode_slice.html
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub enum Packet<'a> {
     /// [MQTT 3.1](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028)
+    #[cfg_attr(feature = "derive", serde(borrow))]
     Connect(Connect<'a>),
     /// [MQTT 3.2](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718033)
-    Connack(Connack),
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    Connack(Connack<'a>),
     /// [MQTT 3.3](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718037)
+    #[cfg_attr(feature = "derive", serde(borrow))]
     Publish(Publish<'a>),
     /// [MQTT 3.4](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718043)
-    Puback(Pid),
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    Puback(Ack<'a>),
     /// [MQTT 3.5](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718048)
-    Pubrec(Pid),
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    Pubrec(Ack<'a>),
     /// [MQTT 3.6](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718053)
-    Pubrel(Pid),
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    Pubrel(Ack<'a>),
     /// [MQTT 3.7](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718058)
-    Pubcomp(Pid),
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    Pubcomp(Ack<'a>),
     /// [MQTT 3.8](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718063)
-    Subscribe(Subscribe),
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    Subscribe(Subscribe<'a>),
     /// [MQTT 3.9](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718068)
     Suback(Suback),
     /// [MQTT 3.10](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718072)
@@ -57,7 +79,11 @@ pub enum Packet<'a> {
     /// [MQTT 3.13](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718086)
     Pingresp,
     /// [MQTT 3.14](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718090)
-    Disconnect,
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    Disconnect(Disconnect<'a>),
+    /// [MQTT 5 3.15](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html)
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    Auth(Auth<'a>),
 }
 impl<'a> Packet<'a> {
     /// Return the packet type variant.
@@ -79,11 +105,214 @@ impl<'a> Packet<'a> {
             Packet::Unsuback(_) => PacketType::Unsuback,
             Packet::Pingreq => PacketType::Pingreq,
             Packet::Pingresp => PacketType::Pingresp,
-            Packet::Disconnect => PacketType::Disconnect,
+            Packet::Disconnect(_) => PacketType::Disconnect,
+            Packet::Auth(_) => PacketType::Auth,
+        }
+    }
+
+    /// Return whether this is a `Connect`, the only packet allowed first on a connection.
+    pub fn is_connect(&self) -> bool {
+        self.get_type() == PacketType::Connect
+    }
+
+    /// Return the topic name of a `Publish`, or `None` for other packet types.
+    pub fn topic(&self) -> Option<&'a str> {
+        match self {
+            Packet::Publish(p) => Some(p.topic_name),
+            _ => None,
+        }
+    }
+
+    /// Return the packet identifier, for packet types that have one.
+    ///
+    /// This is `None` for a QoS 0 `Publish`.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// # use core::convert::TryFrom;
+    /// let pid = Pid::try_from(3).unwrap();
+    /// assert_eq!(Some(pid), Packet::Unsuback(pid).pid());
+    /// assert_eq!(None, Packet::Pingreq.pid());
+    /// ```
+    pub fn pid(&self) -> Option<Pid> {
+        match self {
+            Packet::Publish(p) => p.qospid.pid(),
+            Packet::Puback(a) | Packet::Pubrec(a) | Packet::Pubrel(a) | Packet::Pubcomp(a) => {
+                Some(a.pid)
+            }
+            Packet::Subscribe(s) => Some(s.pid),
+            Packet::Suback(s) => Some(s.pid),
+            Packet::Unsubscribe(u) => Some(u.pid),
+            Packet::Unsuback(pid) => Some(*pid),
+            _ => None,
+        }
+    }
+
+    /// Return the number of bytes that [`encode_slice()`] writes for this packet, including the
+    /// fixed header.
+    ///
+    /// Use this to allocate an exactly-sized buffer. The result is meaningless for a packet that
+    /// fails to encode, for example because of invalid properties.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let packet = Packet::Pingreq;
+    /// let mut buf = vec![0u8; packet.encoded_size()];
+    /// assert_eq!(Ok(2), encode_slice(&packet, &mut buf));
+    /// ```
+    ///
+    /// [`encode_slice()`]: fn.encode_slice.html
+    pub fn encoded_size(&self) -> usize {
+        let len = match self {
+            Packet::Connect(c) => c.remaining_len(),
+            Packet::Connack(c) => c.remaining_len(),
+            Packet::Publish(p) => p.remaining_len(),
+            Packet::Puback(a) | Packet::Pubrec(a) | Packet::Pubrel(a) | Packet::Pubcomp(a) => {
+                a.remaining_len()
+            }
+            Packet::Subscribe(s) => s.remaining_len(),
+            Packet::Suback(s) => s.remaining_len(),
+            Packet::Unsubscribe(u) => u.remaining_len(),
+            Packet::Unsuback(_) => 2,
+            Packet::Pingreq | Packet::Pingresp => 0,
+            Packet::Disconnect(d) => d.remaining_len(),
+            Packet::Auth(a) => a.remaining_len(),
+        };
+        1 + variable_int_len(len) + len
+    }
+
+    /// Compare packets by type, then pid, then `Publish` topic.
+    ///
+    /// This is meant for sorting packets deterministically, for example in tests. `Packet`
+    /// doesn't implement `Ord`, as packets that compare `Equal` here can still differ.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// # use core::convert::TryFrom;
+    /// let mut packets = [Packet::Pingreq, Packet::Unsuback(Pid::try_from(3).unwrap())];
+    /// packets.sort_by(Packet::cmp_by_type_then_pid);
+    /// assert_eq!(packets[0], Packet::Unsuback(Pid::try_from(3).unwrap()));
+    /// ```
+    pub fn cmp_by_type_then_pid(&self, other: &Packet) -> Ordering {
+        (self.get_type(), self.pid(), self.topic()).cmp(&(
+            other.get_type(),
+            other.pid(),
+            other.topic(),
+        ))
+    }
+
+    /// Return a [`PacketSummary`] of this packet.
+    ///
+    /// [`PacketSummary`]: struct.PacketSummary.html
+    pub fn summary(&self) -> PacketSummary {
+        let (qos, topic_len, payload_len) = match self {
+            Packet::Publish(p) => (
+                Some(p.qospid.qos()),
+                Some(p.topic_name.len()),
+                Some(p.payload.len()),
+            ),
+            _ => (None, None, None),
+        };
+        PacketSummary {
+            typ: self.get_type(),
+            qos,
+            pid: self.pid().map(|p| p.get()),
+            topic_len,
+            payload_len,
         }
     }
 }
 
+/// Owned summary of a [`Packet`], returned by [`Packet::summary()`].
+///
+/// Unlike `Packet`, this doesn't borrow the decoding buffer, so it can be sent to another thread,
+/// for example for metrics.
+///
+/// [`Packet`]: enum.Packet.html
+/// [`Packet::summary()`]: enum.Packet.html#method.summary
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketSummary {
+    pub typ: PacketType,
+    /// QoS of a `Publish`.
+    pub qos: Option<QoS>,
+    /// Packet identifier, for packet types that have one.
+    pub pid: Option<u16>,
+    /// Topic name length of a `Publish`.
+    pub topic_len: Option<usize>,
+    /// Payload length of a `Publish`.
+    pub payload_len: Option<usize>,
+}
+
+/// One handler per packet type, for use with [`dispatch()`].
+///
+/// All fields must be set, so adding a packet type to this crate will break the build until the
+/// new handler is written, instead of silently falling through a `_` match arm.
+///
+/// [`dispatch()`]: fn.dispatch.html
+pub struct PacketHandlers<'h, T> {
+    pub connect: &'h dyn Fn(&Connect) -> T,
+    pub connack: &'h dyn Fn(&Connack) -> T,
+    pub publish: &'h dyn Fn(&Publish) -> T,
+    pub puback: &'h dyn Fn(&Ack) -> T,
+    pub pubrec: &'h dyn Fn(&Ack) -> T,
+    pub pubrel: &'h dyn Fn(&Ack) -> T,
+    pub pubcomp: &'h dyn Fn(&Ack) -> T,
+    pub subscribe: &'h dyn Fn(&Subscribe) -> T,
+    pub suback: &'h dyn Fn(&Suback) -> T,
+    pub unsubscribe: &'h dyn Fn(&Unsubscribe) -> T,
+    pub unsuback: &'h dyn Fn(Pid) -> T,
+    pub pingreq: &'h dyn Fn() -> T,
+    pub pingresp: &'h dyn Fn() -> T,
+    pub disconnect: &'h dyn Fn(&Disconnect) -> T,
+    pub auth: &'h dyn Fn(&Auth) -> T,
+}
+
+/// Route a packet to the handler matching its [`PacketType`], and return that handler's result.
+///
+/// ```
+/// # use mqttrs::*;
+/// let handlers = PacketHandlers {
+///     connect: &|c| c.client_id.len(),
+///     connack: &|_| 0,
+///     publish: &|p| p.payload.len(),
+///     puback: &|_| 0,
+///     pubrec: &|_| 0,
+///     pubrel: &|_| 0,
+///     pubcomp: &|_| 0,
+///     subscribe: &|s| s.len(),
+///     suback: &|_| 0,
+///     unsubscribe: &|_| 0,
+///     unsuback: &|_| 0,
+///     pingreq: &|| 0,
+///     pingresp: &|| 0,
+///     disconnect: &|_| 0,
+///     auth: &|_| 0,
+/// };
+/// assert_eq!(0, dispatch(&Packet::Pingreq, &handlers));
+/// ```
+///
+/// [`PacketType`]: enum.PacketType.html
+pub fn dispatch<T>(packet: &Packet, handlers: &PacketHandlers<T>) -> T {
+    match packet {
+        Packet::Connect(p) => (handlers.connect)(p),
+        Packet::Connack(p) => (handlers.connack)(p),
+        Packet::Publish(p) => (handlers.publish)(p),
+        Packet::Puback(p) => (handlers.puback)(p),
+        Packet::Pubrec(p) => (handlers.pubrec)(p),
+        Packet::Pubrel(p) => (handlers.pubrel)(p),
+        Packet::Pubcomp(p) => (handlers.pubcomp)(p),
+        Packet::Subscribe(p) => (handlers.subscribe)(p),
+        Packet::Suback(p) => (handlers.suback)(p),
+        Packet::Unsubscribe(p) => (handlers.unsubscribe)(p),
+        Packet::Unsuback(pid) => (handlers.unsuback)(*pid),
+        Packet::Pingreq => (handlers.pingreq)(),
+        Packet::Pingresp => (handlers.pingresp)(),
+        Packet::Disconnect(p) => (handlers.disconnect)(p),
+        Packet::Auth(p) => (handlers.auth)(p),
+    }
+}
+
 macro_rules! packet_from_borrowed {
     ($($t:ident),+) => {
         $(
@@ -92,6 +321,14 @@ macro_rules! packet_from_borrowed {
                     Packet::$t(p)
                 }
             }
+            impl<'a, 'b> PartialEq<$t<'b>> for Packet<'a> {
+                fn eq(&self, other: &$t<'b>) -> bool {
+                    match self {
+                        Packet::$t(p) => p == other,
+                        _ => false,
+                    }
+                }
+            }
         )+
     }
 }
@@ -103,15 +340,28 @@ macro_rules! packet_from {
                     Packet::$t(p)
                 }
             }
+            impl<'a> PartialEq<$t> for Packet<'a> {
+                fn eq(&self, other: &$t) -> bool {
+                    match self {
+                        Packet::$t(p) => p == other,
+                        _ => false,
+                    }
+                }
+            }
         )+
     }
 }
 
-packet_from_borrowed!(Connect, Publish);
-packet_from!(Suback, Connack, Subscribe, Unsubscribe);
+packet_from_borrowed!(Connect, Connack, Publish, Subscribe, Disconnect, Auth);
+packet_from!(Suback, Unsubscribe);
 
 /// Packet type variant, without the associated data.
-#[cfg_attr(feature = "defmt",derive(Format))]
+///
+/// The order of the variants is not part of the API: use [to_u8()] to store or transmit a
+/// `PacketType`.
+///
+/// [to_u8()]: #method.to_u8
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum PacketType {
     Connect,
@@ -128,4 +378,59 @@ pub enum PacketType {
     Pingreq,
     Pingresp,
     Disconnect,
+    /// MQTT 5 only.
+    Auth,
+}
+
+impl PacketType {
+    /// Return the MQTT wire value of this packet type, from 1 (`Connect`) to 15 (`Auth`).
+    ///
+    /// This is the value of the first 4 bits of the fixed header. Unlike the enum discriminant,
+    /// it is fixed by the spec and won't change between versions of this crate, so it is the
+    /// right value to persist.
+    pub fn to_u8(self) -> u8 {
+        match self {
+            PacketType::Connect => 1,
+            PacketType::Connack => 2,
+            PacketType::Publish => 3,
+            PacketType::Puback => 4,
+            PacketType::Pubrec => 5,
+            PacketType::Pubrel => 6,
+            PacketType::Pubcomp => 7,
+            PacketType::Subscribe => 8,
+            PacketType::Suback => 9,
+            PacketType::Unsubscribe => 10,
+            PacketType::Unsuback => 11,
+            PacketType::Pingreq => 12,
+            PacketType::Pingresp => 13,
+            PacketType::Disconnect => 14,
+            PacketType::Auth => 15,
+        }
+    }
+
+    /// Convert an MQTT wire value back to a packet type, see [to_u8()].
+    ///
+    /// Returns `Err(Error::InvalidHeader)` for unknown values.
+    ///
+    /// [to_u8()]: #method.to_u8
+    pub fn from_u8(byte: u8) -> Result<PacketType, Error> {
+        match byte {
+            1 => Ok(PacketType::Connect),
+            2 => Ok(PacketType::Connack),
+            3 => Ok(PacketType::Publish),
+            4 => Ok(PacketType::Puback),
+            5 => Ok(PacketType::Pubrec),
+            6 => Ok(PacketType::Pubrel),
+            7 => Ok(PacketType::Pubcomp),
+            8 => Ok(PacketType::Subscribe),
+            9 => Ok(PacketType::Suback),
+            10 => Ok(PacketType::Unsubscribe),
+            11 => Ok(PacketType::Unsuback),
+            12 => Ok(PacketType::Pingreq),
+            13 => Ok(PacketType::Pingresp),
+            14 => Ok(PacketType::Disconnect),
+            15 => Ok(PacketType::Auth),
+            _ => Err(Error::InvalidHeader),
+        }
+    }
 }
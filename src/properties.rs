@@ -0,0 +1,553 @@
+#[cfg(feature = "defmt")]
+use defmt::Format;
+use crate::{decoder::*, encoder::*, *};
+use core::fmt;
+#[cfg(feature = "derive")]
+use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+/// MQTT 5 [property].
+///
+/// Each packet type only accepts a subset of properties, trying to decode or encode a property in
+/// the wrong packet type returns `Error::InvalidProperty` with the property identifier.
+///
+/// [property]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901027
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Property<'a> {
+    /// `0x01`, `true` if the payload is UTF-8 encoded character data.
+    PayloadFormatIndicator(bool),
+    /// `0x02`, lifetime of the message in seconds.
+    MessageExpiryInterval(u32),
+    /// `0x03`, MIME type of the payload.
+    ContentType(&'a str),
+    /// `0x08`, topic name for a response message.
+    ResponseTopic(&'a str),
+    /// `0x09`, used by the sender of a request message to identify the response.
+    CorrelationData(&'a [u8]),
+    /// `0x0B`, in the range `1..=268435455`.
+    SubscriptionIdentifier(u32),
+    /// `0x11`, in seconds.
+    SessionExpiryInterval(u32),
+    /// `0x12`
+    AssignedClientIdentifier(&'a str),
+    /// `0x13`, in seconds.
+    ServerKeepAlive(u16),
+    /// `0x15`
+    AuthenticationMethod(&'a str),
+    /// `0x16`
+    AuthenticationData(&'a [u8]),
+    /// `0x17`
+    RequestProblemInformation(bool),
+    /// `0x18`, in seconds.
+    WillDelayInterval(u32),
+    /// `0x19`
+    RequestResponseInformation(bool),
+    /// `0x1A`
+    ResponseInformation(&'a str),
+    /// `0x1C`
+    ServerReference(&'a str),
+    /// `0x1F`, human readable diagnostic.
+    ReasonString(&'a str),
+    /// `0x21`, must not be 0.
+    ReceiveMaximum(u16),
+    /// `0x22`
+    TopicAliasMaximum(u16),
+    /// `0x23`, must not be 0.
+    TopicAlias(u16),
+    /// `0x24`, only `AtMostOnce` and `AtLeastOnce` are allowed.
+    MaximumQoS(QoS),
+    /// `0x25`
+    RetainAvailable(bool),
+    /// `0x26`, key/value pair. This is the only property that can appear multiple times in
+    /// every packet type (`SubscriptionIdentifier` can also be repeated in `Publish`).
+    UserProperty(&'a str, &'a str),
+    /// `0x27`, must not be 0.
+    MaximumPacketSize(u32),
+    /// `0x28`
+    WildcardSubscriptionAvailable(bool),
+    /// `0x29`
+    SubscriptionIdentifierAvailable(bool),
+    /// `0x2A`
+    SharedSubscriptionAvailable(bool),
+}
+
+impl<'a> Property<'a> {
+    /// Return the property identifier, as found on the wire.
+    pub fn id(&self) -> u8 {
+        match self {
+            Property::PayloadFormatIndicator(_) => 0x01,
+            Property::MessageExpiryInterval(_) => 0x02,
+            Property::ContentType(_) => 0x03,
+            Property::ResponseTopic(_) => 0x08,
+            Property::CorrelationData(_) => 0x09,
+            Property::SubscriptionIdentifier(_) => 0x0B,
+            Property::SessionExpiryInterval(_) => 0x11,
+            Property::AssignedClientIdentifier(_) => 0x12,
+            Property::ServerKeepAlive(_) => 0x13,
+            Property::AuthenticationMethod(_) => 0x15,
+            Property::AuthenticationData(_) => 0x16,
+            Property::RequestProblemInformation(_) => 0x17,
+            Property::WillDelayInterval(_) => 0x18,
+            Property::RequestResponseInformation(_) => 0x19,
+            Property::ResponseInformation(_) => 0x1A,
+            Property::ServerReference(_) => 0x1C,
+            Property::ReasonString(_) => 0x1F,
+            Property::ReceiveMaximum(_) => 0x21,
+            Property::TopicAliasMaximum(_) => 0x22,
+            Property::TopicAlias(_) => 0x23,
+            Property::MaximumQoS(_) => 0x24,
+            Property::RetainAvailable(_) => 0x25,
+            Property::UserProperty(_, _) => 0x26,
+            Property::MaximumPacketSize(_) => 0x27,
+            Property::WildcardSubscriptionAvailable(_) => 0x28,
+            Property::SubscriptionIdentifierAvailable(_) => 0x29,
+            Property::SharedSubscriptionAvailable(_) => 0x2A,
+        }
+    }
+
+    /// Check value ranges that the type system can't express.
+    fn check(&self) -> Result<(), Error> {
+        let valid = match *self {
+            Property::SubscriptionIdentifier(n) => (1..=268435455).contains(&n),
+            Property::ReceiveMaximum(n) | Property::TopicAlias(n) => n != 0,
+            Property::MaximumPacketSize(n) => n != 0,
+            Property::MaximumQoS(qos) => qos != QoS::ExactlyOnce,
+            _ => true,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidProperty(self.id()))
+        }
+    }
+
+    /// Encoded length, including the identifier byte.
+    pub(crate) fn encoded_len(&self) -> usize {
+        1 + match self {
+            Property::PayloadFormatIndicator(_)
+            | Property::RequestProblemInformation(_)
+            | Property::RequestResponseInformation(_)
+            | Property::MaximumQoS(_)
+            | Property::RetainAvailable(_)
+            | Property::WildcardSubscriptionAvailable(_)
+            | Property::SubscriptionIdentifierAvailable(_)
+            | Property::SharedSubscriptionAvailable(_) => 1,
+            Property::ServerKeepAlive(_)
+            | Property::ReceiveMaximum(_)
+            | Property::TopicAliasMaximum(_)
+            | Property::TopicAlias(_) => 2,
+            Property::MessageExpiryInterval(_)
+            | Property::SessionExpiryInterval(_)
+            | Property::WillDelayInterval(_)
+            | Property::MaximumPacketSize(_) => 4,
+            Property::SubscriptionIdentifier(n) => variable_int_len(*n as usize),
+            Property::ContentType(s)
+            | Property::ResponseTopic(s)
+            | Property::AssignedClientIdentifier(s)
+            | Property::AuthenticationMethod(s)
+            | Property::ResponseInformation(s)
+            | Property::ServerReference(s)
+            | Property::ReasonString(s) => 2 + s.len(),
+            Property::CorrelationData(b) | Property::AuthenticationData(b) => 2 + b.len(),
+            Property::UserProperty(k, v) => 4 + k.len() + v.len(),
+        }
+    }
+
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let id = read_u8(buf, offset)?;
+        let read_bool = |buf: &'a [u8], offset: &mut usize| match read_u8(buf, offset)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::InvalidProperty(id)),
+        };
+        let property = match id {
+            0x01 => Property::PayloadFormatIndicator(read_bool(buf, offset)?),
+            0x02 => Property::MessageExpiryInterval(read_u32(buf, offset)?),
+            0x03 => Property::ContentType(read_str(buf, offset)?),
+            0x08 => Property::ResponseTopic(read_str(buf, offset)?),
+            0x09 => Property::CorrelationData(read_bytes(buf, offset)?),
+            0x0B => Property::SubscriptionIdentifier(read_variable_int(buf, offset)? as u32),
+            0x11 => Property::SessionExpiryInterval(read_u32(buf, offset)?),
+            0x12 => Property::AssignedClientIdentifier(read_str(buf, offset)?),
+            0x13 => Property::ServerKeepAlive(read_u16(buf, offset)?),
+            0x15 => Property::AuthenticationMethod(read_str(buf, offset)?),
+            0x16 => Property::AuthenticationData(read_bytes(buf, offset)?),
+            0x17 => Property::RequestProblemInformation(read_bool(buf, offset)?),
+            0x18 => Property::WillDelayInterval(read_u32(buf, offset)?),
+            0x19 => Property::RequestResponseInformation(read_bool(buf, offset)?),
+            0x1A => Property::ResponseInformation(read_str(buf, offset)?),
+            0x1C => Property::ServerReference(read_str(buf, offset)?),
+            0x1F => Property::ReasonString(read_str(buf, offset)?),
+            0x21 => Property::ReceiveMaximum(read_u16(buf, offset)?),
+            0x22 => Property::TopicAliasMaximum(read_u16(buf, offset)?),
+            0x23 => Property::TopicAlias(read_u16(buf, offset)?),
+            0x24 => match read_u8(buf, offset)? {
+                0 => Property::MaximumQoS(QoS::AtMostOnce),
+                1 => Property::MaximumQoS(QoS::AtLeastOnce),
+                _ => return Err(Error::InvalidProperty(id)),
+            },
+            0x25 => Property::RetainAvailable(read_bool(buf, offset)?),
+            0x26 => Property::UserProperty(read_str(buf, offset)?, read_str(buf, offset)?),
+            0x27 => Property::MaximumPacketSize(read_u32(buf, offset)?),
+            0x28 => Property::WildcardSubscriptionAvailable(read_bool(buf, offset)?),
+            0x29 => Property::SubscriptionIdentifierAvailable(read_bool(buf, offset)?),
+            0x2A => Property::SharedSubscriptionAvailable(read_bool(buf, offset)?),
+            _ => return Err(Error::InvalidProperty(id)),
+        };
+        property.check()?;
+        Ok(property)
+    }
+
+    pub(crate) fn to_buffer(self, buf: &mut [u8], offset: &mut usize) -> Result<(), Error> {
+        self.check()?;
+        write_u8(buf, offset, self.id())?;
+        match self {
+            Property::PayloadFormatIndicator(b)
+            | Property::RequestProblemInformation(b)
+            | Property::RequestResponseInformation(b)
+            | Property::RetainAvailable(b)
+            | Property::WildcardSubscriptionAvailable(b)
+            | Property::SubscriptionIdentifierAvailable(b)
+            | Property::SharedSubscriptionAvailable(b) => write_u8(buf, offset, b as u8),
+            Property::MaximumQoS(qos) => write_u8(buf, offset, qos.to_u8()),
+            Property::ServerKeepAlive(n)
+            | Property::ReceiveMaximum(n)
+            | Property::TopicAliasMaximum(n)
+            | Property::TopicAlias(n) => write_u16(buf, offset, n),
+            Property::MessageExpiryInterval(n)
+            | Property::SessionExpiryInterval(n)
+            | Property::WillDelayInterval(n)
+            | Property::MaximumPacketSize(n) => write_u32(buf, offset, n),
+            Property::SubscriptionIdentifier(n) => {
+                write_variable_int(buf, offset, n as usize).map(|_| ())
+            }
+            Property::ContentType(s)
+            | Property::ResponseTopic(s)
+            | Property::AssignedClientIdentifier(s)
+            | Property::AuthenticationMethod(s)
+            | Property::ResponseInformation(s)
+            | Property::ServerReference(s)
+            | Property::ReasonString(s) => write_string(buf, offset, s),
+            Property::CorrelationData(b) | Property::AuthenticationData(b) => {
+                write_bytes(buf, offset, b)
+            }
+            Property::UserProperty(k, v) => {
+                write_string(buf, offset, k)?;
+                write_string(buf, offset, v)
+            }
+        }
+    }
+}
+
+/// MQTT 5 [properties] of a packet.
+///
+/// Decoded packets borrow the validated property block from the input buffer, and properties
+/// are only parsed when iterated over. To encode a packet, build `Properties` from a slice:
+///
+/// ```
+/// # use mqttrs::*;
+/// let props = Properties::new(&[Property::ContentType("text/plain"),
+///                               Property::UserProperty("key", "value")]);
+/// assert_eq!(Some(Property::ContentType("text/plain")), props.iter().next());
+/// ```
+///
+/// [properties]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901027
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Clone, Copy)]
+pub struct Properties<'a>(Repr<'a>);
+
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Clone, Copy)]
+enum Repr<'a> {
+    /// Validated property block, excluding the length prefix.
+    Encoded(&'a [u8], Skip),
+    List(&'a [Property<'a>], Skip),
+    /// Up to two properties stored by value, for constructors that can't borrow a list.
+    Inline([Option<Property<'a>>; 2]),
+}
+
+/// Identifier of a property to leave out, so that it can be removed without copying the others.
+type Skip = Option<u8>;
+
+impl<'a> Properties<'a> {
+    pub fn new(list: &'a [Property<'a>]) -> Self {
+        Properties(Repr::List(list, None))
+    }
+
+    pub(crate) fn inline(first: Property<'a>, second: Option<Property<'a>>) -> Self {
+        Properties(Repr::Inline([Some(first), second]))
+    }
+
+    /// The same properties, without the ones with identifier `id`.
+    #[cfg(feature = "std")]
+    pub(crate) fn without(self, id: u8) -> Self {
+        Properties(match self.0 {
+            Repr::Encoded(buf, _) => Repr::Encoded(buf, Some(id)),
+            Repr::List(list, _) => Repr::List(list, Some(id)),
+            Repr::Inline(mut array) => {
+                for p in array.iter_mut() {
+                    if p.map(|p| p.id()) == Some(id) {
+                        *p = None;
+                    }
+                }
+                Repr::Inline(array)
+            }
+        })
+    }
+
+    /// Iterate over the properties, in wire order.
+    pub fn iter(&self) -> PropertiesIter<'a> {
+        match self.0 {
+            Repr::Encoded(buf, skip) => PropertiesIter(IterRepr::Encoded(buf, 0), skip),
+            Repr::List(list, skip) => PropertiesIter(IterRepr::List(list.iter()), skip),
+            Repr::Inline(array) => PropertiesIter(IterRepr::Inline(array, 0), None),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self.0 {
+            Repr::Encoded(buf, None) => buf.is_empty(),
+            Repr::List(list, None) => list.is_empty(),
+            Repr::Inline(array) => array.iter().all(Option::is_none),
+            _ => self.iter().next().is_none(),
+        }
+    }
+
+    /// Properties from a block copied by `to_vec()`.
+    #[cfg(feature = "std")]
+    pub(crate) fn encoded(block: &'a [u8]) -> Self {
+        Properties(Repr::Encoded(block, None))
+    }
+
+    /// Copy the property block, excluding the property length prefix.
+    #[cfg(feature = "std")]
+    pub(crate) fn to_vec(self) -> Result<std::vec::Vec<u8>, Error> {
+        let mut block = std::vec![0; self.len()];
+        match self.0 {
+            Repr::Encoded(buf, None) => block.copy_from_slice(buf),
+            _ => {
+                let mut offset = 0;
+                for property in self.iter() {
+                    property.to_buffer(&mut block, &mut offset)?;
+                }
+            }
+        }
+        Ok(block)
+    }
+
+    /// Length of the property block, excluding the property length prefix.
+    pub(crate) fn len(&self) -> usize {
+        match self.0 {
+            Repr::Encoded(buf, None) => buf.len(),
+            _ => self.iter().map(|p| p.encoded_len()).sum(),
+        }
+    }
+
+    /// Length of the property block, including the property length prefix.
+    pub(crate) fn len_with_prefix(&self) -> usize {
+        let len = self.len();
+        variable_int_len(len) + len
+    }
+
+    /// Check that all properties are allowed in the packet and that only repeatable ones are
+    /// repeated.
+    pub(crate) fn check(&self, allowed: &Allowed) -> Result<(), Error> {
+        for (i, property) in self.iter().enumerate() {
+            let id = property.id();
+            if !allowed.ids.contains(&id)
+                || (!allowed.repeatable.contains(&id) && self.iter().take(i).any(|p| p.id() == id))
+            {
+                return Err(Error::InvalidProperty(id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a property block (length prefix and properties).
+    pub(crate) fn from_buffer(
+        buf: &'a [u8],
+        offset: &mut usize,
+        allowed: &Allowed,
+    ) -> Result<Self, Error> {
+        let len = read_variable_int(buf, offset)?;
+        let end = *offset + len;
+        if end > buf.len() {
+            return Err(Error::InvalidLength);
+        }
+        let block = &buf[*offset..end];
+        let mut pos = 0;
+        while pos < block.len() {
+            Property::from_buffer(block, &mut pos)?;
+        }
+        let properties = Properties(Repr::Encoded(block, None));
+        properties.check(allowed)?;
+        *offset = end;
+        Ok(properties)
+    }
+
+    /// Write a property block (length prefix and properties).
+    pub(crate) fn to_buffer(
+        self,
+        buf: &mut [u8],
+        offset: &mut usize,
+        allowed: &Allowed,
+    ) -> Result<(), Error> {
+        self.check(allowed)?;
+        write_variable_int(buf, offset, self.len())?;
+        match self.0 {
+            Repr::Encoded(block, None) => {
+                check_remaining(buf, offset, block.len())?;
+                buf[*offset..*offset + block.len()].copy_from_slice(block);
+                *offset += block.len();
+            }
+            _ => {
+                for property in self.iter() {
+                    property.to_buffer(buf, offset)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Default for Properties<'a> {
+    fn default() -> Self {
+        Properties::new(&[])
+    }
+}
+
+impl<'a> From<&'a [Property<'a>]> for Properties<'a> {
+    fn from(list: &'a [Property<'a>]) -> Self {
+        Properties::new(list)
+    }
+}
+
+/// Properties are equal if they contain the same properties in the same order, regardless of
+/// whether they were decoded or built from a slice.
+impl<'a, 'b> PartialEq<Properties<'b>> for Properties<'a> {
+    fn eq(&self, other: &Properties<'b>) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+/// Serialized as the encoded property block, the same as `OwnedProperties`.
+///
+/// Without the `std` feature, only decoded properties can be serialized.
+#[cfg(feature = "derive")]
+impl<'a> Serialize for Properties<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Repr::Encoded(block, None) => serializer.serialize_bytes(block),
+            #[cfg(feature = "std")]
+            _ => serializer.serialize_bytes(&self.to_vec().map_err(S::Error::custom)?),
+            #[cfg(not(feature = "std"))]
+            _ => Err(S::Error::custom("can't serialize properties that weren't decoded")),
+        }
+    }
+}
+
+#[cfg(feature = "derive")]
+impl<'de: 'a, 'a> Deserialize<'de> for Properties<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let block = <&'a [u8]>::deserialize(deserializer)?;
+        let mut pos = 0;
+        while pos < block.len() {
+            Property::from_buffer(block, &mut pos).map_err(D::Error::custom)?;
+        }
+        Ok(Properties(Repr::Encoded(block, None)))
+    }
+}
+
+impl<'a> fmt::Debug for Properties<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Iterator over [Properties].
+///
+/// [Properties]: struct.Properties.html
+#[derive(Debug, Clone)]
+pub struct PropertiesIter<'a>(IterRepr<'a>, Skip);
+
+#[derive(Debug, Clone)]
+enum IterRepr<'a> {
+    Encoded(&'a [u8], usize),
+    List(core::slice::Iter<'a, Property<'a>>),
+    Inline([Option<Property<'a>>; 2], usize),
+}
+
+impl<'a> Iterator for PropertiesIter<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        loop {
+            let property = self.0.next()?;
+            if Some(property.id()) != self.1 {
+                return Some(property);
+            }
+        }
+    }
+}
+
+impl<'a> IterRepr<'a> {
+    fn next(&mut self) -> Option<Property<'a>> {
+        match self {
+            // The block was validated when decoding, so parsing can't fail.
+            IterRepr::Encoded(buf, pos) if *pos < buf.len() => Property::from_buffer(buf, pos).ok(),
+            IterRepr::Encoded(..) => None,
+            IterRepr::List(iter) => iter.next().copied(),
+            IterRepr::Inline(array, pos) => {
+                while *pos < array.len() {
+                    *pos += 1;
+                    if array[*pos - 1].is_some() {
+                        return array[*pos - 1];
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Property identifiers allowed in a packet type, and the ones that can appear more than once.
+pub(crate) struct Allowed {
+    ids: &'static [u8],
+    repeatable: &'static [u8],
+}
+
+pub(crate) const ACK_PROPERTIES: Allowed = Allowed {
+    ids: &[0x1F, 0x26],
+    repeatable: &[0x26],
+};
+pub(crate) const CONNECT_PROPERTIES: Allowed = Allowed {
+    ids: &[0x11, 0x15, 0x16, 0x17, 0x19, 0x21, 0x22, 0x26, 0x27],
+    repeatable: &[0x26],
+};
+pub(crate) const WILL_PROPERTIES: Allowed = Allowed {
+    ids: &[0x01, 0x02, 0x03, 0x08, 0x09, 0x18, 0x26],
+    repeatable: &[0x26],
+};
+pub(crate) const CONNACK_PROPERTIES: Allowed = Allowed {
+    ids: &[
+        0x11, 0x12, 0x13, 0x15, 0x16, 0x1A, 0x1C, 0x1F, 0x21, 0x22, 0x24, 0x25, 0x26, 0x27, 0x28,
+        0x29, 0x2A,
+    ],
+    repeatable: &[0x26],
+};
+pub(crate) const DISCONNECT_PROPERTIES: Allowed = Allowed {
+    ids: &[0x11, 0x1C, 0x1F, 0x26],
+    repeatable: &[0x26],
+};
+pub(crate) const AUTH_PROPERTIES: Allowed = Allowed {
+    ids: &[0x15, 0x16, 0x1F, 0x26],
+    repeatable: &[0x26],
+};
+pub(crate) const PUBLISH_PROPERTIES: Allowed = Allowed {
+    ids: &[0x01, 0x02, 0x03, 0x08, 0x09, 0x0B, 0x23, 0x26],
+    repeatable: &[0x0B, 0x26],
+};
+pub(crate) const SUBSCRIBE_PROPERTIES: Allowed = Allowed {
+    ids: &[0x0B, 0x26],
+    repeatable: &[0x26],
+};
@@ -1,29 +1,294 @@
 #[cfg(feature = "defmt")]
 use defmt::Format;
-use crate::{decoder::*, encoder::*, *};
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
+use crate::{decoder::*, encoder::*, properties::*, *};
 
 /// Publish packet ([MQTT 3.3]).
 ///
 /// [MQTT 3.3]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718037
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Publish<'a> {
     pub dup: bool,
     pub qospid: QosPid,
     pub retain: bool,
     pub topic_name: &'a str,
+    /// Application message, borrowed from the decoding buffer.
+    ///
+    /// This is everything after the variable header: the `Pid` of QoS 1 and 2 messages and the
+    /// MQTT 5 properties are not part of it.
     pub payload: &'a [u8],
+    /// MQTT 5 properties.
+    ///
+    /// `None` for MQTT 3.1.1. `Some` (even if empty) encodes the MQTT 5 packet format.
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    pub properties: Option<Properties<'a>>,
 }
 
 impl<'a> Publish<'a> {
+    /// Return the payload as a `str`, if it is valid UTF-8.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let buf = [0b00110000, 7, 0, 3, b'a', b'/', b'b', b'h', b'i'];
+    /// match decode_slice(&buf) {
+    ///     Ok(Some(Packet::Publish(p))) => assert_eq!(Ok("hi"), p.payload_str()),
+    ///     _ => panic!(),
+    /// }
+    /// ```
+    pub fn payload_str(&self) -> Result<&'a str, core::str::Utf8Error> {
+        core::str::from_utf8(self.payload)
+    }
+
+    /// Return `true` if the payload is empty.
+    ///
+    /// An empty retained message deletes the retained message of its topic.
+    pub fn is_empty(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    /// Return the MQTT 5 `ResponseTopic` property, if any.
+    ///
+    /// The receiver of a request message should publish its response to this topic.
+    pub fn response_topic(&self) -> Option<&'a str> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::ResponseTopic(s) => Some(s),
+            _ => None,
+        })
+    }
+
+    /// Return the MQTT 5 `CorrelationData` property, if any.
+    ///
+    /// The receiver of a request message should copy it into its response, so that the
+    /// requester can tell which request it is a response to.
+    pub fn correlation_data(&self) -> Option<&'a [u8]> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::CorrelationData(b) => Some(b),
+            _ => None,
+        })
+    }
+
+    /// Return the MQTT 5 `ContentType` property, if any.
+    ///
+    /// This is a MIME type like `application/json`, which the receiver can use to pick a payload
+    /// deserializer.
+    pub fn content_type(&self) -> Option<&'a str> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::ContentType(s) => Some(s),
+            _ => None,
+        })
+    }
+
+    /// Return the MQTT 5 `TopicAlias` property, if any.
+    pub fn topic_alias(&self) -> Option<u16> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::TopicAlias(n) => Some(n),
+            _ => None,
+        })
+    }
+
+    /// Replace the MQTT 5 topic alias of a received publish by its topic, before forwarding it
+    /// (std only).
+    ///
+    /// A publish with an empty topic gets its topic from `map`, or returns
+    /// `Err(Error::ProtocolViolation)` if the alias is unknown. The `TopicAlias` property is
+    /// removed, as aliases are specific to a connection. A publish without alias is unchanged.
+    ///
+    /// `map` isn't updated: the receiver should [insert()] the alias of a publish that has both a
+    /// topic and an alias.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let mut aliases = TopicAliasMap::default();
+    /// aliases.insert(1, "sensors/temp");
+    /// let props = [Property::TopicAlias(1)];
+    /// let publish = Publish { dup: false,
+    ///                         qospid: QosPid::AtMostOnce,
+    ///                         retain: false,
+    ///                         topic_name: "",
+    ///                         payload: b"21.5",
+    ///                         properties: Some(Properties::new(&props)) };
+    /// let resolved = publish.resolve_alias(&aliases)?;
+    /// assert_eq!("sensors/temp", resolved.topic_name);
+    /// assert_eq!(None, resolved.topic_alias());
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [insert()]: struct.TopicAliasMap.html#method.insert
+    #[cfg(feature = "std")]
+    pub fn resolve_alias<'m>(&self, map: &'m TopicAliasMap) -> Result<Publish<'m>, Error>
+    where
+        'a: 'm,
+    {
+        let (alias, properties) = match (self.topic_alias(), self.properties) {
+            // 0x23 is the TopicAlias identifier
+            (Some(alias), Some(properties)) => (alias, properties.without(0x23)),
+            _ => return Ok(self.clone()),
+        };
+        let topic_name = if self.topic_name.is_empty() {
+            map.get(alias).ok_or(Error::ProtocolViolation)?
+        } else {
+            self.topic_name
+        };
+        Ok(Publish {
+            topic_name,
+            properties: Some(properties),
+            ..self.clone()
+        })
+    }
+
+    /// Set the `dup` flag, for a fluent "decode, tweak, encode" pipeline.
+    ///
+    /// Returns `Err(Error::InvalidHeader)` when setting `dup` on a QoS 0 message ([MQTT-3.3.1-2]).
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// # use core::convert::TryFrom;
+    /// let publish = Publish { dup: false,
+    ///                         qospid: QosPid::AtLeastOnce(Pid::try_from(10).unwrap()),
+    ///                         retain: true,
+    ///                         topic_name: "to/pic",
+    ///                         payload: b"payload",
+    ///                         properties: None };
+    /// let resend = publish.set_dup(true)?.set_retain(false)?;
+    /// assert!(resend.dup && !resend.retain);
+    /// assert_eq!(Err(Error::InvalidHeader), resend.set_qospid(QosPid::AtMostOnce));
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [MQTT-3.3.1-2]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718037
+    pub fn set_dup(mut self, dup: bool) -> Result<Self, Error> {
+        self.dup = dup;
+        self.validate_flags()
+    }
+
+    /// Set the `retain` flag, see [set_dup()].
+    ///
+    /// This can't fail, but returns a `Result` to chain with the other setters.
+    ///
+    /// [set_dup()]: #method.set_dup
+    pub fn set_retain(mut self, retain: bool) -> Result<Self, Error> {
+        self.retain = retain;
+        self.validate_flags()
+    }
+
+    /// Return a copy with the `retain` flag set to `retain`, for a broker delivering a message.
+    ///
+    /// A broker sends a stored retained message to a new subscriber with `retain` set
+    /// ([MQTT-3.3.1-8]), but must clear it when forwarding a message to existing subscribers,
+    /// whatever the publisher sent ([MQTT-3.3.1-9]).
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let stored = Publish { dup: false,
+    ///                        qospid: QosPid::AtMostOnce,
+    ///                        retain: true,
+    ///                        topic_name: "to/pic",
+    ///                        payload: b"last value",
+    ///                        properties: None };
+    /// let live = stored.with_retain(false);
+    /// assert!(stored.retain && !live.retain);
+    /// ```
+    ///
+    /// [MQTT-3.3.1-8]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718037
+    /// [MQTT-3.3.1-9]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718037
+    pub fn with_retain(&self, retain: bool) -> Publish<'a> {
+        Publish {
+            retain,
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy delivered with at most the `max` QoS, for a broker forwarding a message to a
+    /// subscriber with a lower granted QoS ([MQTT-3.8.4-6]).
+    ///
+    /// The `Pid` is kept for QoS 1 and 2, and dropped with the `dup` flag when downgrading to QoS
+    /// 0. A message with a lower QoS than `max` is left unchanged.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// # use core::convert::TryFrom;
+    /// let pid = Pid::try_from(10).unwrap();
+    /// let publish = Publish { dup: true,
+    ///                         qospid: QosPid::ExactlyOnce(pid),
+    ///                         retain: false,
+    ///                         topic_name: "to/pic",
+    ///                         payload: b"payload",
+    ///                         properties: None };
+    /// assert_eq!(QosPid::AtLeastOnce(pid), publish.downgrade_qos(QoS::AtLeastOnce).qospid);
+    /// let qos0 = publish.downgrade_qos(QoS::AtMostOnce);
+    /// assert!(qos0.qospid == QosPid::AtMostOnce && !qos0.dup);
+    /// ```
+    ///
+    /// [MQTT-3.8.4-6]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718063
+    pub fn downgrade_qos(&self, max: QoS) -> Publish<'a> {
+        let qospid = match (self.qospid, max) {
+            (_, QoS::AtMostOnce) => QosPid::AtMostOnce,
+            (QosPid::ExactlyOnce(pid), QoS::AtLeastOnce) => QosPid::AtLeastOnce(pid),
+            (qospid, _) => qospid,
+        };
+        Publish {
+            dup: self.dup && qospid != QosPid::AtMostOnce,
+            qospid,
+            ..self.clone()
+        }
+    }
+
+    /// Set the `qospid`, see [set_dup()].
+    ///
+    /// Returns `Err(Error::InvalidHeader)` when downgrading a message with the `dup` flag to QoS 0.
+    ///
+    /// [set_dup()]: #method.set_dup
+    pub fn set_qospid(mut self, qospid: QosPid) -> Result<Self, Error> {
+        self.qospid = qospid;
+        self.validate_flags()
+    }
+
+    /// Set the MQTT 5 `properties`, see [set_dup()].
+    ///
+    /// Returns `Err(Error::InvalidProperty)` if a property isn't allowed in a publish packet.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let publish = Publish { dup: false,
+    ///                         qospid: QosPid::AtMostOnce,
+    ///                         retain: false,
+    ///                         topic_name: "to/pic",
+    ///                         payload: b"{}",
+    ///                         properties: None };
+    /// let props = [Property::ContentType("application/json")];
+    /// let publish = publish.set_properties(Properties::new(&props))?;
+    /// assert_eq!(Some("application/json"), publish.content_type());
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [set_dup()]: #method.set_dup
+    pub fn set_properties(mut self, properties: Properties<'a>) -> Result<Self, Error> {
+        properties.check(&PUBLISH_PROPERTIES)?;
+        self.properties = Some(properties);
+        Ok(self)
+    }
+
+    fn validate_flags(self) -> Result<Self, Error> {
+        if self.dup && self.qospid == QosPid::AtMostOnce {
+            Err(Error::InvalidHeader)
+        } else {
+            Ok(self)
+        }
+    }
+
     pub(crate) fn from_buffer(
         header: &Header,
         remaining_len: usize,
         buf: &'a [u8],
         offset: &mut usize,
+        protocol: Protocol,
     ) -> Result<Self, Error> {
-        let payload_end = *offset + remaining_len;
-        let topic_name = read_str(buf, offset)?;
+        let payload_end = payload_end(buf, *offset, remaining_len)?;
+        let topic_name = read_topic(buf, offset)?;
+        check_topic_chars(topic_name)?;
 
         let qospid = match header.qos {
             QoS::AtMostOnce => QosPid::AtMostOnce,
@@ -31,6 +296,15 @@ impl<'a> Publish<'a> {
             QoS::ExactlyOnce => QosPid::ExactlyOnce(Pid::from_buffer(buf, offset)?),
         };
 
+        let properties = match protocol {
+            Protocol::MQTT5 => Some(Properties::from_buffer(
+                &buf[..payload_end],
+                offset,
+                &PUBLISH_PROPERTIES,
+            )?),
+            _ => None,
+        };
+
         let payload = &buf[*offset..payload_end];
 
         // update the offset so that it's moved to the end of the payload
@@ -42,15 +316,84 @@ impl<'a> Publish<'a> {
             retain: header.retain,
             topic_name,
             payload,
+            properties,
         })
     }
+    /// Write only the header of a publish packet (everything but the payload) for a payload of
+    /// `payload_len` bytes, and return the number of bytes written.
+    ///
+    /// This lets you stream a large payload directly to the socket after the header, instead of
+    /// assembling the whole packet in memory. Returns `Err(Error::InvalidLength)` if the packet
+    /// would exceed the maximum MQTT packet size.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let mut buf = [0u8; 16];
+    /// let len = Publish::encode_streaming_header("a/b", QosPid::AtMostOnce, false, false, 1000,
+    ///                                            &mut buf)?;
+    /// assert_eq!(&buf[..len], &[0b00110000, 0xED, 0x07, 0, 3, b'a', b'/', b'b']);
+    /// // Now write the 1000 bytes payload.
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn encode_streaming_header(
+        topic: &str,
+        qospid: QosPid,
+        retain: bool,
+        dup: bool,
+        payload_len: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let header = Publish {
+            dup,
+            qospid,
+            retain,
+            topic_name: topic,
+            payload: &[],
+            properties: None,
+        };
+        let mut offset = 0;
+        header.write_header(buf, &mut offset, payload_len)?;
+        Ok(offset)
+    }
+
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let write_len = self.write_header(buf, offset, self.payload.len())?;
+
+        // Payload
+        check_remaining(buf, offset, self.payload.len())?;
+        for &byte in self.payload {
+            write_u8(buf, offset, byte)?;
+        }
+
+        Ok(write_len)
+    }
+
+    /// Length of the packet after the fixed header.
+    pub(crate) fn remaining_len(&self) -> usize {
+        self.fields_len() + self.payload.len()
+    }
+
+    /// Length of the variable header: topic (2+len) + pid (0/2) + properties.
+    fn fields_len(&self) -> usize {
+        self.topic_name.len()
+            + match self.qospid {
+                QosPid::AtMostOnce => 2,
+                _ => 4,
+            }
+            + self.properties.map_or(0, |p| p.len_with_prefix())
+    }
+
+    /// Write everything but the payload, returning the length of the whole packet.
+    fn write_header(
+        &self,
+        buf: &mut [u8],
+        offset: &mut usize,
+        payload_len: usize,
+    ) -> Result<usize, Error> {
+        check_topic_chars(self.topic_name)?;
+
         // Header
-        let mut header: u8 = match self.qospid {
-            QosPid::AtMostOnce => 0b00110000,
-            QosPid::AtLeastOnce(_) => 0b00110010,
-            QosPid::ExactlyOnce(_) => 0b00110100,
-        };
+        let mut header: u8 = 0b00110000 | self.qospid.qos().to_bits(1);
         if self.dup {
             header |= 0b00001000 as u8;
         };
@@ -60,15 +403,12 @@ impl<'a> Publish<'a> {
         check_remaining(buf, offset, 1)?;
         write_u8(buf, offset, header)?;
 
-        // Length: topic (2+len) + pid (0/2) + payload (len)
-        let length = self.topic_name.len()
-            + match self.qospid {
-                QosPid::AtMostOnce => 2,
-                _ => 4,
-            }
-            + self.payload.len();
+        let fields_len = self.fields_len();
+        let length = fields_len + payload_len;
 
-        let write_len = write_length(buf, offset, length)? + 1;
+        // Only the header needs to fit in the buffer
+        let write_len = write_variable_int(buf, offset, length)? + length + 1;
+        check_remaining(buf, offset, fields_len)?;
 
         // Topic
         write_string(buf, offset, self.topic_name)?;
@@ -80,11 +420,191 @@ impl<'a> Publish<'a> {
             QosPid::ExactlyOnce(pid) => pid.to_buffer(buf, offset)?,
         }
 
-        // Payload
-        for &byte in self.payload {
-            write_u8(buf, offset, byte)?;
+        // Properties
+        if let Some(properties) = self.properties {
+            properties.to_buffer(buf, offset, &PUBLISH_PROPERTIES)?;
+        }
+
+        Ok(write_len)
+    }
+}
+
+/// MQTT 5 topic aliases received on one connection, see [Publish::resolve_alias()] (std only).
+///
+/// [Publish::resolve_alias()]: struct.Publish.html#method.resolve_alias
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TopicAliasMap(std::collections::HashMap<u16, std::string::String>);
+
+#[cfg(feature = "std")]
+impl TopicAliasMap {
+    /// Set the topic of an alias, replacing its previous topic.
+    pub fn insert(&mut self, alias: u16, topic: &str) {
+        self.0.insert(alias, topic.into());
+    }
+
+    /// Return the topic of an alias, if it was set.
+    pub fn get(&self, alias: u16) -> Option<&str> {
+        self.0.get(&alias).map(|s| s.as_str())
+    }
+}
+
+/// Check that a topic name is usable in a publish: not empty, and without wildcards or null
+/// characters ([MQTT 3.3.2.1]).
+///
+/// [MQTT 3.3.2.1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718037
+pub(crate) fn check_topic_name(topic: &str) -> Result<(), Error> {
+    if topic.is_empty() {
+        Err(Error::InvalidTopic)
+    } else {
+        check_topic_chars(topic)
+    }
+}
+
+/// Check that a publish topic name has no wildcards or null characters. Unlike
+/// `check_topic_name()`, this allows the empty topic of an MQTT 5 topic alias.
+fn check_topic_chars(topic: &str) -> Result<(), Error> {
+    if topic.contains(&['+', '#', '\0'][..]) {
+        Err(Error::InvalidTopic)
+    } else {
+        Ok(())
+    }
+}
+
+/// Publish acknowledgement packet: Puback, Pubrec, Pubrel, or Pubcomp ([MQTT 3.4] to [MQTT 3.7]).
+///
+/// MQTT 3.1.1 acks only contain a `Pid`, which can be converted with `into()`. MQTT 5 adds a
+/// reason code and properties.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use core::convert::TryFrom;
+/// let pkt = Packet::Puback(Pid::try_from(42).unwrap().into());
+/// ```
+///
+/// [MQTT 3.4]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718043
+/// [MQTT 3.7]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718058
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct Ack<'a> {
+    pub pid: Pid,
+    /// MQTT 5 reason code. Must be `ReasonCode::Success` for MQTT 3.1.1.
+    pub reason_code: ReasonCode,
+    /// MQTT 5 properties: at most one `ReasonString`, and any number of `UserProperty`.
+    ///
+    /// With `None` and `ReasonCode::Success`, the packet is encoded in the MQTT 3.1.1 format.
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    pub properties: Option<Properties<'a>>,
+}
+
+/// Reason codes allowed in Puback and Pubrec packets.
+const PUBACK_REASON_CODES: &[u8] = &[0x00, 0x10, 0x80, 0x83, 0x87, 0x90, 0x91, 0x97, 0x99];
+/// Reason codes allowed in Pubrel and Pubcomp packets.
+const PUBREL_REASON_CODES: &[u8] = &[0x00, 0x92];
+
+impl<'a> From<Pid> for Ack<'a> {
+    fn from(pid: Pid) -> Self {
+        Ack {
+            pid,
+            reason_code: ReasonCode::Success,
+            properties: None,
         }
+    }
+}
+
+impl<'a> Ack<'a> {
+    /// Return the MQTT 5 `ReasonString` property, if any.
+    ///
+    /// This is a human readable explanation of the reason code, meant for diagnostics.
+    pub fn reason_string(&self) -> Option<&'a str> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::ReasonString(s) => Some(s),
+            _ => None,
+        })
+    }
+
+    /// Iterate over the MQTT 5 `UserProperty` key/value pairs.
+    pub fn user_properties(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.properties
+            .into_iter()
+            .flat_map(|p| p.iter())
+            .filter_map(|p| match p {
+                Property::UserProperty(k, v) => Some((k, v)),
+                _ => None,
+            })
+    }
 
+    fn check_reason_code(&self, typ: PacketType) -> Result<(), Error> {
+        let allowed = match typ {
+            PacketType::Puback | PacketType::Pubrec => PUBACK_REASON_CODES,
+            _ => PUBREL_REASON_CODES,
+        };
+        let code = self.reason_code.to_u8();
+        if allowed.contains(&code) {
+            Ok(())
+        } else {
+            Err(Error::InvalidReasonCode(code))
+        }
+    }
+
+    pub(crate) fn from_buffer(
+        typ: PacketType,
+        remaining_len: usize,
+        buf: &'a [u8],
+        offset: &mut usize,
+        protocol: Protocol,
+    ) -> Result<Self, Error> {
+        let end = *offset + remaining_len;
+        let mut ack = Ack::from(Pid::from_buffer(buf, offset)?);
+        if protocol == Protocol::MQTT5 && remaining_len > 2 {
+            ack.reason_code = ReasonCode::from_u8(read_u8(buf, offset)?)?;
+            ack.check_reason_code(typ)?;
+            if remaining_len > 3 {
+                ack.properties = Some(Properties::from_buffer(
+                    &buf[..end],
+                    offset,
+                    &ACK_PROPERTIES,
+                )?);
+            }
+        }
+        Ok(ack)
+    }
+
+    /// Length of the packet after the fixed header: pid(2) + reason code (0/1) + properties.
+    pub(crate) fn remaining_len(&self) -> usize {
+        match (self.reason_code, self.properties) {
+            (ReasonCode::Success, None) => 2,
+            (_, None) => 3,
+            (_, Some(p)) => 3 + p.len_with_prefix(),
+        }
+    }
+
+    pub(crate) fn to_buffer(
+        self,
+        typ: PacketType,
+        buf: &mut [u8],
+        offset: &mut usize,
+    ) -> Result<usize, Error> {
+        let header: u8 = match typ {
+            PacketType::Puback => 0b01000000,
+            PacketType::Pubrec => 0b01010000,
+            PacketType::Pubrel => 0b01100010,
+            _ => 0b01110000,
+        };
+        self.check_reason_code(typ)?;
+        let length = self.remaining_len();
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+        let write_len = write_length(buf, offset, length)? + 1;
+
+        self.pid.to_buffer(buf, offset)?;
+        if length > 2 {
+            write_u8(buf, offset, self.reason_code.to_u8())?;
+        }
+        if let Some(properties) = self.properties {
+            properties.to_buffer(buf, offset, &ACK_PROPERTIES)?;
+        }
         Ok(write_len)
     }
 }
@@ -0,0 +1,133 @@
+use crate::*;
+use std::{
+    io::{ErrorKind, Read},
+    vec::Vec,
+};
+
+/// Read packets from a blocking [Read] source, like a `TcpStream`.
+///
+/// Each packet is read into an internal buffer, which the returned `Packet` borrows until the
+/// next call to [read_packet()].
+///
+/// ```
+/// # use mqttrs::*;
+/// use std::io::Cursor;
+///
+/// let mut reader = MqttReader::new(Cursor::new([0b11000000, 0]), Protocol::MQTT311);
+/// assert_eq!(Ok(Some(Packet::Pingreq)), reader.read_packet());
+/// assert_eq!(Ok(None), reader.read_packet());
+/// ```
+///
+/// [Read]: https://doc.rust-lang.org/std/io/trait.Read.html
+/// [read_packet()]: #method.read_packet
+#[derive(Debug)]
+pub struct MqttReader<R> {
+    inner: R,
+    protocol: Protocol,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> MqttReader<R> {
+    /// Create a reader decoding packets of the given protocol version.
+    pub fn new(inner: R, protocol: Protocol) -> Self {
+        MqttReader {
+            inner,
+            protocol,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read and decode the next packet, blocking until it is complete.
+    ///
+    /// Returns `Ok(None)` at the end of the stream, if it ends between two packets. An end of
+    /// stream in the middle of a packet is an `Error::IoError(ErrorKind::UnexpectedEof, _)`.
+    pub fn read_packet(&mut self) -> Result<Option<Packet<'_>>, Error> {
+        self.buf.clear();
+        let mut byte = [0u8; 1];
+        loop {
+            match self.inner.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.buf.push(byte[0]);
+
+        // Read the remaining length one byte at a time, so that we don't read past the packet.
+        let needed = loop {
+            match bytes_needed(&self.buf)? {
+                Some(needed) => break needed,
+                None => {
+                    self.inner.read_exact(&mut byte)?;
+                    self.buf.push(byte[0]);
+                }
+            }
+        };
+        let start = self.buf.len();
+        self.buf.resize(start + needed, 0);
+        self.inner.read_exact(&mut self.buf[start..])?;
+
+        match decode_slice_with_protocol(&self.buf, self.protocol)? {
+            Some((_, packet)) => Ok(Some(packet)),
+            // The buffer contains exactly one packet
+            None => Err(Error::InvalidLength),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use core::convert::TryFrom;
+    use std::io::{Cursor, ErrorKind, Read, Result};
+
+    /// A reader returning at most one byte per call.
+    struct Trickle(Cursor<std::vec::Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn read_packets() {
+        let publish = Publish {
+            dup: false,
+            qospid: QosPid::AtLeastOnce(Pid::try_from(10).unwrap()),
+            retain: false,
+            topic_name: "a/b",
+            payload: &[0u8; 200],
+            properties: None,
+        };
+        let mut data = [0u8; 256];
+        let len = encode_slice(&publish.clone().into(), &mut data).unwrap();
+        let mut data = data[..len].to_vec();
+        data.extend_from_slice(&[0b11000000, 0]);
+
+        let mut reader = MqttReader::new(Cursor::new(data.clone()), Protocol::MQTT311);
+        assert_eq!(Ok(Some(publish.clone().into())), reader.read_packet());
+        assert_eq!(Ok(Some(Packet::Pingreq)), reader.read_packet());
+        assert_eq!(Ok(None), reader.read_packet());
+
+        let mut reader = MqttReader::new(Trickle(Cursor::new(data.clone())), Protocol::MQTT311);
+        assert_eq!(Ok(Some(publish.into())), reader.read_packet());
+        assert_eq!(Ok(Some(Packet::Pingreq)), reader.read_packet());
+        assert_eq!(Ok(None), reader.read_packet());
+
+        // End of stream in the middle of a packet
+        data.truncate(len - 1);
+        let mut reader = MqttReader::new(Cursor::new(data), Protocol::MQTT311);
+        match reader.read_packet() {
+            Err(Error::IoError(ErrorKind::UnexpectedEof, _)) => (),
+            other => panic!("Failed decode: {:?}", other),
+        }
+    }
+}
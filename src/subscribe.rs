@@ -1,6 +1,6 @@
 #[cfg(feature = "defmt")]
 use defmt::Format;
-use crate::{decoder::*, encoder::*, *};
+use crate::{decoder::*, encoder::*, properties::*, *};
 #[cfg(feature = "derive")]
 use serde::{Deserialize, Serialize};
 
@@ -18,23 +18,184 @@ use core::str::FromStr;
 
 /// Subscribe topic.
 ///
-/// [Subscribe] packets contain a `Vec` of those.
+/// [Subscribe] packets contain a `Vec` of those. The subscription options other than `qos` are
+/// MQTT 5 only ([MQTT 5 3.8.3.1]), and must keep their default value in MQTT 3.1.1.
 ///
 /// [Subscribe]: struct.Subscribe.html
-#[cfg_attr(feature = "defmt",derive(Format))]
+/// [MQTT 5 3.8.3.1]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct SubscribeTopic {
     pub topic_path: LimitedString,
     pub qos: QoS,
+    /// Don't receive the messages published by this client.
+    #[cfg_attr(feature = "derive", serde(default))]
+    pub no_local: bool,
+    /// Keep the `retain` flag of forwarded messages, instead of clearing it.
+    #[cfg_attr(feature = "derive", serde(default))]
+    pub retain_as_published: bool,
+    /// Whether to send the retained messages when subscribing.
+    #[cfg_attr(feature = "derive", serde(default))]
+    pub retain_handling: RetainHandling,
+}
+
+/// When the server sends retained messages to a new subscription, see
+/// [SubscribeTopic::retain_handling].
+///
+/// [SubscribeTopic::retain_handling]: struct.SubscribeTopic.html#structfield.retain_handling
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub enum RetainHandling {
+    /// Send retained messages at the time of the subscribe. This is the MQTT 3.1.1 behavior.
+    #[default]
+    SendAtSubscribe,
+    /// Send retained messages only if the subscription doesn't already exist.
+    SendAtNewSubscribe,
+    /// Don't send retained messages.
+    DoNotSend,
 }
 
 impl SubscribeTopic {
-    pub(crate) fn from_buffer(buf: &[u8], offset: &mut usize) -> Result<Self, Error> {
-        let topic_path = LimitedString::from_str(read_str(buf, offset)?).unwrap();
-        let qos = QoS::from_u8(buf[*offset])?;
-        *offset += 1;
-        Ok(SubscribeTopic { topic_path, qos })
+    pub(crate) fn from_buffer(
+        buf: &[u8],
+        offset: &mut usize,
+        protocol: Protocol,
+    ) -> Result<Self, Error> {
+        let topic_path = limited_string(read_topic(buf, offset)?)?;
+        let (qos, no_local, retain_as_published, retain_handling) =
+            read_options(buf, offset, protocol)?;
+        Ok(SubscribeTopic {
+            topic_path,
+            qos,
+            no_local,
+            retain_as_published,
+            retain_handling,
+        })
+    }
+
+    /// The subscription options byte.
+    fn options(&self) -> u8 {
+        let retain_handling = match self.retain_handling {
+            RetainHandling::SendAtSubscribe => 0,
+            RetainHandling::SendAtNewSubscribe => 1,
+            RetainHandling::DoNotSend => 2,
+        };
+        self.qos.to_u8()
+            | (self.no_local as u8) << 2
+            | (self.retain_as_published as u8) << 3
+            | retain_handling << 4
+    }
+}
+
+/// Copy a decoded topic into a `LimitedString`.
+///
+/// Returns `Err(Error::InvalidLength)` if it doesn't fit, which only happens on `no_std`.
+fn limited_string(topic: &str) -> Result<LimitedString, Error> {
+    LimitedString::from_str(topic).map_err(|_| Error::InvalidLength)
+}
+
+/// Read a subscription options byte: QoS, no local, retain as published, and retain handling.
+///
+/// MQTT 3.1.1 only has the QoS, the other bits must be 0.
+fn read_options(
+    buf: &[u8],
+    offset: &mut usize,
+    protocol: Protocol,
+) -> Result<(QoS, bool, bool, RetainHandling), Error> {
+    let options = read_u8(buf, offset)?;
+    if protocol != Protocol::MQTT5 {
+        return Ok((QoS::from_u8(options)?, false, false, RetainHandling::default()));
+    }
+    let retain_handling = match (options >> 4) & 0b11 {
+        0 => RetainHandling::SendAtSubscribe,
+        1 => RetainHandling::SendAtNewSubscribe,
+        2 => RetainHandling::DoNotSend,
+        _ => return Err(Error::InvalidSubscribeOptions(options)),
+    };
+    // Reserved bits
+    if options & 0b11000000 != 0 {
+        return Err(Error::InvalidSubscribeOptions(options));
+    }
+    Ok((
+        QoS::from_u8(options & 0b11)?,
+        options & 0b100 != 0,
+        options & 0b1000 != 0,
+        retain_handling,
+    ))
+}
+
+/// Compare the `topic_path`, ignoring the `qos`.
+impl PartialEq<str> for SubscribeTopic {
+    fn eq(&self, other: &str) -> bool {
+        self.topic_path.as_str() == other
+    }
+}
+
+/// Compare the `topic_path`, ignoring the `qos`.
+impl PartialEq<&str> for SubscribeTopic {
+    fn eq(&self, other: &&str) -> bool {
+        self.topic_path.as_str() == *other
+    }
+}
+
+// The std `String` and `Vec` don't implement `Format`, so the types holding a `LimitedString` or
+// a `LimitedVec` implement it by hand, the same way with and without std.
+#[cfg(feature = "defmt")]
+impl Format for SubscribeTopic {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "SubscribeTopic {{ topic_path: {=str}, qos: {}, no_local: {=bool}, ",
+            self.topic_path.as_str(),
+            self.qos,
+            self.no_local
+        );
+        defmt::write!(
+            f,
+            "retain_as_published: {=bool}, retain_handling: {} }}",
+            self.retain_as_published,
+            self.retain_handling
+        );
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<'a> Format for Subscribe<'a> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Subscribe {{ pid: {}, topics: {}, properties: {} }}",
+            self.pid,
+            &self.topics[..],
+            self.properties
+        );
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl Format for Suback {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Suback {{ pid: {}, return_codes: {} }}",
+            self.pid,
+            &self.return_codes[..]
+        );
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl Format for Unsubscribe {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "Unsubscribe {{ pid: {}, topics: [", self.pid);
+        for (i, topic) in self.topics.iter().enumerate() {
+            if i > 0 {
+                defmt::write!(f, ", ");
+            }
+            defmt::write!(f, "{=str}", topic.as_str());
+        }
+        defmt::write!(f, "] }}");
     }
 }
 
@@ -43,8 +204,9 @@ impl SubscribeTopic {
 /// [Suback] packets contain a `Vec` of those.
 ///
 /// [Suback]: struct.Subscribe.html
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub enum SubscribeReturnCodes {
     Success(QoS),
     Failure,
@@ -73,18 +235,62 @@ impl SubscribeReturnCodes {
 /// Subscribe packet ([MQTT 3.8]).
 ///
 /// [MQTT 3.8]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718063
-#[cfg_attr(feature = "defmt",derive(Format))]
 #[derive(Debug, Clone, PartialEq)]
-pub struct Subscribe {
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct Subscribe<'a> {
     pub pid: Pid,
     pub topics: LimitedVec<SubscribeTopic>,
+    /// MQTT 5 properties: at most one `SubscriptionIdentifier`, and any number of `UserProperty`.
+    ///
+    /// `None` for MQTT 3.1.1. `Some` (even if empty) encodes the MQTT 5 packet format.
+    #[cfg_attr(feature = "derive", serde(borrow))]
+    pub properties: Option<Properties<'a>>,
+}
+
+/// Subscribe packet borrowing its topics from the decoded buffer, see [decode_subscribe_ref()].
+///
+/// Unlike [Subscribe], this doesn't copy the topics into a `LimitedVec`: they are validated when
+/// decoding, and parsed again from the original buffer by [topics()].
+///
+/// [decode_subscribe_ref()]: fn.decode_subscribe_ref.html
+/// [Subscribe]: struct.Subscribe.html
+/// [topics()]: #method.topics
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubscribeRef<'a> {
+    pub pid: Pid,
+    /// MQTT 5 properties, see [Subscribe::properties](struct.Subscribe.html#structfield.properties).
+    pub properties: Option<Properties<'a>>,
+    topics: &'a [u8],
+}
+
+/// Iterator over the topics of a [SubscribeRef], returned by [SubscribeRef::topics()] and
+/// [Subscribe::topics_iter()].
+///
+/// [SubscribeRef]: struct.SubscribeRef.html
+/// [SubscribeRef::topics()]: struct.SubscribeRef.html#method.topics
+/// [Subscribe::topics_iter()]: struct.Subscribe.html#method.topics_iter
+#[derive(Debug, Clone)]
+pub struct SubscribeTopicsIter<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+/// Iterator over the return codes of a [Suback], returned by [Suback::return_codes_iter()].
+///
+/// [Suback]: struct.Suback.html
+/// [Suback::return_codes_iter()]: struct.Suback.html#method.return_codes_iter
+#[derive(Debug, Clone)]
+pub struct SubackReturnCodesIter<'a> {
+    buf: &'a [u8],
+    offset: usize,
 }
 
 /// Subsack packet ([MQTT 3.9]).
 ///
 /// [MQTT 3.9]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718068
-#[cfg_attr(feature = "defmt",derive(Format))]
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Suback {
     pub pid: Pid,
     pub return_codes: LimitedVec<SubscribeReturnCodes>,
@@ -93,92 +299,343 @@ pub struct Suback {
 /// Unsubscribe packet ([MQTT 3.10]).
 ///
 /// [MQTT 3.10]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718072
-#[cfg_attr(feature = "defmt",derive(Format))]
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Unsubscribe {
     pub pid: Pid,
     pub topics: LimitedVec<LimitedString>,
 }
 
-impl Subscribe {
+impl<'a> Subscribe<'a> {
     pub fn new(pid: Pid, topics: LimitedVec<SubscribeTopic>) -> Self {
-        Subscribe { pid, topics }
+        Subscribe {
+            pid,
+            topics,
+            properties: None,
+        }
+    }
+
+    /// Return the number of topics.
+    pub fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    /// Return `true` if there are no topics.
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    /// Decode the `Subscribe` at the start of `buf` without allocating, returning its length, its
+    /// pid, and an iterator over its `(topic_path, qos)` pairs.
+    ///
+    /// Unlike [decode_slice()], this doesn't copy the topics into a `LimitedVec`, so it isn't
+    /// limited to 5 topics on `no_std`. The topics are validated like [decode_subscribe_ref()]
+    /// does, so iterating over them can't fail. Returns `Ok(None)` if the packet is incomplete,
+    /// and `Err(Error::ProtocolViolation)` if it is not a `Subscribe`.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let buf = [0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'b', 1];
+    /// let (len, pid, mut topics) = Subscribe::topics_iter(&buf, Protocol::MQTT311)?.unwrap();
+    /// assert_eq!((10, 10), (len, pid.get()));
+    /// assert_eq!(Some(("a/b", QoS::AtLeastOnce)), topics.next());
+    /// assert_eq!(None, topics.next());
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [decode_slice()]: fn.decode_slice.html
+    /// [decode_subscribe_ref()]: fn.decode_subscribe_ref.html
+    #[allow(clippy::type_complexity)]
+    pub fn topics_iter(
+        buf: &[u8],
+        protocol: Protocol,
+    ) -> Result<Option<(usize, Pid, SubscribeTopicsIter<'_>)>, Error> {
+        match decode_subscribe_ref(buf, protocol)? {
+            Some((len, subscribe)) => Ok(Some((len, subscribe.pid, subscribe.topics()))),
+            None => Ok(None),
+        }
+    }
+
+    /// Decode the MQTT 3.1.1 `Subscribe` at the start of `buf` into the caller-provided `out`
+    /// slice, returning its length, its pid, and the number of topics written to `out`.
+    ///
+    /// Unlike [decode_slice()], this isn't limited to 5 topics on `no_std`: the caller decides how
+    /// many topics to accept. Returns `Err(Error::InvalidLength)` if `out` is too small or if a
+    /// topic is longer than a `LimitedString`, `Ok(None)` if the packet is incomplete, and
+    /// `Err(Error::ProtocolViolation)` if it is not a `Subscribe`.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let buf = [0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'b', 1];
+    /// let topic = SubscribeTopic { topic_path: Default::default(),
+    ///                              qos: QoS::AtMostOnce,
+    ///                              no_local: false,
+    ///                              retain_as_published: false,
+    ///                              retain_handling: RetainHandling::default() };
+    /// let mut out = [topic.clone(), topic];
+    /// let (len, pid, count) = Subscribe::from_buffer_into(&buf, &mut out)?.unwrap();
+    /// assert_eq!((10, 10, 1), (len, pid.get(), count));
+    /// assert_eq!(out[0], "a/b");
+    /// assert_eq!(out[0].qos, QoS::AtLeastOnce);
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [decode_slice()]: fn.decode_slice.html
+    pub fn from_buffer_into(
+        buf: &[u8],
+        out: &mut [SubscribeTopic],
+    ) -> Result<Option<(usize, Pid, usize)>, Error> {
+        let (len, pid, topics) = match Subscribe::topics_iter(buf, Protocol::MQTT311)? {
+            Some(res) => res,
+            None => return Ok(None),
+        };
+        let mut count = 0;
+        for (topic_path, qos) in topics {
+            let slot = out.get_mut(count).ok_or(Error::InvalidLength)?;
+            *slot = SubscribeTopic {
+                topic_path: limited_string(topic_path)?,
+                qos,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: RetainHandling::SendAtSubscribe,
+            };
+            count += 1;
+        }
+        Ok(Some((len, pid, count)))
+    }
+
+    /// Collapse topics with the same `topic_path` into one, keeping the highest QoS.
+    ///
+    /// The server would otherwise use the last of the duplicates ([MQTT 3.8.4]). Topics keep the
+    /// order of their first occurrence.
+    ///
+    /// [MQTT 3.8.4]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718063
+    pub fn dedup_highest_qos(&mut self) {
+        let mut i = 0;
+        while i < self.topics.len() {
+            let path = &self.topics[i].topic_path;
+            match self.topics[..i].iter().position(|t| t.topic_path == *path) {
+                Some(first) => {
+                    let dup = self.topics.remove(i);
+                    if dup.qos.to_u8() > self.topics[first].qos.to_u8() {
+                        self.topics[first].qos = dup.qos;
+                    }
+                }
+                None => i += 1,
+            }
+        }
+    }
+
+    /// Return the MQTT 5 `SubscriptionIdentifier` property, if any.
+    pub fn subscription_id(&self) -> Option<u32> {
+        self.properties?.iter().find_map(|p| match p {
+            Property::SubscriptionIdentifier(id) => Some(id),
+            _ => None,
+        })
     }
 
     pub(crate) fn from_buffer(
         remaining_len: usize,
-        buf: &[u8],
+        buf: &'a [u8],
         offset: &mut usize,
+        protocol: Protocol,
     ) -> Result<Self, Error> {
-        let payload_end = *offset + remaining_len;
+        let payload_end = payload_end(buf, *offset, remaining_len)?;
         let pid = Pid::from_buffer(buf, offset)?;
+        let properties = match protocol {
+            Protocol::MQTT5 => Some(Properties::from_buffer(
+                &buf[..payload_end],
+                offset,
+                &SUBSCRIBE_PROPERTIES,
+            )?),
+            _ => None,
+        };
 
         let mut topics = LimitedVec::new();
         while *offset < payload_end {
-            let _res = topics.push(SubscribeTopic::from_buffer(buf, offset)?);
+            let _res = topics.push(SubscribeTopic::from_buffer(buf, offset, protocol)?);
 
             #[cfg(not(feature = "std"))]
             _res.map_err(|_| Error::InvalidLength)?;
         }
+        if topics.is_empty() {
+            return Err(Error::InvalidLength);
+        }
+
+        Ok(Subscribe {
+            pid,
+            topics,
+            properties,
+        })
+    }
 
-        Ok(Subscribe { pid, topics })
+    /// Length of the packet after the fixed header.
+    pub(crate) fn remaining_len(&self) -> usize {
+        // pid(2) + properties + topic.for_each(2+len + qos(1))
+        let mut length = 2;
+        if let Some(properties) = self.properties {
+            length += properties.len_with_prefix();
+        }
+        for topic in &self.topics {
+            length += topic.topic_path.len() + 2 + 1;
+        }
+        length
     }
 
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        // A subscribe must contain at least one topic filter.
+        if self.topics.is_empty() {
+            return Err(Error::InvalidLength);
+        }
         let header: u8 = 0b10000010;
         check_remaining(buf, offset, 1)?;
         write_u8(buf, offset, header)?;
 
-        // Length: pid(2) + topic.for_each(2+len + qos(1))
-        let mut length = 2;
-        for topic in &self.topics {
-            length += topic.topic_path.len() + 2 + 1;
-        }
-        let write_len = write_length(buf, offset, length)? + 1;
+        let write_len = write_length(buf, offset, self.remaining_len())? + 1;
 
         // Pid
         self.pid.to_buffer(buf, offset)?;
 
+        // Properties
+        if let Some(properties) = self.properties {
+            properties.to_buffer(buf, offset, &SUBSCRIBE_PROPERTIES)?;
+        }
+
         // Topics
         for topic in &self.topics {
+            let options = topic.options();
+            // Only MQTT 5 has options other than the QoS
+            if self.properties.is_none() && options > 0b11 {
+                return Err(Error::InvalidSubscribeOptions(options));
+            }
             write_string(buf, offset, topic.topic_path.as_str())?;
-            write_u8(buf, offset, topic.qos.to_u8())?;
+            write_u8(buf, offset, options)?;
         }
 
         Ok(write_len)
     }
 }
 
+impl<'a> SubscribeRef<'a> {
+    /// Iterate over the `(topic_path, qos)` pairs, without allocating.
+    pub fn topics(&self) -> SubscribeTopicsIter<'a> {
+        SubscribeTopicsIter {
+            buf: self.topics,
+            offset: 0,
+        }
+    }
+
+    pub(crate) fn from_buffer(
+        remaining_len: usize,
+        buf: &'a [u8],
+        offset: &mut usize,
+        protocol: Protocol,
+    ) -> Result<Self, Error> {
+        let payload_end = payload_end(buf, *offset, remaining_len)?;
+        // Don't read past the packet, so that `start` can't be after `payload_end`.
+        let buf = &buf[..payload_end];
+        let pid = Pid::from_buffer(buf, offset)?;
+        let properties = match protocol {
+            Protocol::MQTT5 => Some(Properties::from_buffer(buf, offset, &SUBSCRIBE_PROPERTIES)?),
+            _ => None,
+        };
+
+        // Validate the topics now, so that iterating over them can't fail.
+        let start = *offset;
+        while *offset < payload_end {
+            read_topic(buf, offset)?;
+            read_options(buf, offset, protocol)?;
+        }
+        if start == payload_end {
+            return Err(Error::InvalidLength);
+        }
+
+        Ok(SubscribeRef {
+            pid,
+            properties,
+            topics: &buf[start..payload_end],
+        })
+    }
+}
+
+impl<'a> Iterator for SubackReturnCodesIter<'a> {
+    type Item = Result<SubscribeReturnCodes, Error>;
+
+    fn next(&mut self) -> Option<Result<SubscribeReturnCodes, Error>> {
+        if self.offset >= self.buf.len() {
+            return None;
+        }
+        Some(SubscribeReturnCodes::from_buffer(self.buf, &mut self.offset))
+    }
+}
+
+impl<'a> Iterator for SubscribeTopicsIter<'a> {
+    type Item = (&'a str, QoS);
+
+    fn next(&mut self) -> Option<(&'a str, QoS)> {
+        if self.offset >= self.buf.len() {
+            return None;
+        }
+        // The topics were validated when decoding, so parsing can't fail.
+        let topic_path = read_str(self.buf, &mut self.offset).ok()?;
+        let qos = QoS::from_u8(read_u8(self.buf, &mut self.offset).ok()? & 0b11).ok()?;
+        Some((topic_path, qos))
+    }
+}
+
 impl Unsubscribe {
     pub fn new(pid: Pid, topics: LimitedVec<LimitedString>) -> Self {
         Unsubscribe { pid, topics }
     }
 
+    /// Return the number of topics.
+    pub fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    /// Return `true` if there are no topics.
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
     pub(crate) fn from_buffer(
         remaining_len: usize,
         buf: &[u8],
         offset: &mut usize,
     ) -> Result<Self, Error> {
-        let payload_end = *offset + remaining_len;
+        let payload_end = payload_end(buf, *offset, remaining_len)?;
         let pid = Pid::from_buffer(buf, offset)?;
 
         let mut topics = LimitedVec::new();
         while *offset < payload_end {
-            let _res = topics.push(LimitedString::from_str(read_str(buf, offset)?).unwrap());
+            let _res = topics.push(limited_string(read_topic(buf, offset)?)?);
 
             #[cfg(not(feature = "std"))]
             _res.map_err(|_| Error::InvalidLength)?;
         }
+        if topics.is_empty() {
+            return Err(Error::InvalidLength);
+        }
 
         Ok(Unsubscribe { pid, topics })
     }
 
-    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
-        let header: u8 = 0b10100010;
+    /// Length of the packet after the fixed header.
+    pub(crate) fn remaining_len(&self) -> usize {
         let mut length = 2;
         for topic in &self.topics {
             length += 2 + topic.len();
         }
+        length
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        // An unsubscribe must contain at least one topic filter.
+        if self.topics.is_empty() {
+            return Err(Error::InvalidLength);
+        }
+        let header: u8 = 0b10100010;
+        let length = self.remaining_len();
         check_remaining(buf, offset, 1)?;
         write_u8(buf, offset, header)?;
 
@@ -196,12 +653,63 @@ impl Suback {
         Suback { pid, return_codes }
     }
 
+    /// Return the number of return codes.
+    pub fn len(&self) -> usize {
+        self.return_codes.len()
+    }
+
+    /// Return `true` if there are no return codes.
+    pub fn is_empty(&self) -> bool {
+        self.return_codes.is_empty()
+    }
+
+    /// Decode the `Suback` at the start of `buf` lazily, returning its length, its pid, and an
+    /// iterator over its return codes.
+    ///
+    /// Unlike [decode_slice()], this doesn't copy the return codes into a `LimitedVec`, so it isn't
+    /// limited to 5 return codes on `no_std`. Invalid return codes are only reported when
+    /// iterating. Returns `Ok(None)` if the packet is incomplete, and
+    /// `Err(Error::ProtocolViolation)` if it is not a `Suback`.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let buf = [0b10010000, 4, 0, 10, 0x01, 0x80];
+    /// let (len, pid, mut codes) = Suback::return_codes_iter(&buf)?.unwrap();
+    /// assert_eq!((6, 10), (len, pid.get()));
+    /// assert_eq!(Some(Ok(SubscribeReturnCodes::Success(QoS::AtLeastOnce))), codes.next());
+    /// assert_eq!(Some(Ok(SubscribeReturnCodes::Failure)), codes.next());
+    /// assert_eq!(None, codes.next());
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// [decode_slice()]: fn.decode_slice.html
+    #[allow(clippy::type_complexity)]
+    pub fn return_codes_iter(
+        buf: &[u8],
+    ) -> Result<Option<(usize, Pid, SubackReturnCodesIter<'_>)>, Error> {
+        let mut offset = 0;
+        match read_header(buf, &mut offset)? {
+            Some((header, remaining_len)) if header.typ == PacketType::Suback => {
+                let end = offset + remaining_len;
+                let pid = Pid::from_buffer(&buf[..end], &mut offset)?;
+                let codes = SubackReturnCodesIter {
+                    buf: &buf[offset..end],
+                    offset: 0,
+                };
+                Ok(Some((end, pid, codes)))
+            }
+            Some(_) => Err(Error::ProtocolViolation),
+            // Don't have a full packet
+            None => Ok(None),
+        }
+    }
+
     pub(crate) fn from_buffer(
         remaining_len: usize,
         buf: &[u8],
         offset: &mut usize,
     ) -> Result<Self, Error> {
-        let payload_end = *offset + remaining_len;
+        let payload_end = payload_end(buf, *offset, remaining_len)?;
         let pid = Pid::from_buffer(buf, offset)?;
 
         let mut return_codes = LimitedVec::new();
@@ -215,9 +723,14 @@ impl Suback {
         Ok(Suback { pid, return_codes })
     }
 
+    /// Length of the packet after the fixed header.
+    pub(crate) fn remaining_len(&self) -> usize {
+        2 + self.return_codes.len()
+    }
+
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
         let header: u8 = 0b10010000;
-        let length = 2 + self.return_codes.len();
+        let length = self.remaining_len();
         check_remaining(buf, offset, 1)?;
         write_u8(buf, offset, header)?;
 
@@ -0,0 +1,146 @@
+/// Check whether a topic name matches a topic filter ([MQTT 4.7]).
+///
+/// `+` matches exactly one level and `#` matches any number of levels, including the parent
+/// level: `sport/#` matches `sport`. Topics starting with `$` are only matched by filters that
+/// don't start with a wildcard ([MQTT-4.7.2-1]).
+///
+/// Returns `false` for an invalid filter, like `sport/#/ranking` or `sp+ort`, and for a topic
+/// name containing wildcards. Use this to route a [Publish] to a [SubscribeTopic]'s `topic_path`.
+///
+/// ```
+/// # use mqttrs::*;
+/// assert!(topic_matches("sport/tennis/+", "sport/tennis/player1"));
+/// assert!(topic_matches("sport/#", "sport"));
+/// assert!(!topic_matches("sport/+", "sport/tennis/player1"));
+/// assert!(!topic_matches("#", "$SYS/uptime"));
+/// ```
+///
+/// [MQTT 4.7]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718106
+/// [MQTT-4.7.2-1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718106
+/// [Publish]: struct.Publish.html
+/// [SubscribeTopic]: struct.SubscribeTopic.html
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    if !is_valid_filter(filter) || topic.is_empty() || topic.contains(&['+', '#', '\0'][..]) {
+        return false;
+    }
+    if topic.starts_with('$') && filter.starts_with(&['+', '#'][..]) {
+        return false;
+    }
+    let mut filter_levels = topic_levels(filter);
+    let mut levels = topic_levels(topic);
+    loop {
+        match (filter_levels.next(), levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => (),
+            (Some(f), Some(t)) if f == t => (),
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Iterate over the levels of a topic name or filter ([MQTT 4.7.1.1]).
+///
+/// Empty levels are kept: `/finance` starts with an empty level, `finance/` ends with one, and the
+/// empty string is a single empty level. Wildcards are returned as levels of their own.
+///
+/// ```
+/// # use mqttrs::*;
+/// assert!(topic_levels("sport/+/player1").eq(["sport", "+", "player1"].iter().copied()));
+/// assert!(topic_levels("/finance").eq(["", "finance"].iter().copied()));
+/// assert_eq!(topic_levels("").count(), 1);
+/// ```
+///
+/// [MQTT 4.7.1.1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718106
+pub fn topic_levels(topic: &str) -> impl Iterator<Item = &str> {
+    topic.split('/')
+}
+
+/// Check that wildcards fill a whole level, and that `#` is the last level.
+fn is_valid_filter(filter: &str) -> bool {
+    if filter.is_empty() || filter.contains('\0') {
+        return false;
+    }
+    let mut levels = topic_levels(filter).peekable();
+    while let Some(level) = levels.next() {
+        let valid = match level {
+            "#" => levels.peek().is_none(),
+            "+" => true,
+            _ => !level.contains(&['+', '#'][..]),
+        };
+        if !valid {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{topic_levels, topic_matches};
+    use std::vec::Vec;
+
+    #[test]
+    fn levels() {
+        for &(topic, levels) in [
+            ("sport/tennis/player1", &["sport", "tennis", "player1"][..]),
+            ("sport/tennis/+", &["sport", "tennis", "+"][..]),
+            ("sport/#", &["sport", "#"][..]),
+            ("/finance", &["", "finance"][..]),
+            ("finance/", &["finance", ""][..]),
+            ("/", &["", ""][..]),
+            ("a//b", &["a", "", "b"][..]),
+            ("", &[""][..]),
+        ]
+        .iter()
+        {
+            assert_eq!(topic_levels(topic).collect::<Vec<_>>(), levels, "{:?}", topic);
+        }
+    }
+
+    #[test]
+    fn matches() {
+        for &(filter, topic, matches) in [
+            // Multi-level wildcard
+            ("sport/tennis/player1/#", "sport/tennis/player1", true),
+            ("sport/tennis/player1/#", "sport/tennis/player1/ranking", true),
+            ("sport/tennis/player1/#", "sport/tennis/player1/score/wimbledon", true),
+            ("sport/#", "sport", true),
+            ("#", "sport/tennis", true),
+            ("#", "/", true),
+            ("sport/tennis#", "sport/tennis", false),
+            ("sport/tennis/#/ranking", "sport/tennis/x/ranking", false),
+            // Single-level wildcard
+            ("sport/tennis/+", "sport/tennis/player1", true),
+            ("sport/tennis/+", "sport/tennis/player2", true),
+            ("sport/tennis/+", "sport/tennis/player1/ranking", false),
+            ("sport/+", "sport", false),
+            ("sport/+", "sport/", true),
+            ("+/+", "/finance", true),
+            ("/+", "/finance", true),
+            ("+", "/finance", false),
+            ("+/tennis/#", "sport/tennis/player1", true),
+            ("sport+", "sport", false),
+            ("sp+ort", "sp+ort", false),
+            // No wildcard
+            ("sport/tennis", "sport/tennis", true),
+            ("sport/tennis", "sport/Tennis", false),
+            ("sport/tennis", "sport/tennis/", false),
+            ("sport", "sport/tennis", false),
+            // Topics starting with $
+            ("#", "$SYS/uptime", false),
+            ("+/monitor/Clients", "$SYS/monitor/Clients", false),
+            ("$SYS/#", "$SYS/uptime", true),
+            ("$SYS/monitor/+", "$SYS/monitor/Clients", true),
+            // Invalid filters and topics
+            ("", "", false),
+            ("#", "", false),
+            ("#", "sport/+", false),
+            ("a\0b", "a\0b", false),
+        ]
+        .iter()
+        {
+            assert_eq!(matches, topic_matches(filter, topic), "{:?} {:?}", filter, topic);
+        }
+    }
+}
@@ -2,8 +2,8 @@
 use defmt::{Format};
 
 
-use crate::encoder::write_u16;
-use core::{convert::TryFrom, fmt, num::NonZeroU16};
+use crate::{decoder::read_u16, encoder::write_u16, ConnectReturnCode, PacketType};
+use core::{convert::TryFrom, fmt, num::NonZeroU16, str::FromStr};
 
 #[cfg(feature = "derive")]
 use serde::{Deserialize, Serialize};
@@ -21,7 +21,6 @@ use std::{
 /// [`decode()`]: fn.decode.html
 
 
-#[cfg_attr(feature = "defmt",derive(Format))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     /// Not enough space in the write buffer.
@@ -32,22 +31,61 @@ pub enum Error {
     InvalidPid,
     /// Tried to decode a QoS > 2.
     InvalidQos(u8),
-    /// Tried to decode a ConnectReturnCode > 5.
+    /// Tried to decode a ConnectReturnCode > 5, or to encode an MQTT 5 reason code in an MQTT
+    /// 3.1.1 `Connack`.
     InvalidConnectReturnCode(u8),
-    /// Tried to decode an unknown protocol.
+    /// Tried to encode or decode an MQTT 5 reason code that is unknown or invalid for this packet
+    /// type.
+    InvalidReasonCode(u8),
+    /// Tried to decode an unknown protocol name and level combination. Contains the name
+    /// (truncated to 10 bytes on `no_std`) and the level.
+    ///
+    /// A server receiving a known protocol name (`"MQTT"` or `"MQIsdp"`) with an unsupported level
+    /// should respond with a `ConnectReturnCode::RefusedProtocolVersion` [Connack] before
+    /// disconnecting ([MQTT-3.1.2-2]). Other names are garbage, and the server may disconnect
+    /// without a `Connack` ([MQTT-3.1.2-1]).
+    ///
+    /// [Connack]: struct.Connack.html
+    /// [MQTT-3.1.2-2]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028
+    /// [MQTT-3.1.2-1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028
     #[cfg(feature = "std")]
     InvalidProtocol(std::string::String, u8),
     #[cfg(not(feature = "std"))]
     InvalidProtocol(heapless::String<10>, u8),
-    /// Tried to decode an invalid fixed header (packet type, flags, or remaining_length).
+    /// Tried to use a `Connect` client identifier that the server would refuse.
+    InvalidClientId,
+    /// `Connect` has a password without a username, which is only allowed by MQTT 5.
+    InvalidCredentials,
+    /// Tried to use an empty topic name, or one with wildcards or null characters.
+    InvalidTopic,
+    /// Decoded a valid packet of a type that isn't allowed here, for example a first packet that
+    /// isn't a `Connect`.
+    ProtocolViolation,
+    /// Decoded a `Disconnect` followed by more data, see `decode_slice_strict()`. Contains the
+    /// number of trailing bytes.
+    TrailingData(usize),
+    /// Tried to decode an invalid fixed header (packet type or remaining_length).
     InvalidHeader,
+    /// Tried to decode a fixed header with the wrong flags for its packet type. Contains the
+    /// packet type and the 4 flag bits.
+    InvalidHeaderFlags(PacketType, u8),
     /// Trying to encode/decode an invalid length.
     ///
     /// The difference with `WriteZero`/`UnexpectedEof` is that it refers to an invalid/corrupt
     /// length rather than a buffer size issue.
     InvalidLength,
+    /// Tried to encode or decode an MQTT 5 property that is unknown, invalid for this packet type,
+    /// repeated, or has an invalid value. Contains the property identifier.
+    InvalidProperty(u8),
+    /// Tried to encode or decode invalid MQTT 5 subscription options, or MQTT 5 options in an
+    /// MQTT 3.1.1 `Subscribe`. Contains the options byte.
+    InvalidSubscribeOptions(u8),
+    /// A decoded packet exceeds the configured `DecodeLimits`.
+    LimitExceeded,
     /// Trying to decode a non-utf8 string.
-    InvalidString(#[cfg_attr(feature = "defmt",defmt(Debug2Format))] core::str::Utf8Error),
+    InvalidString(core::str::Utf8Error),
+    /// Tried to decode a string containing U+0000, which is valid UTF-8 but not allowed by MQTT.
+    InvalidNullCharacter,
     /// Catch-all error when converting from `std::io::Error`.
     ///
     /// Note: Only available when std is available.
@@ -57,7 +95,14 @@ pub enum Error {
 }
 
 #[cfg(feature = "std")]
-impl ErrorTrait for Error {}
+impl ErrorTrait for Error {
+    fn source(&self) -> Option<&(dyn ErrorTrait + 'static)> {
+        match self {
+            Error::InvalidString(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -65,6 +110,97 @@ impl fmt::Display for Error {
     }
 }
 
+/// Implemented by hand, because the std `String` and `ErrorKind` don't implement `Format`.
+#[cfg(feature = "defmt")]
+impl Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::WriteZero => defmt::write!(f, "WriteZero"),
+            Error::InvalidPid => defmt::write!(f, "InvalidPid"),
+            Error::InvalidQos(q) => defmt::write!(f, "InvalidQos({=u8})", q),
+            Error::InvalidConnectReturnCode(c) => {
+                defmt::write!(f, "InvalidConnectReturnCode({=u8})", c)
+            }
+            Error::InvalidReasonCode(c) => defmt::write!(f, "InvalidReasonCode({=u8})", c),
+            Error::InvalidProtocol(name, level) => {
+                defmt::write!(f, "InvalidProtocol({=str}, {=u8})", name.as_str(), level)
+            }
+            Error::InvalidClientId => defmt::write!(f, "InvalidClientId"),
+            Error::InvalidCredentials => defmt::write!(f, "InvalidCredentials"),
+            Error::InvalidTopic => defmt::write!(f, "InvalidTopic"),
+            Error::ProtocolViolation => defmt::write!(f, "ProtocolViolation"),
+            Error::TrailingData(n) => defmt::write!(f, "TrailingData({=usize})", n),
+            Error::InvalidHeader => defmt::write!(f, "InvalidHeader"),
+            Error::InvalidHeaderFlags(typ, flags) => {
+                defmt::write!(f, "InvalidHeaderFlags({}, {=u8})", typ, flags)
+            }
+            Error::InvalidLength => defmt::write!(f, "InvalidLength"),
+            Error::InvalidProperty(id) => defmt::write!(f, "InvalidProperty({=u8})", id),
+            Error::InvalidSubscribeOptions(o) => {
+                defmt::write!(f, "InvalidSubscribeOptions({=u8})", o)
+            }
+            Error::LimitExceeded => defmt::write!(f, "LimitExceeded"),
+            Error::InvalidString(e) => defmt::write!(f, "InvalidString({})", defmt::Debug2Format(e)),
+            Error::InvalidNullCharacter => defmt::write!(f, "InvalidNullCharacter"),
+            #[cfg(feature = "std")]
+            Error::IoError(kind, msg) => {
+                defmt::write!(f, "IoError({}, {=str})", defmt::Debug2Format(kind), msg.as_str())
+            }
+        }
+    }
+}
+
+impl Error {
+    /// The [Connack] return code that a server should send when decoding a [Connect] fails.
+    ///
+    /// `None` means that the server should close the network connection without a `Connack`,
+    /// which is the response to most malformed packets in MQTT 3.1.1.
+    ///
+    /// [Connack]: struct.Connack.html
+    /// [Connect]: struct.Connect.html
+    pub fn as_connack_code(&self) -> Option<ConnectReturnCode> {
+        match self {
+            Error::InvalidProtocol(name, _) if name == "MQTT" || name == "MQIsdp" => {
+                Some(ConnectReturnCode::RefusedProtocolVersion)
+            }
+            Error::InvalidClientId => Some(ConnectReturnCode::RefusedIdentifierRejected),
+            _ => None,
+        }
+    }
+
+    /// The MQTT 5 `Disconnect` reason code that a server should send when decoding a packet
+    /// after the [Connect] fails.
+    ///
+    /// `None` for errors that aren't caused by the peer, like `WriteZero` or `IoError`.
+    ///
+    /// [Connect]: struct.Connect.html
+    pub fn as_disconnect_reason(&self) -> Option<ReasonCode> {
+        match self {
+            Error::WriteZero => None,
+            #[cfg(feature = "std")]
+            Error::IoError(..) => None,
+            Error::InvalidProtocol(..)
+            | Error::InvalidClientId
+            | Error::InvalidCredentials
+            | Error::ProtocolViolation
+            | Error::TrailingData(_) => Some(ReasonCode::ProtocolError),
+            Error::InvalidPid
+            | Error::InvalidQos(_)
+            | Error::InvalidConnectReturnCode(_)
+            | Error::InvalidReasonCode(_)
+            | Error::InvalidHeader
+            | Error::InvalidHeaderFlags(..)
+            | Error::InvalidLength
+            | Error::InvalidProperty(_)
+            | Error::InvalidSubscribeOptions(_)
+            | Error::InvalidString(_)
+            | Error::InvalidNullCharacter => Some(ReasonCode::MalformedPacket),
+            Error::InvalidTopic => Some(ReasonCode::TopicNameInvalid),
+            Error::LimitExceeded => Some(ReasonCode::ImplementationSpecificError),
+        }
+    }
+}
+
 #[cfg(feature = "std")]
 impl From<Error> for IoError {
     fn from(err: Error) -> IoError {
@@ -114,7 +250,7 @@ impl From<IoError> for Error {
 /// [MQTT-2.3.1-1]: https://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718025
 /// [MQTT-2.2.1-3]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901026
 
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Pid(NonZeroU16);
@@ -129,10 +265,38 @@ impl Pid {
         self.0.get()
     }
 
+    /// Return the next `Pid`, wrapping from `u16::MAX` to 1. This is the same as `self + 1`.
+    ///
+    /// ```
+    /// # use mqttrs::Pid;
+    /// # use core::convert::TryFrom;
+    /// assert_eq!(2, Pid::new().next().get());
+    /// assert_eq!(1, Pid::try_from(u16::MAX).unwrap().next().get());
+    /// ```
+    pub fn next(self) -> Pid {
+        self + 1
+    }
+
+    /// Return the previous `Pid`, wrapping from 1 to `u16::MAX`. This is the same as `self - 1`.
+    pub fn prev(self) -> Pid {
+        self - 1
+    }
+
+    /// Add a `u16` to the `Pid`, returning `None` instead of wrapping past `u16::MAX`.
+    ///
+    /// This can detect that all pids have been used, when they are allocated in order.
+    ///
+    /// ```
+    /// # use mqttrs::Pid;
+    /// assert_eq!(Some(u16::MAX), Pid::new().checked_add(u16::MAX - 1).map(Pid::get));
+    /// assert_eq!(None, Pid::new().checked_add(u16::MAX));
+    /// ```
+    pub fn checked_add(self, u: u16) -> Option<Pid> {
+        self.0.get().checked_add(u).map(|n| Pid(NonZeroU16::new(n).unwrap()))
+    }
+
     pub(crate) fn from_buffer<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
-        let pid = ((buf[*offset] as u16) << 8) | buf[*offset + 1] as u16;
-        *offset += 2;
-        Self::try_from(pid)
+        Self::try_from(read_u16(buf, offset)?)
     }
 
     pub(crate) fn to_buffer(self, buf: &mut [u8], offset: &mut usize) -> Result<(), Error> {
@@ -162,7 +326,10 @@ impl core::ops::Add<u16> for Pid {
 impl core::ops::Sub<u16> for Pid {
     type Output = Pid;
 
-    /// Adding a `u16` to a `Pid` will wrap around and avoid 0.
+    /// Subtracting a `u16` from a `Pid` will wrap around and avoid 0.
+    ///
+    /// Pids are treated as a ring of `1..=u16::MAX`, so `Pid(n) - n` is `u16::MAX`, `Pid(n) - 0`
+    /// is `Pid(n)`, and subtracting `u16::MAX` goes all the way around back to `Pid(n)`.
     fn sub(self, u: u16) -> Pid {
         let n = match self.get().overflowing_sub(u) {
             (0, _) => core::u16::MAX,
@@ -192,10 +359,83 @@ impl TryFrom<u16> for Pid {
     }
 }
 
+impl fmt::Display for Pid {
+    /// Print the raw `u16` value, which [FromStr] parses back.
+    ///
+    /// [FromStr]: #impl-FromStr
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
+
+impl FromStr for Pid {
+    type Err = Error;
+
+    /// Parse a `u16` to `Pid`. Will fail with `Error::InvalidPid` for 0 or a non-`u16` string.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let u = s.parse::<u16>().map_err(|_| Error::InvalidPid)?;
+        Pid::try_from(u)
+    }
+}
+
+/// Allocator of the [Pid]s of in-flight QoS 1 and 2 packets (std only).
+///
+/// `allocate()` hands out increasing pids, wrapping around and skipping the ones still in flight.
+/// Call `release()` when the flow completes (`Puback` or `Pubcomp`) to make its pid reusable.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut pids = PidPool::new();
+/// let pid = pids.allocate().unwrap();
+/// assert_eq!(1, pid.get());
+/// assert!(pids.is_in_flight(pid));
+/// assert!(pids.release(pid));
+/// ```
+///
+/// [Pid]: struct.Pid.html
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct PidPool {
+    next: Pid,
+    in_flight: std::collections::HashSet<Pid>,
+}
+
+#[cfg(feature = "std")]
+impl PidPool {
+    /// Returns an empty pool, which starts allocating at `1`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark the next free pid as in flight and return it, or `None` if all 65535 are in flight.
+    pub fn allocate(&mut self) -> Option<Pid> {
+        if self.in_flight.len() == u16::MAX as usize {
+            return None;
+        }
+        while self.in_flight.contains(&self.next) {
+            self.next = self.next + 1;
+        }
+        let pid = self.next;
+        self.in_flight.insert(pid);
+        self.next = pid + 1;
+        Some(pid)
+    }
+
+    /// Return a pid to the pool. Returns `false` if it wasn't in flight.
+    pub fn release(&mut self, pid: Pid) -> bool {
+        self.in_flight.remove(&pid)
+    }
+
+    /// Check whether a pid was allocated and not released yet.
+    pub fn is_in_flight(&self, pid: Pid) -> bool {
+        self.in_flight.contains(&pid)
+    }
+}
+
 /// Packet delivery [Quality of Service] level.
 ///
 /// [Quality of Service]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718099
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub enum QoS {
@@ -208,6 +448,21 @@ pub enum QoS {
 }
 
 impl QoS {
+    /// Return the first ack packet that the receiver of a `Publish` with this QoS sends back.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// assert_eq!(None, QoS::AtMostOnce.ack_packet_type());
+    /// assert_eq!(Some(PacketType::Pubrec), QoS::ExactlyOnce.ack_packet_type());
+    /// ```
+    pub fn ack_packet_type(self) -> Option<PacketType> {
+        match self {
+            QoS::AtMostOnce => None,
+            QoS::AtLeastOnce => Some(PacketType::Puback),
+            QoS::ExactlyOnce => Some(PacketType::Pubrec),
+        }
+    }
+
     pub(crate) fn to_u8(&self) -> u8 {
         match *self {
             QoS::AtMostOnce => 0,
@@ -224,6 +479,21 @@ impl QoS {
             n => Err(Error::InvalidQos(n)),
         }
     }
+
+    /// Read the 2 QoS bits at position `shift` of a flags byte.
+    ///
+    /// This is used for all QoS bits within a byte (publish header and connect flags), so that
+    /// they consistently return `Err(Error::InvalidQos(3))` for the reserved value.
+    pub(crate) fn from_bits(byte: u8, shift: u8) -> Result<QoS, Error> {
+        QoS::from_u8((byte >> shift) & 0b11)
+    }
+
+    /// Return the 2 QoS bits at position `shift`, see [from_bits()].
+    ///
+    /// [from_bits()]: #method.from_bits
+    pub(crate) fn to_bits(self, shift: u8) -> u8 {
+        self.to_u8() << shift
+    }
 }
 
 /// Combined [`QoS`]/[`Pid`].
@@ -233,7 +503,7 @@ impl QoS {
 /// [`Publish`]: struct.Publish.html
 /// [`QoS`]: enum.QoS.html
 /// [`Pid`]: struct.Pid.html
-#[cfg_attr(feature = "defmt",derive(Format))]
+#[cfg_attr(feature = "defmt", derive(Format))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub enum QosPid {
@@ -276,11 +546,180 @@ impl QosPid {
     }
 }
 
+/// Compact format for logging: `qos0`, `qos1(pid=10)`, or `qos2(pid=10)`.
+impl fmt::Display for QosPid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QosPid::AtMostOnce => write!(f, "qos0"),
+            QosPid::AtLeastOnce(pid) => write!(f, "qos1(pid={})", pid.get()),
+            QosPid::ExactlyOnce(pid) => write!(f, "qos2(pid={})", pid.get()),
+        }
+    }
+}
+
+/// MQTT 5 reason code ([MQTT 2.4]).
+///
+/// Each packet type only allows a subset of these, see the spec.
+///
+/// [MQTT 2.4]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901031
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub enum ReasonCode {
+    /// Also "Normal disconnection" and "Granted QoS 0".
+    Success,
+    GrantedQoS1,
+    GrantedQoS2,
+    DisconnectWithWillMessage,
+    NoMatchingSubscribers,
+    NoSubscriptionExisted,
+    ContinueAuthentication,
+    ReAuthenticate,
+    UnspecifiedError,
+    MalformedPacket,
+    ProtocolError,
+    ImplementationSpecificError,
+    UnsupportedProtocolVersion,
+    ClientIdentifierNotValid,
+    BadUserNameOrPassword,
+    NotAuthorized,
+    ServerUnavailable,
+    ServerBusy,
+    Banned,
+    ServerShuttingDown,
+    BadAuthenticationMethod,
+    KeepAliveTimeout,
+    SessionTakenOver,
+    TopicFilterInvalid,
+    TopicNameInvalid,
+    PacketIdentifierInUse,
+    PacketIdentifierNotFound,
+    ReceiveMaximumExceeded,
+    TopicAliasInvalid,
+    PacketTooLarge,
+    MessageRateTooHigh,
+    QuotaExceeded,
+    AdministrativeAction,
+    PayloadFormatInvalid,
+    RetainNotSupported,
+    QoSNotSupported,
+    UseAnotherServer,
+    ServerMoved,
+    SharedSubscriptionsNotSupported,
+    ConnectionRateExceeded,
+    MaximumConnectTime,
+    SubscriptionIdentifiersNotSupported,
+    WildcardSubscriptionsNotSupported,
+}
+
+impl ReasonCode {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            ReasonCode::Success => 0x00,
+            ReasonCode::GrantedQoS1 => 0x01,
+            ReasonCode::GrantedQoS2 => 0x02,
+            ReasonCode::DisconnectWithWillMessage => 0x04,
+            ReasonCode::NoMatchingSubscribers => 0x10,
+            ReasonCode::NoSubscriptionExisted => 0x11,
+            ReasonCode::ContinueAuthentication => 0x18,
+            ReasonCode::ReAuthenticate => 0x19,
+            ReasonCode::UnspecifiedError => 0x80,
+            ReasonCode::MalformedPacket => 0x81,
+            ReasonCode::ProtocolError => 0x82,
+            ReasonCode::ImplementationSpecificError => 0x83,
+            ReasonCode::UnsupportedProtocolVersion => 0x84,
+            ReasonCode::ClientIdentifierNotValid => 0x85,
+            ReasonCode::BadUserNameOrPassword => 0x86,
+            ReasonCode::NotAuthorized => 0x87,
+            ReasonCode::ServerUnavailable => 0x88,
+            ReasonCode::ServerBusy => 0x89,
+            ReasonCode::Banned => 0x8A,
+            ReasonCode::ServerShuttingDown => 0x8B,
+            ReasonCode::BadAuthenticationMethod => 0x8C,
+            ReasonCode::KeepAliveTimeout => 0x8D,
+            ReasonCode::SessionTakenOver => 0x8E,
+            ReasonCode::TopicFilterInvalid => 0x8F,
+            ReasonCode::TopicNameInvalid => 0x90,
+            ReasonCode::PacketIdentifierInUse => 0x91,
+            ReasonCode::PacketIdentifierNotFound => 0x92,
+            ReasonCode::ReceiveMaximumExceeded => 0x93,
+            ReasonCode::TopicAliasInvalid => 0x94,
+            ReasonCode::PacketTooLarge => 0x95,
+            ReasonCode::MessageRateTooHigh => 0x96,
+            ReasonCode::QuotaExceeded => 0x97,
+            ReasonCode::AdministrativeAction => 0x98,
+            ReasonCode::PayloadFormatInvalid => 0x99,
+            ReasonCode::RetainNotSupported => 0x9A,
+            ReasonCode::QoSNotSupported => 0x9B,
+            ReasonCode::UseAnotherServer => 0x9C,
+            ReasonCode::ServerMoved => 0x9D,
+            ReasonCode::SharedSubscriptionsNotSupported => 0x9E,
+            ReasonCode::ConnectionRateExceeded => 0x9F,
+            ReasonCode::MaximumConnectTime => 0xA0,
+            ReasonCode::SubscriptionIdentifiersNotSupported => 0xA1,
+            ReasonCode::WildcardSubscriptionsNotSupported => 0xA2,
+        }
+    }
+
+    pub(crate) fn from_u8(byte: u8) -> Result<ReasonCode, Error> {
+        Ok(match byte {
+            0x00 => ReasonCode::Success,
+            0x01 => ReasonCode::GrantedQoS1,
+            0x02 => ReasonCode::GrantedQoS2,
+            0x04 => ReasonCode::DisconnectWithWillMessage,
+            0x10 => ReasonCode::NoMatchingSubscribers,
+            0x11 => ReasonCode::NoSubscriptionExisted,
+            0x18 => ReasonCode::ContinueAuthentication,
+            0x19 => ReasonCode::ReAuthenticate,
+            0x80 => ReasonCode::UnspecifiedError,
+            0x81 => ReasonCode::MalformedPacket,
+            0x82 => ReasonCode::ProtocolError,
+            0x83 => ReasonCode::ImplementationSpecificError,
+            0x84 => ReasonCode::UnsupportedProtocolVersion,
+            0x85 => ReasonCode::ClientIdentifierNotValid,
+            0x86 => ReasonCode::BadUserNameOrPassword,
+            0x87 => ReasonCode::NotAuthorized,
+            0x88 => ReasonCode::ServerUnavailable,
+            0x89 => ReasonCode::ServerBusy,
+            0x8A => ReasonCode::Banned,
+            0x8B => ReasonCode::ServerShuttingDown,
+            0x8C => ReasonCode::BadAuthenticationMethod,
+            0x8D => ReasonCode::KeepAliveTimeout,
+            0x8E => ReasonCode::SessionTakenOver,
+            0x8F => ReasonCode::TopicFilterInvalid,
+            0x90 => ReasonCode::TopicNameInvalid,
+            0x91 => ReasonCode::PacketIdentifierInUse,
+            0x92 => ReasonCode::PacketIdentifierNotFound,
+            0x93 => ReasonCode::ReceiveMaximumExceeded,
+            0x94 => ReasonCode::TopicAliasInvalid,
+            0x95 => ReasonCode::PacketTooLarge,
+            0x96 => ReasonCode::MessageRateTooHigh,
+            0x97 => ReasonCode::QuotaExceeded,
+            0x98 => ReasonCode::AdministrativeAction,
+            0x99 => ReasonCode::PayloadFormatInvalid,
+            0x9A => ReasonCode::RetainNotSupported,
+            0x9B => ReasonCode::QoSNotSupported,
+            0x9C => ReasonCode::UseAnotherServer,
+            0x9D => ReasonCode::ServerMoved,
+            0x9E => ReasonCode::SharedSubscriptionsNotSupported,
+            0x9F => ReasonCode::ConnectionRateExceeded,
+            0xA0 => ReasonCode::MaximumConnectTime,
+            0xA1 => ReasonCode::SubscriptionIdentifiersNotSupported,
+            0xA2 => ReasonCode::WildcardSubscriptionsNotSupported,
+            n => return Err(Error::InvalidReasonCode(n)),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::Pid;
+    use crate::{
+        decode_slice, ConnectReturnCode, Error, PacketType, Pid, QoS, QosPid, ReasonCode,
+    };
+    #[cfg(feature = "std")]
+    use crate::PidPool;
     use core::convert::TryFrom;
-    use std::vec;
+    use std::{format, vec};
 
     #[test]
     fn pid_add_sub() {
@@ -304,4 +743,188 @@ mod test {
             assert_eq!(next, add.get(), "{} + {} should be {}", cur, d, next);
         }
     }
+
+    #[test]
+    fn pid_sub_boundaries() {
+        let t: Vec<(u16, u16, u16)> = vec![
+            // Landing exactly on 0 skips to MAX
+            (2, 2, u16::MAX),
+            (3, 3, u16::MAX),
+            (u16::MAX, u16::MAX, u16::MAX),
+            // Going below 0 skips over it too
+            (2, 3, u16::MAX - 1),
+            // Subtracting 0 or a full turn is a no-op
+            (1, 0, 1),
+            (2, 0, 2),
+            (1, u16::MAX, 1),
+            (2, u16::MAX, 2),
+        ];
+        for (cur, d, prev) in t {
+            let sub = Pid::try_from(cur).unwrap() - d;
+            assert_eq!(prev, sub.get(), "{} - {} should be {}", cur, d, prev);
+        }
+    }
+
+    #[test]
+    fn pid_next_prev_checked_add() {
+        let max = Pid::try_from(u16::MAX).unwrap();
+        assert_eq!(1, max.next().get());
+        assert_eq!(u16::MAX, Pid::new().prev().get());
+        assert_eq!(u16::MAX - 1, max.prev().get());
+        assert_eq!(max, max.next().prev());
+        for &u in [1, 10, u16::MAX - 1].iter() {
+            let pid = Pid::try_from(u).unwrap();
+            assert_eq!(pid + 1, pid.next());
+            assert_eq!(pid - 1, pid.prev());
+            assert_eq!(Some(pid + 1), pid.checked_add(1));
+        }
+
+        assert_eq!(Some(max), max.checked_add(0));
+        assert_eq!(None, max.checked_add(1));
+        assert_eq!(Some(max), Pid::new().checked_add(u16::MAX - 1));
+        assert_eq!(None, Pid::new().checked_add(u16::MAX));
+        assert_eq!(None, Pid::try_from(10).unwrap().checked_add(u16::MAX - 9));
+    }
+
+    #[test]
+    fn pid_display_parse() {
+        for &u in [1, 10, u16::MAX].iter() {
+            let pid = Pid::try_from(u).unwrap();
+            assert_eq!(format!("{}", u), format!("{}", pid));
+            assert_eq!(Ok(pid), format!("{}", pid).parse());
+        }
+        for s in ["0", "65536", "-1", "", "x"].iter() {
+            assert_eq!(Err(Error::InvalidPid), s.parse::<Pid>(), "{:?}", s);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn pid_pool() {
+        let mut pids = PidPool::new();
+        let first = pids.allocate().unwrap();
+        assert_eq!(first, Pid::new());
+        assert!(pids.is_in_flight(first));
+
+        // Wrap around, skipping the pid still in flight
+        let mut last = first;
+        for _ in 0..70000 {
+            let pid = pids.allocate().unwrap();
+            assert_ne!(pid, first);
+            assert!(pids.release(pid));
+            last = pid;
+        }
+        // 2..=65535, then 2..=4467
+        assert_eq!(last.get(), 4467);
+        assert!(!pids.release(last));
+
+        // Exhaust the pool
+        let mut count = 1;
+        while pids.allocate().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 65535);
+        assert_eq!(None, pids.allocate());
+
+        // Reuse a released pid
+        let pid = Pid::try_from(10).unwrap();
+        assert!(pids.release(pid));
+        assert!(!pids.is_in_flight(pid));
+        assert_eq!(Some(pid), pids.allocate());
+        assert_eq!(None, pids.allocate());
+    }
+
+    #[test]
+    fn qos_ack_packet_type() {
+        assert_eq!(None, QoS::AtMostOnce.ack_packet_type());
+        assert_eq!(Some(PacketType::Puback), QoS::AtLeastOnce.ack_packet_type());
+        assert_eq!(Some(PacketType::Pubrec), QoS::ExactlyOnce.ack_packet_type());
+    }
+
+    #[test]
+    fn qos_bits() {
+        for &shift in [1, 3].iter() {
+            for byte in 0..=255u8 {
+                match (byte >> shift) & 0b11 {
+                    3 => assert_eq!(Err(Error::InvalidQos(3)), QoS::from_bits(byte, shift)),
+                    n => {
+                        let qos = QoS::from_bits(byte, shift).unwrap();
+                        assert_eq!(n, qos.to_u8());
+                        assert_eq!(byte & (0b11 << shift), qos.to_bits(shift));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn qospid_display() {
+        assert_eq!("qos0", format!("{}", QosPid::AtMostOnce));
+        assert_eq!("qos1(pid=10)", format!("{}", QosPid::from_u8u16(1, 10)));
+        assert_eq!("qos2(pid=65535)", format!("{}", QosPid::from_u8u16(2, 65535)));
+    }
+
+    #[test]
+    fn error_responses() {
+        let bad_version = [0b00010000, 7, 0, 4, b'M', b'Q', b'T', b'T', 9];
+        let err = decode_slice(&bad_version).unwrap_err();
+        assert_eq!(Some(ConnectReturnCode::RefusedProtocolVersion), err.as_connack_code());
+        assert_eq!(Some(ReasonCode::ProtocolError), err.as_disconnect_reason());
+
+        let bad_name = [0b00010000, 7, 0, 4, b'A', b'B', b'C', b'D', 4];
+        let err = decode_slice(&bad_name).unwrap_err();
+        assert_eq!(None, err.as_connack_code());
+
+        assert_eq!(None, Error::InvalidHeader.as_connack_code());
+        assert_eq!(Some(ReasonCode::MalformedPacket), Error::InvalidHeader.as_disconnect_reason());
+        assert_eq!(None, Error::InvalidLength.as_connack_code());
+        assert_eq!(Some(ReasonCode::MalformedPacket), Error::InvalidLength.as_disconnect_reason());
+        assert_eq!(
+            Some(ConnectReturnCode::RefusedIdentifierRejected),
+            Error::InvalidClientId.as_connack_code()
+        );
+        assert_eq!(None, Error::WriteZero.as_disconnect_reason());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn error_source() {
+        use std::{boxed::Box, error::Error as ErrorTrait};
+
+        let err: Box<dyn ErrorTrait> = Box::from(Error::InvalidPid);
+        assert!(err.source().is_none());
+
+        match decode_slice(&[0b00110000, 3, 0, 1, 0xFF]) {
+            Err(err @ Error::InvalidString(_)) => {
+                let source = err.source().unwrap();
+                assert!(source.downcast_ref::<core::str::Utf8Error>().is_some());
+            }
+            other => panic!("Expected InvalidString, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn error_io_conversion() {
+        use std::io::{Error as IoError, ErrorKind};
+
+        let io: IoError = Error::WriteZero.into();
+        assert_eq!(io.kind(), ErrorKind::WriteZero);
+        assert_eq!(Error::from(io), Error::WriteZero);
+
+        let io: IoError = Error::InvalidPid.into();
+        assert_eq!(io.kind(), ErrorKind::InvalidData);
+        let io = IoError::new(ErrorKind::ConnectionReset, "reset");
+        assert_eq!(Error::from(io), Error::IoError(ErrorKind::ConnectionReset, "reset".into()));
+
+        // `?` converts in both directions
+        fn read(data: &[u8]) -> Result<Option<crate::Packet<'_>>, IoError> {
+            Ok(decode_slice(data)?)
+        }
+        assert_eq!(read(&[0, 0]).unwrap_err().kind(), ErrorKind::InvalidData);
+        fn write() -> Result<usize, Error> {
+            Err(IoError::new(ErrorKind::WriteZero, "full"))?
+        }
+        assert_eq!(write(), Err(Error::WriteZero));
+    }
 }
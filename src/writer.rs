@@ -0,0 +1,95 @@
+use crate::*;
+use std::{io::Write, vec};
+
+/// Packets up to this size are encoded on the stack.
+const STACK_BUF_LEN: usize = 256;
+
+/// Write packets to a blocking [Write] destination, like a `TcpStream`.
+///
+/// This is implemented for all `Write` types, see [MqttReader] for the other direction.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut out = Vec::new();
+/// out.write_packet(&Packet::Pingreq).unwrap();
+/// assert_eq!(Ok(Some(Packet::Pingreq)), decode_slice(&out));
+/// ```
+///
+/// [Write]: https://doc.rust-lang.org/std/io/trait.Write.html
+/// [MqttReader]: struct.MqttReader.html
+pub trait MqttWriter {
+    /// Encode a packet, write all of it, and flush. Returns the number of bytes written.
+    fn write_packet(&mut self, packet: &Packet) -> Result<usize, Error>;
+}
+
+impl<W: Write> MqttWriter for W {
+    fn write_packet(&mut self, packet: &Packet) -> Result<usize, Error> {
+        let len = packet.encoded_size();
+        let mut stack_buf = [0u8; STACK_BUF_LEN];
+        let mut heap_buf;
+        let buf = if len <= STACK_BUF_LEN {
+            &mut stack_buf[..len]
+        } else {
+            heap_buf = vec![0u8; len];
+            &mut heap_buf[..]
+        };
+        let len = encode_slice(packet, buf)?;
+        self.write_all(&buf[..len])?;
+        self.flush()?;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use core::convert::TryFrom;
+    use std::vec::Vec;
+
+    #[test]
+    fn write_packets() {
+        let payload = [7u8; 1000];
+        let small = Publish {
+            dup: false,
+            qospid: QosPid::AtLeastOnce(Pid::try_from(10).unwrap()),
+            retain: false,
+            topic_name: "a/b",
+            payload: &payload[..10],
+            properties: None,
+        }
+        .into();
+        let large = Publish {
+            dup: false,
+            qospid: QosPid::AtMostOnce,
+            retain: true,
+            topic_name: "a/b",
+            payload: &payload,
+            properties: None,
+        }
+        .into();
+
+        let mut out = Vec::new();
+        let small_len = out.write_packet(&small).unwrap();
+        assert_eq!(small_len, small.encoded_size());
+        let large_len = out.write_packet(&large).unwrap();
+        assert_eq!(large_len, large.encoded_size());
+        assert_eq!(out.len(), small_len + large_len);
+
+        assert_eq!(Ok(Some((small_len, small))), decode_slice_with_len(&out));
+        assert_eq!(Ok(Some((large_len, large))), decode_slice_with_len(&out[small_len..]));
+    }
+
+    #[test]
+    fn write_mqisdp_connect() {
+        let connect = Connect {
+            protocol: Protocol::MQIsdp,
+            ..Connect::builder("client").build().unwrap()
+        }
+        .into();
+        let mut out = Vec::new();
+        let len = out.write_packet(&connect).unwrap();
+        assert_eq!(len, connect.encoded_size());
+        assert_eq!(&out[2..11], &[0, 6, b'M', b'Q', b'I', b's', b'd', b'p', 3]);
+        assert_eq!(Ok(Some((len, connect))), decode_slice_with_len(&out));
+    }
+}